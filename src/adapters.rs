@@ -0,0 +1,1839 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+    terminal::{quiet, status, status_line, status_line_clear, status_line_remove},
+    StreamData,
+};
+use failure::{format_err, Error};
+use futures::{try_ready, Async, Future, Poll, Stream};
+use lazy_static::lazy_static;
+use regex::Regex;
+use rogcat::{
+    parser::Parser,
+    record::{Level, Record, Template},
+};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::{self, Display, Formatter},
+    fs::{self, File},
+    io::{BufWriter, Write},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use time::{strftime, Timespec, Tm};
+use tokio::timer::{Delay, Interval};
+
+lazy_static! {
+    /// Continuation lines of a java stack trace
+    static ref TRACE_CONTINUATION: Regex =
+        Regex::new(r"^\s*(at \S+\(.*\)$|Caused by: |\.\.\. \d+ more$)").unwrap();
+    /// Variable parts of a message for --diff
+    static ref VARIABLE: Regex = Regex::new(r"0[xX][0-9a-fA-F]+|\d+").unwrap();
+    /// Notice of logd about lines it dropped. The uid is followed by the
+    /// package name in parentheses since Android 7 and "identical" is
+    /// reported since Android 8 e.g
+    /// "uid=1000(system) Binder:1234_5 identical 3 lines" or
+    /// "uid=10054 com.example expire 1 line"
+    static ref CHATTY: Regex =
+        Regex::new(r"^uid=(\d+)(?:\([^)]*\))?\s+(.*?)\s*\b(identical|expire)\s+(\d+)\s+lines?$").unwrap();
+}
+
+/// Number of tags listed in the statistics
+const STATS_TAGS: usize = 10;
+
+/// Stream adapter that yields only the last n items of a stream once the
+/// stream is finished. All items are passed through if n is `None`
+pub struct Tail<S: Stream> {
+    stream: S,
+    n: Option<usize>,
+    buffer: VecDeque<S::Item>,
+    done: bool,
+}
+
+/// Keep the last n items of stream
+pub fn tail<S: Stream>(stream: S, n: Option<usize>) -> Tail<S> {
+    Tail {
+        stream,
+        n,
+        buffer: VecDeque::new(),
+        done: false,
+    }
+}
+
+impl<S: Stream> Stream for Tail<S> {
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, S::Error> {
+        let n = match self.n {
+            Some(n) => n,
+            None => return self.stream.poll(),
+        };
+
+        while !self.done {
+            match try_ready!(self.stream.poll()) {
+                Some(item) => {
+                    if self.buffer.len() == n {
+                        self.buffer.pop_front();
+                    }
+                    if n > 0 {
+                        self.buffer.push_back(item);
+                    }
+                }
+                None => self.done = true,
+            }
+        }
+
+        Ok(Async::Ready(self.buffer.pop_front()))
+    }
+}
+
+/// Record counts per level and tag
+#[derive(Debug, Default)]
+pub struct Statistics {
+    total: usize,
+    levels: Vec<(Level, usize)>,
+    tags: HashMap<String, usize>,
+    /// Lines collapsed by chatty because they are identical
+    identical: usize,
+    /// Lines expired by chatty e.g because the buffer is full
+    expired: usize,
+}
+
+impl Statistics {
+    fn add(&mut self, record: &Record) {
+        self.total += 1;
+        match self.levels.iter_mut().find(|(l, _)| *l == record.level) {
+            Some((_, n)) => *n += 1,
+            None => self.levels.push((record.level.clone(), 1)),
+        }
+        *self.tags.entry(record.tag.clone()).or_insert(0) += 1;
+    }
+
+    /// Take the lines dropped on the device that were counted before the
+    /// filters
+    fn set_dropped(&mut self, dropped: &DroppedLines) {
+        self.identical = dropped.identical.load(Ordering::Relaxed);
+        self.expired = dropped.expired.load(Ordering::Relaxed);
+    }
+
+    fn percent(&self, n: usize) -> f64 {
+        n as f64 * 100.0 / self.total as f64
+    }
+
+    /// One line summary of the level counts e.g "12 records: I 10, E 2"
+    fn levels_line(&self) -> String {
+        let mut levels = self.levels.iter().collect::<Vec<_>>();
+        levels.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+        let levels = levels
+            .iter()
+            .map(|(level, n)| format!("{} {}", level, n))
+            .collect::<Vec<_>>();
+        if levels.is_empty() {
+            format!("{} records", self.total)
+        } else {
+            format!("{} records: {}", self.total, levels.join(", "))
+        }
+    }
+}
+
+impl Display for Statistics {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "{} records", self.total)?;
+        if self.total == 0 {
+            return Ok(());
+        }
+
+        let mut levels = self.levels.iter().collect::<Vec<_>>();
+        levels.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+        writeln!(f, "{:<32} {:>10} {:>7}", "Level", "Count", "%")?;
+        for (level, n) in levels {
+            let level = level.to_string();
+            writeln!(f, "{:<32} {:>10} {:>7.1}", level, n, self.percent(*n))?;
+        }
+
+        let mut tags = self.tags.iter().collect::<Vec<_>>();
+        tags.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        writeln!(f, "{:<32} {:>10} {:>7}", "Tag", "Count", "%")?;
+        for (tag, n) in tags.iter().take(STATS_TAGS) {
+            let tag = if tag.is_empty() { "-" } else { tag };
+            writeln!(f, "{:<32} {:>10} {:>7.1}", tag, n, self.percent(**n))?;
+        }
+
+        if self.identical + self.expired > 0 {
+            writeln!(
+                f,
+                "{} lines dropped on the device: {} identical, {} expired",
+                self.identical + self.expired,
+                self.identical,
+                self.expired
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Lines dropped on the device as reported by the chatty notices. Counted
+/// before the filters that would reject the notices e.g a tag filter
+#[derive(Clone, Debug, Default)]
+pub struct DroppedLines {
+    identical: Arc<AtomicUsize>,
+    expired: Arc<AtomicUsize>,
+}
+
+impl DroppedLines {
+    /// Count the lines of record if it is a chatty notice
+    pub fn count(&self, record: &Record) {
+        if let Some(dropped) = dropped(record) {
+            let lines = if dropped.identical {
+                &self.identical
+            } else {
+                &self.expired
+            };
+            lines.fetch_add(dropped.lines, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Stream adapter that counts the records passing through and prints the
+/// statistics to stderr when dropped. With an interval the level counts since
+/// the last tick are printed periodically
+pub struct Stats<S> {
+    stream: S,
+    statistics: Option<Statistics>,
+    /// Interval of --stats-interval and the counts since the last tick
+    interval: Option<(Interval, Statistics)>,
+    /// Time of the last printed tick
+    last: Instant,
+    /// Overwrite the periodic line with --stats-inline
+    inline: bool,
+    /// Lines dropped on the device counted before the filters
+    dropped: DroppedLines,
+}
+
+/// Count records of stream if enabled. Periodic updates are suppressed with
+/// --quiet
+pub fn stats<S: Stream<Item = Record>>(
+    stream: S,
+    enabled: bool,
+    interval: Option<Duration>,
+    inline: bool,
+    dropped: DroppedLines,
+) -> Stats<S> {
+    Stats {
+        stream,
+        statistics: if enabled {
+            Some(Statistics::default())
+        } else {
+            None
+        },
+        interval: interval
+            .filter(|_| !quiet())
+            .map(|i| (Interval::new(Instant::now() + i, i), Statistics::default())),
+        last: Instant::now(),
+        inline,
+        dropped,
+    }
+}
+
+impl<S> Stats<S> {
+    /// Statistics printed on exit including the dropped lines counted so far
+    fn summary(&mut self) -> Option<String> {
+        let statistics = self.statistics.as_mut()?;
+        statistics.set_dropped(&self.dropped);
+        Some(statistics.to_string())
+    }
+
+    /// Print the counts since the last tick and start over. Ticks missed
+    /// while the stream stalled are printed as one line labeled with the
+    /// time since the last line
+    fn tick(&mut self, ticks: usize) {
+        if let Some(line) = self.tick_line(ticks) {
+            if self.inline {
+                status_line("stats", line);
+            } else {
+                status_line_clear();
+                eprintln!("{}", line);
+            }
+        }
+    }
+
+    fn tick_line(&mut self, ticks: usize) -> Option<String> {
+        let (_, ref mut delta) = self.interval.as_mut()?;
+        let mut line = std::mem::take(delta).levels_line();
+        if ticks > 1 {
+            line = format!("{} in {:.1}s", line, self.last.elapsed().as_secs_f64());
+        }
+        self.last = Instant::now();
+        Some(line)
+    }
+}
+
+impl<S: Stream<Item = Record>> Stream for Stats<S> {
+    type Item = Record;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Record>, S::Error> {
+        let mut ticks = 0;
+        if let Some((ref mut interval, _)) = self.interval {
+            while let Ok(Async::Ready(Some(_))) = interval.poll() {
+                ticks += 1;
+            }
+        }
+        if ticks > 0 {
+            self.tick(ticks);
+        }
+
+        let record = try_ready!(self.stream.poll());
+        if let Some(ref record) = record {
+            if let Some(ref mut statistics) = self.statistics {
+                statistics.add(record);
+            }
+            if let Some((_, ref mut delta)) = self.interval {
+                delta.add(record);
+            }
+        }
+        Ok(Async::Ready(record))
+    }
+}
+
+impl<S> Drop for Stats<S> {
+    fn drop(&mut self) {
+        if self.inline && self.interval.is_some() {
+            status_line_remove("stats");
+        }
+        if let Some(summary) = self.summary() {
+            status_line_clear();
+            eprint!("{}", summary);
+        }
+    }
+}
+
+/// Stream adapter that pairs each item with the seconds since start when it is
+/// read from the stream. The seconds are `None` without a start
+pub struct Received<S> {
+    stream: S,
+    start: Option<Instant>,
+}
+
+pub fn received<S: Stream>(stream: S, start: Option<Instant>) -> Received<S> {
+    Received { stream, start }
+}
+
+impl<S: Stream> Stream for Received<S> {
+    type Item = (Option<f64>, S::Item);
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, S::Error> {
+        let item = try_ready!(self.stream.poll());
+        let seconds = self.start.map(|start| start.elapsed().as_secs_f64());
+        Ok(Async::Ready(item.map(|item| (seconds, item))))
+    }
+}
+
+/// Stream adapter that writes the unmodified input lines to a file before
+/// any parsing and filtering
+pub struct TeeRaw<S> {
+    stream: S,
+    file: Option<(String, BufWriter<File>)>,
+}
+
+/// Tee the input to the file passed with --tee-raw
+pub fn tee_raw<S: Stream<Item = StreamData, Error = Error>>(
+    stream: S,
+    path: Option<&str>,
+) -> Result<TeeRaw<S>, Error> {
+    let file = path
+        .map(|p| {
+            File::create(p)
+                .map(|f| (p.to_owned(), BufWriter::new(f)))
+                .map_err(|e| format_err!("Failed to create tee file {}: {}", p, e))
+        })
+        .transpose()?;
+    Ok(TeeRaw { stream, file })
+}
+
+impl<S: Stream<Item = StreamData, Error = Error>> Stream for TeeRaw<S> {
+    type Item = StreamData;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<StreamData>, Error> {
+        let (path, file) = match self.file {
+            Some((ref path, ref mut file)) => (path, file),
+            None => return self.stream.poll(),
+        };
+        let tee_error = |e| format_err!("Failed to write tee file {}: {}", path, e);
+        match self.stream.poll()? {
+            Async::Ready(Some(data)) => {
+                let raw = match data {
+                    StreamData::Line(ref l) => Some(Cow::Borrowed(l)),
+                    StreamData::Labeled(ref origin, ref l) => Some(Cow::Owned(origin.prefix(l))),
+                    StreamData::Record(ref r) => Some(Cow::Borrowed(&r.raw)),
+                    StreamData::EndOfFile => None,
+                };
+                if let Some(raw) = raw {
+                    writeln!(file, "{}", raw).map_err(tee_error)?;
+                }
+                Ok(Async::Ready(Some(data)))
+            }
+            // Flush whenever the input is idle and on the end of the input
+            Async::Ready(None) => {
+                file.flush().map_err(tee_error)?;
+                Ok(Async::Ready(None))
+            }
+            Async::NotReady => {
+                file.flush().map_err(tee_error)?;
+                Ok(Async::NotReady)
+            }
+        }
+    }
+}
+
+/// Stream adapter that ends the stream if the inner stream yields no item
+/// for the timeout
+pub struct Idle<S> {
+    stream: S,
+    timeout: Option<Duration>,
+    delay: Option<Delay>,
+}
+
+pub fn idle<S: Stream>(stream: S, timeout: Option<Duration>) -> Idle<S> {
+    Idle {
+        stream,
+        timeout,
+        delay: None,
+    }
+}
+
+impl<S: Stream> Stream for Idle<S> {
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, S::Error> {
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return self.stream.poll(),
+        };
+        match self.stream.poll()? {
+            Async::Ready(item) => {
+                // Restart the timer on each item
+                self.delay = None;
+                Ok(Async::Ready(item))
+            }
+            Async::NotReady => {
+                let delay = self
+                    .delay
+                    .get_or_insert_with(|| Delay::new(Instant::now() + timeout));
+                match delay.poll() {
+                    Ok(Async::Ready(())) => {
+                        status(&format!("No input for {}s. Exiting", timeout.as_secs_f64()));
+                        Ok(Async::Ready(None))
+                    }
+                    _ => Ok(Async::NotReady),
+                }
+            }
+        }
+    }
+}
+
+/// Default longest pause between two records with --replay-speed
+pub const REPLAY_MAX_GAP: Duration = Duration::from_secs(10);
+
+/// Stream adapter that delays records by the difference of their timestamps
+/// divided by the replay speed
+pub struct Replay<S> {
+    stream: S,
+    /// Speed and longest pause
+    replay: Option<(f64, Duration)>,
+    /// Timestamp of the last record with a timestamp
+    last: Option<Timespec>,
+    /// Record waiting for its delay
+    pending: Option<(Delay, Record)>,
+}
+
+pub fn replay<S: Stream<Item = Record>>(stream: S, replay: Option<(f64, Duration)>) -> Replay<S> {
+    Replay {
+        stream,
+        replay,
+        last: None,
+        pending: None,
+    }
+}
+
+impl<S: Stream<Item = Record>> Stream for Replay<S> {
+    type Item = Record;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Record>, S::Error> {
+        let (speed, max_gap) = match self.replay {
+            Some(replay) => replay,
+            None => return self.stream.poll(),
+        };
+        if let Some((ref mut delay, _)) = self.pending {
+            if let Ok(Async::NotReady) = delay.poll() {
+                return Ok(Async::NotReady);
+            }
+            return Ok(Async::Ready(self.pending.take().map(|(_, r)| r)));
+        }
+
+        let record = match try_ready!(self.stream.poll()) {
+            Some(record) => record,
+            None => return Ok(Async::Ready(None)),
+        };
+        let timestamp = record.timestamp.as_ref().map(|t| t.to_timespec());
+        // Records going back in time are emitted immediately
+        let gap = match (self.last, timestamp) {
+            (Some(last), Some(timestamp)) => (timestamp - last).to_std().ok(),
+            _ => None,
+        };
+        if timestamp.is_some() {
+            self.last = timestamp;
+        }
+        match gap
+            // Clamped before the conversion to not overflow for tiny speeds
+            .map(|gap| {
+                Duration::from_secs_f64((gap.as_secs_f64() / speed).min(max_gap.as_secs_f64()))
+            })
+            .filter(|gap| *gap > Duration::from_millis(0))
+        {
+            Some(gap) => {
+                self.pending = Some((Delay::new(Instant::now() + gap), record));
+                self.poll()
+            }
+            None => Ok(Async::Ready(Some(record))),
+        }
+    }
+}
+
+/// Stream adapter that ends the stream after a fixed duration regardless of
+/// the activity of the inner stream
+pub struct Deadline<S> {
+    stream: S,
+    delay: Option<Delay>,
+}
+
+pub fn deadline<S: Stream>(stream: S, duration: Option<Duration>) -> Deadline<S> {
+    Deadline {
+        stream,
+        delay: duration.map(|d| Delay::new(Instant::now() + d)),
+    }
+}
+
+impl<S: Stream> Stream for Deadline<S> {
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, S::Error> {
+        if let Some(ref mut delay) = self.delay {
+            if let Ok(Async::Ready(())) = delay.poll() {
+                return Ok(Async::Ready(None));
+            }
+        }
+        self.stream.poll()
+    }
+}
+
+/// Wrap lines into records without parsing them for --raw
+pub fn raw(data: StreamData) -> Option<Record> {
+    match data {
+        StreamData::Line(raw) => Some(Record {
+            raw,
+            ..Default::default()
+        }),
+        StreamData::Labeled(origin, raw) => Some(origin.label(Record {
+            raw,
+            ..Default::default()
+        })),
+        StreamData::Record(record) => Some(record),
+        StreamData::EndOfFile => None,
+    }
+}
+
+/// Stream adapter that sets a flag once a record passes
+pub struct Matched<S> {
+    stream: S,
+    matched: Arc<AtomicBool>,
+}
+
+pub fn matched<S: Stream<Item = Record>>(stream: S, matched: Arc<AtomicBool>) -> Matched<S> {
+    Matched { stream, matched }
+}
+
+impl<S: Stream<Item = Record>> Stream for Matched<S> {
+    type Item = Record;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Record>, S::Error> {
+        let record = try_ready!(self.stream.poll());
+        if record.is_some() {
+            self.matched.store(true, Ordering::Relaxed);
+        }
+        Ok(Async::Ready(record))
+    }
+}
+
+/// Stream adapter that numbers the records passing through with
+/// --line-numbers
+pub struct Numbers<S> {
+    stream: S,
+    /// Number of the next record if enabled
+    next: Option<u64>,
+}
+
+pub fn numbers<S: Stream<Item = Record>>(stream: S, enabled: bool) -> Numbers<S> {
+    Numbers {
+        stream,
+        next: if enabled { Some(1) } else { None },
+    }
+}
+
+impl<S: Stream<Item = Record>> Stream for Numbers<S> {
+    type Item = Record;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Record>, S::Error> {
+        let mut record = try_ready!(self.stream.poll());
+        if let (Some(ref mut next), Some(ref mut record)) = (&mut self.next, &mut record) {
+            record.number = Some(*next);
+            *next += 1;
+        }
+        Ok(Async::Ready(record))
+    }
+}
+
+/// Small seedable pseudo random number generator (splitmix64). Good enough
+/// to pick samples
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform number in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Stream adapter that passes each record with a given probability
+pub struct Sample<S> {
+    stream: S,
+    sample: Option<(f64, Rng)>,
+}
+
+pub fn sample<S: Stream<Item = Record>>(stream: S, sample: Option<(f64, Rng)>) -> Sample<S> {
+    Sample { stream, sample }
+}
+
+impl<S: Stream<Item = Record>> Stream for Sample<S> {
+    type Item = Record;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Record>, S::Error> {
+        let (probability, rng) = match self.sample {
+            Some((ref probability, ref mut rng)) => (*probability, rng),
+            None => return self.stream.poll(),
+        };
+        loop {
+            match try_ready!(self.stream.poll()) {
+                Some(record) => {
+                    if rng.next_f64() < probability {
+                        return Ok(Async::Ready(Some(record)));
+                    }
+                }
+                None => return Ok(Async::Ready(None)),
+            }
+        }
+    }
+}
+
+/// Stream adapter that shows the number of records per second on stderr
+pub struct Rate<S> {
+    stream: S,
+    interval: Option<Interval>,
+    start: Instant,
+    /// Time of the last update of the meter
+    last: Instant,
+    total: u64,
+    /// Records since the last tick of interval
+    current: u64,
+}
+
+/// Show the record rate if enabled and stderr is a terminal
+pub fn rate<S: Stream<Item = Record>>(stream: S, enabled: bool) -> Rate<S> {
+    let interval = if enabled && atty::is(atty::Stream::Stderr) {
+        Some(Interval::new_interval(Duration::from_secs(1)))
+    } else {
+        None
+    };
+    Rate {
+        stream,
+        interval,
+        start: Instant::now(),
+        last: Instant::now(),
+        total: 0,
+        current: 0,
+    }
+}
+
+impl<S> Rate<S> {
+    /// Text of the meter for the records of the last elapsed seconds. Ticks
+    /// of a stalled stream are combined
+    fn line(&self, elapsed: f64) -> String {
+        let rate = self.current as f64 / elapsed.max(f64::EPSILON);
+        format!("{:.0} records/s, {} total", rate, self.total)
+    }
+}
+
+impl<S: Stream<Item = Record>> Stream for Rate<S> {
+    type Item = Record;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Record>, S::Error> {
+        let mut ticks = 0;
+        if let Some(ref mut interval) = self.interval {
+            while let Ok(Async::Ready(Some(_))) = interval.poll() {
+                ticks += 1;
+            }
+        }
+        if ticks > 0 {
+            let line = self.line(self.last.elapsed().as_secs_f64());
+            status_line("rate", line);
+            self.last = Instant::now();
+            self.current = 0;
+        }
+
+        let record = try_ready!(self.stream.poll());
+        if record.is_some() && self.interval.is_some() {
+            self.total += 1;
+            self.current += 1;
+        }
+        Ok(Async::Ready(record))
+    }
+}
+
+impl<S> Drop for Rate<S> {
+    fn drop(&mut self) {
+        if self.interval.is_some() {
+            status_line_remove("rate");
+            status_line_clear();
+            eprintln!(
+                "{} records in {:.1}s",
+                self.total,
+                self.start.elapsed().as_secs_f64()
+            );
+        }
+    }
+}
+
+/// Stream adapter that collapses consecutive records with the same tag and
+/// message. A run of duplicates is followed by a record with the number of
+/// repetitions.
+pub struct Dedup<S> {
+    stream: S,
+    enabled: bool,
+    strict: bool,
+    last: Option<Record>,
+    repeated: usize,
+    next: Option<Record>,
+}
+
+/// Deduplicate records of stream if enabled. Strict comparison includes
+/// timestamp, pid and tid
+pub fn dedup<S: Stream<Item = Record>>(stream: S, enabled: bool, strict: bool) -> Dedup<S> {
+    Dedup {
+        stream,
+        enabled,
+        strict,
+        last: None,
+        repeated: 0,
+        next: None,
+    }
+}
+
+impl<S> Dedup<S> {
+    fn is_duplicate(&self, record: &Record) -> bool {
+        match self.last {
+            Some(ref last) => {
+                last.tag == record.tag
+                    && last.message == record.message
+                    && (!self.strict
+                        || (last.timestamp == record.timestamp
+                            && last.process == record.process
+                            && last.thread == record.thread))
+            }
+            None => false,
+        }
+    }
+
+    /// Record with the number of repetitions of the last record if any
+    fn repetitions(&mut self) -> Option<Record> {
+        if self.repeated == 0 {
+            return None;
+        }
+        let message = format!(
+            "... (repeated {} {})",
+            self.repeated,
+            if self.repeated == 1 { "time" } else { "times" }
+        );
+        self.repeated = 0;
+        self.last.as_ref().map(|last| Record {
+            raw: message.clone(),
+            message,
+            ..last.clone()
+        })
+    }
+}
+
+impl<S: Stream<Item = Record>> Stream for Dedup<S> {
+    type Item = Record;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Record>, S::Error> {
+        if !self.enabled {
+            return self.stream.poll();
+        }
+
+        if let Some(record) = self.next.take() {
+            return Ok(Async::Ready(Some(record)));
+        }
+
+        loop {
+            match try_ready!(self.stream.poll()) {
+                Some(record) => {
+                    if self.is_duplicate(&record) {
+                        self.repeated += 1;
+                        self.last = Some(record);
+                        continue;
+                    }
+
+                    let repetitions = self.repetitions();
+                    self.last = Some(record.clone());
+                    return Ok(Async::Ready(match repetitions {
+                        Some(repetitions) => {
+                            self.next = Some(record);
+                            Some(repetitions)
+                        }
+                        None => Some(record),
+                    }));
+                }
+                None => return Ok(Async::Ready(self.repetitions())),
+            }
+        }
+    }
+}
+
+/// Match the raw line of record. Records without one are matched as "tag: message"
+fn matches(regex: &Regex, record: &Record) -> bool {
+    if record.raw.is_empty() {
+        regex.is_match(&format!("{}: {}", record.tag, record.message))
+    } else {
+        regex.is_match(&record.raw)
+    }
+}
+
+/// Stream adapter that passes records matching a regex with up to context
+/// records before and after each match
+pub struct Grep<S> {
+    stream: S,
+    regex: Option<Regex>,
+    context: usize,
+    before: VecDeque<Record>,
+    after: usize,
+    pending: VecDeque<Record>,
+}
+
+/// Grep records of stream if regex is set
+pub fn grep<S: Stream<Item = Record>>(stream: S, regex: Option<Regex>, context: usize) -> Grep<S> {
+    Grep {
+        stream,
+        regex,
+        context,
+        before: VecDeque::with_capacity(context),
+        after: 0,
+        pending: VecDeque::new(),
+    }
+}
+
+impl<S: Stream<Item = Record>> Stream for Grep<S> {
+    type Item = Record;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Record>, S::Error> {
+        let regex = match self.regex {
+            Some(ref regex) => regex,
+            None => return self.stream.poll(),
+        };
+
+        loop {
+            if let Some(record) = self.pending.pop_front() {
+                return Ok(Async::Ready(Some(record)));
+            }
+
+            let record = match try_ready!(self.stream.poll()) {
+                Some(record) => record,
+                None => return Ok(Async::Ready(None)),
+            };
+
+            if matches(regex, &record) {
+                self.pending.extend(self.before.drain(..));
+                self.pending.push_back(record);
+                self.after = self.context;
+            } else if self.after > 0 {
+                self.after -= 1;
+                return Ok(Async::Ready(Some(record)));
+            } else if self.context > 0 {
+                if self.before.len() == self.context {
+                    self.before.pop_front();
+                }
+                self.before.push_back(record);
+            }
+        }
+    }
+}
+
+/// Stream adapter that ends the stream n records after the first record
+/// matching a regex
+pub struct Trigger<S> {
+    stream: S,
+    regex: Option<Regex>,
+    tail: usize,
+    /// Records left after the trigger matched
+    remaining: Option<usize>,
+}
+
+/// End stream tail records after the trigger regex matched
+pub fn trigger<S: Stream<Item = Record>>(
+    stream: S,
+    regex: Option<Regex>,
+    tail: usize,
+) -> Trigger<S> {
+    Trigger {
+        stream,
+        regex,
+        tail,
+        remaining: None,
+    }
+}
+
+impl<S: Stream<Item = Record>> Stream for Trigger<S> {
+    type Item = Record;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Record>, S::Error> {
+        match self.remaining {
+            Some(0) => return Ok(Async::Ready(None)),
+            Some(ref mut n) => {
+                let record = try_ready!(self.stream.poll());
+                *n -= 1;
+                return Ok(Async::Ready(record));
+            }
+            None => (),
+        }
+
+        let record = try_ready!(self.stream.poll());
+        if let (Some(ref regex), Some(ref record)) = (&self.regex, &record) {
+            if matches(regex, record) {
+                self.remaining = Some(self.tail);
+            }
+        }
+        Ok(Async::Ready(record))
+    }
+}
+
+/// Stream adapter that merges the lines of java stack traces into the
+/// message of the preceding record. A record is held back until the next
+/// record of the stream shows that the trace ended.
+pub struct Coalesce<S> {
+    stream: S,
+    enabled: bool,
+    pending: Option<Record>,
+}
+
+/// Coalesce stack traces of stream if enabled
+pub fn coalesce<S: Stream<Item = Record>>(stream: S, enabled: bool) -> Coalesce<S> {
+    Coalesce {
+        stream,
+        enabled,
+        pending: None,
+    }
+}
+
+impl<S: Stream<Item = Record>> Stream for Coalesce<S> {
+    type Item = Record;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Record>, S::Error> {
+        if !self.enabled {
+            return self.stream.poll();
+        }
+
+        loop {
+            let record = match try_ready!(self.stream.poll()) {
+                Some(record) => record,
+                None => return Ok(Async::Ready(self.pending.take())),
+            };
+
+            match self.pending {
+                // Never merge records of other tags or processes
+                Some(ref mut pending)
+                    if pending.tag == record.tag
+                        && pending.process == record.process
+                        && TRACE_CONTINUATION.is_match(&record.message) =>
+                {
+                    pending.message.push('\n');
+                    pending.message.push_str(&record.message);
+                    pending.raw.push('\n');
+                    pending.raw.push_str(&record.raw);
+                }
+                Some(_) => return Ok(Async::Ready(self.pending.replace(record))),
+                None => self.pending = Some(record),
+            }
+        }
+    }
+}
+
+/// Local host time in ISO-8601 with milliseconds e.g "2019-03-14T10:00:00.123"
+fn host_time(tm: &Tm) -> String {
+    let time = strftime("%Y-%m-%dT%H:%M:%S", tm).expect("Date format error");
+    format!("{}.{:03}", time, tm.tm_nsec / 1_000_000)
+}
+
+/// Prepend the local host time to the raw line of record. The clock is read
+/// once per record
+pub fn hostclock(mut record: Record) -> Record {
+    record.raw = format!("{} {}", host_time(&time::now()), record.raw);
+    record
+}
+
+/// Lines dropped on the device as reported by a chatty notice
+#[derive(Debug, PartialEq)]
+pub struct Dropped {
+    /// Uid of the process whose lines are dropped
+    pub uid: u32,
+    /// Process or thread name. Empty on some Android versions
+    pub process: String,
+    /// Number of lines dropped
+    pub lines: usize,
+    /// Lines are identical to the previous one. Otherwise they expired
+    pub identical: bool,
+}
+
+/// Parse a notice of logd about dropped lines. These are logged with the tag
+/// chatty
+pub fn dropped(record: &Record) -> Option<Dropped> {
+    if record.tag != "chatty" {
+        return None;
+    }
+    let captures = CHATTY.captures(record.message.trim())?;
+    Some(Dropped {
+        uid: captures[1].parse().ok()?,
+        process: captures[2].to_owned(),
+        lines: captures[4].parse().ok()?,
+        identical: &captures[3] == "identical",
+    })
+}
+
+/// Replace a chatty notice with a warning of rogcat that tells how many lines
+/// of which process are missing. Other records are passed unmodified
+pub fn show_dropped(mut record: Record) -> Record {
+    if let Some(dropped) = dropped(&record) {
+        let process = if dropped.process.is_empty() {
+            format!("uid {}", dropped.uid)
+        } else {
+            format!("{} (uid {})", dropped.process, dropped.uid)
+        };
+        let reason = if dropped.identical {
+            "identical"
+        } else {
+            "expired"
+        };
+        record.level = Level::Warn;
+        record.tag = "rogcat".to_owned();
+        let lines = if dropped.lines == 1 { "line" } else { "lines" };
+        record.message = format!(
+            "Device dropped {} {} {} of {}",
+            dropped.lines, reason, lines, process
+        );
+    }
+    record
+}
+
+/// Truncate messages longer than max characters. The last character is
+/// replaced by an ellipsis
+pub fn truncate(mut record: Record, max: usize) -> Record {
+    if record.message.chars().count() > max {
+        let mut message = record
+            .message
+            .chars()
+            .take(max.saturating_sub(1))
+            .collect::<String>();
+        if max > 0 {
+            message.push('…');
+        }
+        record.message = message;
+    }
+    record
+}
+
+/// Check if the ratio of non-printable characters in message exceeds
+/// threshold. Bytes that are invalid UTF-8 are decoded as the replacement
+/// character and count as non-printable, too
+pub fn binary(message: &str, threshold: f64) -> bool {
+    let mut total = 0;
+    let mut garbage = 0;
+    for c in message.chars() {
+        total += 1;
+        if (c.is_control() && c != '\t') || c == '\u{fffd}' {
+            garbage += 1;
+        }
+    }
+    total > 0 && garbage as f64 / total as f64 > threshold
+}
+
+/// Extracts the capture groups of a regex from the record messages
+pub struct Extract {
+    regex: Regex,
+    template: Option<Template>,
+    /// Replace the raw line with the captured groups
+    output: bool,
+}
+
+impl Extract {
+    /// Template may contain the names of the capture groups. The captures
+    /// are written tab separated if output is set and no template is given
+    pub fn new(regex: Regex, template: Option<&str>, output: bool) -> Result<Extract, Error> {
+        let groups = regex.capture_names().flatten().collect::<Vec<_>>();
+        let template = template
+            .map(|t| Template::with_groups(t, &groups))
+            .transpose()?;
+        Ok(Extract {
+            regex,
+            template,
+            output,
+        })
+    }
+
+    /// Drop record if the message does not match
+    pub fn extract(&self, mut record: Record) -> Option<Record> {
+        let raw = if record.message.is_empty() {
+            record.raw.clone()
+        } else {
+            record.message.clone()
+        };
+        let captures = self.regex.captures(&raw)?;
+        if let Some(ref template) = self.template {
+            record.raw =
+                template.render_groups(&record, |name| captures.name(name).map(|m| m.as_str()));
+        } else if self.output {
+            record.raw = captures
+                .iter()
+                .skip(1)
+                .map(|c| c.map(|m| m.as_str()).unwrap_or(""))
+                .collect::<Vec<_>>()
+                .join("\t");
+        }
+        Some(record)
+    }
+}
+
+/// Convert the timestamp of record to UTC with the device zone offset in
+/// seconds or the host zone
+pub fn utc(mut record: Record, offset: Option<i32>, now: &Tm) -> Record {
+    record.timestamp = record.timestamp.map(|t| t.to_utc(offset, now));
+    record
+}
+
+/// Tag and message pairs of a previous capture for --diff
+pub struct Baseline {
+    pairs: HashSet<(String, String)>,
+    exact: bool,
+}
+
+impl Baseline {
+    /// Load a capture written with --format json or in any other format
+    /// the parser detects
+    pub fn load(path: &str, exact: bool) -> Result<Baseline, Error> {
+        let content =
+            fs::read(path).map_err(|e| format_err!("Failed to read baseline {}: {}", path, e))?;
+        let mut parser = Parser::default();
+        let pairs = String::from_utf8_lossy(&content)
+            .lines()
+            .filter(|l| !l.is_empty())
+            .map(|l| parser.parse(l))
+            .map(|r| Self::key(&r, exact))
+            .collect();
+        Ok(Baseline { pairs, exact })
+    }
+
+    /// Numbers like pids, addresses, durations or times within the message
+    /// differ between runs. Replace them unless exact
+    fn key(record: &Record, exact: bool) -> (String, String) {
+        if exact {
+            (record.tag.clone(), record.message.clone())
+        } else {
+            (
+                VARIABLE.replace_all(&record.tag, "#").into_owned(),
+                VARIABLE.replace_all(&record.message, "#").into_owned(),
+            )
+        }
+    }
+
+    pub fn contains(&self, record: &Record) -> bool {
+        self.pairs.contains(&Self::key(record, self.exact))
+    }
+}
+
+#[test]
+fn tail_ring_buffer() {
+    use futures::{stream::iter_ok, Future};
+
+    let t = tail(iter_ok::<_, ()>(0..100), Some(3)).collect().wait();
+    assert_eq!(t, Ok(vec![97, 98, 99]));
+    let t = tail(iter_ok::<_, ()>(0..2), Some(3)).collect().wait();
+    assert_eq!(t, Ok(vec![0, 1]));
+    let t = tail(iter_ok::<_, ()>(0..2), None).collect().wait();
+    assert_eq!(t, Ok(vec![0, 1]));
+}
+
+#[test]
+fn stats_counts() {
+    let record = |level, tag: &str| Record {
+        level,
+        tag: tag.to_owned(),
+        ..Default::default()
+    };
+    let mut statistics = Statistics::default();
+    for r in &[
+        record(Level::Info, "A"),
+        record(Level::Info, "B"),
+        record(Level::Error, "A"),
+        record(Level::Info, "A"),
+    ] {
+        statistics.add(r);
+    }
+    assert_eq!(statistics.total, 4);
+    assert_eq!(statistics.levels, vec![(Level::Info, 3), (Level::Error, 1)]);
+    assert_eq!(statistics.tags["A"], 3);
+    assert_eq!(statistics.tags["B"], 1);
+
+    let summary = statistics.to_string();
+    let lines = summary.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "4 records");
+    assert!(lines[2].starts_with('I') && lines[2].ends_with("3    75.0"));
+    assert!(lines[5].starts_with('A') && lines[5].ends_with("3    75.0"));
+}
+
+#[test]
+fn stats_dropped_filtered() {
+    use futures::stream::iter_ok;
+    let args = crate::cli::args(&["rogcat", "-t", "MyApp", "--stats"]);
+    let filter =
+        crate::filter::from_args_profile(&args, &crate::profiles::Profile::default()).unwrap();
+    let parser = &mut Parser::default();
+    let records = [
+        "01-01 12:00:00.000  1234  1234 I chatty  : uid=10054(u0_a54) com.example identical 3 lines",
+        "01-01 12:00:00.000  1234  1234 I MyApp   : message",
+        "01-01 12:00:00.000  1234  1234 I chatty  : uid=10054(u0_a54) com.example expire 2 lines",
+    ]
+    .iter()
+    .map(|l| parser.parse(l))
+    .collect::<Vec<_>>();
+    let dropped = DroppedLines::default();
+    let counted = dropped.clone();
+    let filtered = iter_ok::<_, ()>(records).filter(move |r| {
+        counted.count(r);
+        filter.filter(r)
+    });
+    let mut s = stats(filtered, true, None, false, dropped);
+    let passed = s.by_ref().collect().wait().unwrap();
+    assert_eq!(passed.len(), 1);
+    // The notices are rejected by the tag filter but their lines are counted
+    let summary = s.summary().unwrap();
+    assert!(summary.starts_with("1 records\n"));
+    assert!(summary.ends_with("5 lines dropped on the device: 3 identical, 2 expired\n"));
+}
+
+#[test]
+fn rate_line() {
+    use futures::stream::iter_ok;
+    let mut rate = rate(iter_ok::<_, ()>(vec![Record::default(); 4]), false);
+    rate.total = 40;
+    rate.current = 20;
+    assert_eq!(rate.line(1.0), "20 records/s, 40 total");
+    // Missed ticks of a stall are not counted as one second
+    assert_eq!(rate.line(4.0), "5 records/s, 40 total");
+}
+
+#[test]
+fn stats_interval_delta() {
+    use futures::stream::iter_ok;
+    let record = |level| Record {
+        level,
+        ..Default::default()
+    };
+    let records = vec![
+        record(Level::Info),
+        record(Level::Error),
+        record(Level::Info),
+    ];
+    let mut stats = stats(
+        iter_ok::<_, ()>(records),
+        true,
+        Some(Duration::from_secs(3600)),
+        false,
+        DroppedLines::default(),
+    );
+    let delta = |s: &Stats<_>| s.interval.as_ref().unwrap().1.levels_line();
+    stats.poll().unwrap();
+    stats.poll().unwrap();
+    assert_eq!(delta(&stats), "2 records: I 1, E 1");
+
+    // A tick resets the delta but not the totals
+    stats.tick(1);
+    assert_eq!(delta(&stats), "0 records");
+    stats.poll().unwrap();
+    assert_eq!(delta(&stats), "1 records: I 1");
+    assert_eq!(stats.statistics.as_ref().unwrap().total, 3);
+
+    // Missed ticks of a stall are labeled with the actual interval
+    stats.last -= Duration::from_secs(3);
+    let line = stats.tick_line(3).unwrap();
+    assert!(line.starts_with("1 records: I 1 in 3."));
+    assert_eq!(stats.tick_line(1).unwrap(), "0 records");
+}
+
+#[test]
+fn replay_speed() {
+    use futures::stream::iter_ok;
+    use tokio::runtime::Runtime;
+    let record = |t: &str| Record {
+        timestamp: Some(rogcat::record::Timestamp::new(
+            time::strptime(t, "%m-%d %H:%M:%S.%f").unwrap(),
+        )),
+        ..Default::default()
+    };
+    let records = vec![
+        record("03-14 10:00:00.000"),
+        record("03-14 10:00:00.100"),
+        record("03-14 10:01:00.100"),
+    ];
+    let replayed = replay(
+        iter_ok::<_, ()>(records),
+        Some((2.0, Duration::from_millis(70))),
+    )
+    .map(|_| Instant::now())
+    .collect();
+    let emitted = Runtime::new().unwrap().block_on(replayed).unwrap();
+
+    // 100ms at twice the speed and a minute capped at the max gap
+    let gap = emitted[1] - emitted[0];
+    assert!(gap >= Duration::from_millis(45) && gap < Duration::from_millis(68));
+    let gap = emitted[2] - emitted[1];
+    assert!(gap >= Duration::from_millis(65) && gap < Duration::from_millis(120));
+
+    // A tiny speed is capped at the max gap instead of overflowing
+    let records = vec![record("03-14 10:00:00.000"), record("03-14 10:01:00.000")];
+    let replayed = replay(
+        iter_ok::<_, ()>(records),
+        Some((1e-20, Duration::from_millis(10))),
+    )
+    .collect();
+    assert_eq!(Runtime::new().unwrap().block_on(replayed).unwrap().len(), 2);
+}
+
+#[test]
+fn numbers_after_filter() {
+    use futures::stream::iter_ok;
+    use rogcat::record::Format;
+    let args = crate::cli::args(&["rogcat", "-m", "!drop"]);
+    let filter =
+        crate::filter::from_args_profile(&args, &crate::profiles::Profile::default()).unwrap();
+    let records = ["one", "drop", "two", "drop", "drop", "three"]
+        .iter()
+        .map(|m| Record {
+            message: (*m).to_owned(),
+            raw: (*m).to_owned(),
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+    let numbered = numbers(
+        iter_ok::<_, ()>(records).filter(move |r| filter.filter(r)),
+        true,
+    )
+    .collect()
+    .wait()
+    .unwrap();
+    let numbers = numbered.iter().map(|r| r.number).collect::<Vec<_>>();
+    assert_eq!(numbers, vec![Some(1), Some(2), Some(3)]);
+    assert_eq!(numbered[2].message, "three");
+
+    assert_eq!(
+        Format::Raw.fmt_record(&numbered[2]).unwrap(),
+        "     3 three"
+    );
+    let json = Format::Json.fmt_record(&numbered[2]).unwrap();
+    assert!(json.ends_with(",\"n\":3}"));
+    let columns = rogcat::record::CsvColumns {
+        number: true,
+        ..Default::default()
+    };
+    let csv = Format::Csv(columns).fmt_record(&numbered[2]).unwrap();
+    assert!(csv.ends_with(",three,3"));
+    let csv = Format::Csv(Default::default())
+        .fmt_record(&numbered[2])
+        .unwrap();
+    assert!(csv.ends_with(",three"));
+}
+
+#[test]
+fn monotonic_increasing() {
+    use futures::stream::iter_ok;
+    use rogcat::record::Format;
+    let items = (0..3).map(|_| {
+        std::thread::sleep(Duration::from_millis(20));
+        Record {
+            raw: "unparsed".to_owned(),
+            ..Default::default()
+        }
+    });
+    let seconds = received(iter_ok::<_, ()>(items), Some(Instant::now()))
+        .map(|(seconds, record)| {
+            let record = Record {
+                received: seconds,
+                ..record
+            };
+            let line = Format::Raw.fmt_record(&record).unwrap();
+            assert!(line.starts_with('['));
+            assert!(line.ends_with("] unparsed"));
+            line[1..9].trim().parse::<f64>().unwrap()
+        })
+        .collect()
+        .wait()
+        .unwrap();
+    assert!(seconds[0] >= 0.02);
+    assert!(seconds.windows(2).all(|w| w[1] > w[0]));
+    assert_eq!(
+        received(iter_ok::<_, ()>(vec![1]), None).collect().wait(),
+        Ok(vec![(None, 1)])
+    );
+}
+
+#[test]
+fn dedup_burst() {
+    use futures::{stream::iter_ok, Future};
+    let record = |message: &str, pid: &str| Record {
+        message: message.to_owned(),
+        process: pid.to_owned(),
+        ..Default::default()
+    };
+    let mut records = (0..5)
+        .map(|i| record("spam", &i.to_string()))
+        .collect::<Vec<_>>();
+    records.push(record("ham", "0"));
+
+    let messages = |strict| {
+        dedup(iter_ok::<_, ()>(records.clone()), true, strict)
+            .map(|r| r.message)
+            .collect()
+            .wait()
+            .unwrap()
+    };
+    assert_eq!(
+        messages(false),
+        vec!["spam", "... (repeated 4 times)", "ham"]
+    );
+    assert_eq!(messages(true).len(), 6);
+}
+
+#[test]
+fn grep_context() {
+    use futures::{stream::iter_ok, Future};
+    let records = (0..10)
+        .map(|i| Record {
+            raw: format!("line {}", i),
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+    let lines = |context, regex| {
+        grep(
+            iter_ok::<_, ()>(records.clone()),
+            Some(Regex::new(regex).unwrap()),
+            context,
+        )
+        .map(|r| r.raw)
+        .collect()
+        .wait()
+        .unwrap()
+    };
+    assert_eq!(
+        lines(2, "line 5"),
+        vec!["line 3", "line 4", "line 5", "line 6", "line 7"]
+    );
+    assert_eq!(
+        lines(1, "line [34]"),
+        vec!["line 2", "line 3", "line 4", "line 5"]
+    );
+    assert_eq!(lines(0, "line 0"), vec!["line 0"]);
+}
+
+#[test]
+fn extract_groups() {
+    let record = |message: &str| Record {
+        message: message.to_owned(),
+        raw: format!("I Net: {}", message),
+        ..Default::default()
+    };
+    let regex = Regex::new(r"request (?P<url>\S+) took (?P<ms>\d+)ms").unwrap();
+    let lines = [
+        "request /a took 12ms",
+        "connection reset",
+        "request /b took 345ms",
+    ];
+
+    let extract = Extract::new(regex.clone(), None, true).unwrap();
+    let raw = lines
+        .iter()
+        .filter_map(|l| extract.extract(record(l)))
+        .map(|r| r.raw)
+        .collect::<Vec<_>>();
+    assert_eq!(raw, vec!["/a\t12", "/b\t345"]);
+
+    let extract = Extract::new(regex.clone(), Some("{ms} {tag}{url}"), true).unwrap();
+    assert_eq!(extract.extract(record(lines[2])).unwrap().raw, "345 /b");
+
+    // Without output the records are just filtered
+    let extract = Extract::new(regex.clone(), None, false).unwrap();
+    assert_eq!(
+        extract.extract(record(lines[0])).unwrap().raw,
+        "I Net: request /a took 12ms"
+    );
+    assert!(extract.extract(record(lines[1])).is_none());
+
+    assert!(Extract::new(regex, Some("{latency}"), true).is_err());
+}
+
+#[test]
+fn sample_seed() {
+    let records = || {
+        (0..20)
+            .map(|n| Record {
+                message: n.to_string(),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>()
+    };
+    let run = |probability, seed| {
+        let stream = futures::stream::iter_ok::<_, ()>(records());
+        sample(stream, Some((probability, Rng::new(seed))))
+            .collect()
+            .wait()
+            .unwrap()
+            .iter()
+            .map(|r| r.message.clone())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(run(0.3, 42), run(0.3, 42));
+    assert_eq!(
+        run(0.3, 42),
+        vec!["1", "2", "4", "6", "10", "15", "16", "18"]
+    );
+    assert_eq!(run(0.0, 42).len(), 0);
+    assert_eq!(run(1.0, 42).len(), 20);
+    let disabled = sample(futures::stream::iter_ok::<_, ()>(records()), None);
+    assert_eq!(disabled.collect().wait().unwrap().len(), 20);
+}
+
+#[test]
+fn truncate_message() {
+    let record = |message: &str| Record {
+        message: message.to_owned(),
+        ..Default::default()
+    };
+    assert_eq!(truncate(record("0123456789"), 5).message, "0123…");
+    assert_eq!(truncate(record("01234"), 5).message, "01234");
+    assert_eq!(truncate(record("äöüäöü"), 3).message, "äö…");
+}
+
+#[test]
+fn binary_message() {
+    assert!(!binary("Start proc com.android.settings", 0.3));
+    assert!(!binary("", 0.3));
+    assert!(!binary("tab\tseparated", 0.0));
+    let garbage = "ok\u{1}\u{2}\u{fffd}\u{fffd}\u{1b}";
+    assert!(binary(garbage, 0.3));
+    assert!(!binary(garbage, 0.9));
+}
+
+#[test]
+fn hostclock_prefix() {
+    let tm = time::strptime("2019-03-14 10:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+    let tm = Tm {
+        tm_nsec: 42_000_000,
+        ..tm
+    };
+    assert_eq!(host_time(&tm), "2019-03-14T10:00:00.042");
+
+    let record = hostclock(Record {
+        raw: "unparsed line".to_owned(),
+        ..Default::default()
+    });
+    let re = Regex::new(r"^\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}\.\d{3} unparsed line$").unwrap();
+    assert!(re.is_match(&record.raw));
+}
+
+#[test]
+fn trigger_tail() {
+    use futures::{stream::iter_ok, Future};
+    let records = (0..10).map(|i| Record {
+        raw: format!("line {}", i),
+        ..Default::default()
+    });
+    let lines = trigger(
+        iter_ok::<_, ()>(records),
+        Some(Regex::new("line 3").unwrap()),
+        2,
+    )
+    .map(|r| r.raw)
+    .collect()
+    .wait()
+    .unwrap();
+    assert_eq!(
+        lines,
+        vec!["line 0", "line 1", "line 2", "line 3", "line 4", "line 5"]
+    );
+}
+
+#[test]
+fn coalesce_trace() {
+    use futures::{stream::iter_ok, Future};
+    let record = |tag: &str, message: &str| Record {
+        tag: tag.to_owned(),
+        process: "42".to_owned(),
+        message: message.to_owned(),
+        raw: message.to_owned(),
+        ..Default::default()
+    };
+    let records = vec![
+        record("AndroidRuntime", "java.lang.IllegalStateException: boom"),
+        record("AndroidRuntime", "at com.foo.Bar.run(Bar.java:10)"),
+        record("AndroidRuntime", "at com.foo.Main.main(Main.java:5)"),
+        record("Other", "at com.foo.Bar.run(Bar.java:10)"),
+        record("AndroidRuntime", "done"),
+    ];
+    let messages = coalesce(iter_ok::<_, ()>(records), true)
+        .map(|r| r.message)
+        .collect()
+        .wait()
+        .unwrap();
+    assert_eq!(
+        messages,
+        vec![
+            "java.lang.IllegalStateException: boom\nat com.foo.Bar.run(Bar.java:10)\nat com.foo.Main.main(Main.java:5)",
+            "at com.foo.Bar.run(Bar.java:10)",
+            "done"
+        ]
+    );
+}
+
+#[test]
+fn matched_records() {
+    use futures::{stream::iter_ok, Future};
+
+    let flag = Arc::new(AtomicBool::new(false));
+    let records = vec![Record::default()];
+    let s = matched(iter_ok::<_, ()>(records).filter(|_| false), flag.clone());
+    assert_eq!(s.collect().wait(), Ok(vec![]));
+    assert!(!flag.load(Ordering::Relaxed));
+
+    let s = matched(iter_ok::<_, ()>(vec![Record::default()]), flag.clone());
+    assert_eq!(s.collect().wait().map(|r| r.len()), Ok(1));
+    assert!(flag.load(Ordering::Relaxed));
+}
+
+#[test]
+fn diff_baseline() {
+    let dir = crate::utils::TempDir::new("baseline");
+    let path = dir.join("baseline");
+    let baseline = [
+        r#"{"timestamp":"03-14 10:00:00.000","message":"Start proc 1234 at 0x7f00","level":"Info","tag":"am","process":"1","thread":"1","raw":""}"#,
+        "03-14 10:00:01.000   100   100 W wm: Window lost",
+    ];
+    fs::write(&path, baseline.join("\n")).unwrap();
+    let path = path.to_str().unwrap();
+    let record = |tag: &str, message: &str| Record {
+        tag: tag.to_owned(),
+        message: message.to_owned(),
+        ..Default::default()
+    };
+    let live = [
+        record("am", "Start proc 5678 at 0x7f42"),
+        record("wm", "Window lost"),
+        record("wm", "Window found"),
+        record("am", "Stop proc 5678"),
+    ];
+    let new = |exact| {
+        let baseline = Baseline::load(path, exact).unwrap();
+        live.iter()
+            .filter(|r| !baseline.contains(r))
+            .map(|r| r.message.as_str())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(new(false), vec!["Window found", "Stop proc 5678"]);
+    assert_eq!(
+        new(true),
+        vec![
+            "Start proc 5678 at 0x7f42",
+            "Window found",
+            "Stop proc 5678"
+        ]
+    );
+    fs::remove_file(path).ok();
+    assert!(Baseline::load(path, false).is_err());
+}
+
+#[test]
+fn tee_raw_unfiltered() {
+    use futures::{stream::iter_ok, Future};
+    use std::fs::read_to_string;
+
+    let dir = crate::utils::TempDir::new("tee");
+    let path = dir.join("tee");
+    let path = path.to_str().unwrap();
+    let lines = vec![
+        StreamData::Line("\x1b[31mline\x1b[0m".to_owned()),
+        StreamData::Record(Record {
+            raw: "record".to_owned(),
+            ..Default::default()
+        }),
+    ];
+    let s = tee_raw(iter_ok(lines), Some(path)).unwrap();
+    // Records dropped after the tee are still written
+    let passed = s.filter(|_| false).collect().wait().unwrap();
+    assert!(passed.is_empty());
+    assert_eq!(
+        read_to_string(path).unwrap(),
+        "\x1b[31mline\x1b[0m\nrecord\n"
+    );
+}
+
+#[test]
+fn idle_timeout() {
+    use futures::{future::Future, stream::poll_fn};
+    use tokio::runtime::Runtime;
+
+    // Emit 1, pause shorter than the timeout, emit 2 and then stall forever
+    let pause = Delay::new(Instant::now() + Duration::from_millis(50))
+        .into_stream()
+        .map(|_| 2)
+        .map_err(|_| ());
+    let stall = poll_fn(|| -> Poll<Option<i32>, ()> { Ok(Async::NotReady) });
+    let s = futures::stream::iter_ok(vec![1]).chain(pause).chain(stall);
+    let start = Instant::now();
+    let items = Runtime::new()
+        .unwrap()
+        .block_on(idle(s, Some(Duration::from_millis(200))).collect())
+        .unwrap();
+    assert_eq!(items, vec![1, 2]);
+    assert!(start.elapsed() >= Duration::from_millis(250));
+}
+
+#[test]
+fn deadline_endless() {
+    use futures::future::lazy;
+    use tokio::runtime::Runtime;
+
+    let start = Instant::now();
+    let items = Runtime::new()
+        .unwrap()
+        .block_on(lazy(|| {
+            let endless = Interval::new_interval(Duration::from_millis(10)).map_err(|_| ());
+            deadline(endless, Some(Duration::from_millis(200))).collect()
+        }))
+        .unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert!(!items.is_empty() && items.len() <= 21);
+}
+
+#[test]
+fn chatty_dropped() {
+    let parser = &mut Parser::default();
+    let lines = [
+        "01-01 12:00:00.000  1234  1234 I chatty  : uid=1000(system) Binder:1234_5 identical 3 lines",
+        "01-01 12:00:00.000  2345  2345 I chatty  : uid=10054(u0_a54) com.example expire 5 lines",
+        "01-01 12:00:00.000  3456  3456 I chatty  : uid=1000 system_server expire 1 line",
+        "01-01 12:00:00.000  3456  3456 I chatty  : uid=10020 expire 2 lines",
+        "01-01 12:00:00.000  4567  4567 I Foo     : uid=1000 bar identical 3 lines",
+    ];
+    let records = lines.iter().map(|l| parser.parse(l)).collect::<Vec<_>>();
+    let dropped = records.iter().map(dropped).collect::<Vec<_>>();
+    assert_eq!(
+        dropped[0],
+        Some(Dropped {
+            uid: 1000,
+            process: "Binder:1234_5".to_owned(),
+            lines: 3,
+            identical: true,
+        })
+    );
+    assert_eq!(
+        dropped[1].as_ref().map(|d| (d.lines, d.identical)),
+        Some((5, false))
+    );
+    assert_eq!(
+        dropped[2].as_ref().map(|d| d.process.as_str()),
+        Some("system_server")
+    );
+    assert_eq!(dropped[3].as_ref().map(|d| d.process.as_str()), Some(""));
+    assert_eq!(dropped[4], None);
+
+    let counted = DroppedLines::default();
+    records.iter().for_each(|r| counted.count(r));
+    let mut statistics = Statistics::default();
+    records.iter().for_each(|r| statistics.add(r));
+    statistics.set_dropped(&counted);
+    assert!(statistics
+        .to_string()
+        .ends_with("11 lines dropped on the device: 3 identical, 8 expired\n"));
+
+    let record = show_dropped(records[2].clone());
+    assert_eq!(record.level, Level::Warn);
+    assert_eq!(record.tag, "rogcat");
+    assert_eq!(
+        record.message,
+        "Device dropped 1 expired line of system_server (uid 1000)"
+    );
+    assert_eq!(show_dropped(records[4].clone()), records[4]);
+}
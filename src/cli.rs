@@ -18,10 +18,10 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::utils;
-use clap::{crate_authors, crate_name, crate_version, App, AppSettings, Arg, SubCommand};
+use crate::{utils, BUFFERS};
+use clap::{crate_authors, crate_name, crate_version, App, AppSettings, Arg, ArgGroup, SubCommand};
 use lazy_static::lazy_static;
-use rogcat::record::Level;
+use rogcat::{parser::InputFormat, record::Level};
 
 lazy_static! {
     static ref ABOUT: String = {
@@ -32,6 +32,12 @@ lazy_static! {
     };
 }
 
+/// Matches of argv for tests
+#[cfg(test)]
+pub fn args(argv: &[&str]) -> clap::ArgMatches<'static> {
+    cli().get_matches_from(argv)
+}
+
 /// Build cli
 pub fn cli() -> App<'static, 'static> {
     App::new(crate_name!())
@@ -39,29 +45,163 @@ pub fn cli() -> App<'static, 'static> {
         .version(crate_version!())
         .author(crate_authors!())
         .about(ABOUT.as_str())
+        .arg(Arg::with_name("adb")
+             .long("adb")
+             .takes_value(true)
+             .global(true)
+             .help("Path to the adb binary. Defaults to ADB if set or adb found in PATH"))
         .arg(Arg::with_name("buffer")
              .short("b")
              .long("buffer")
              .multiple(true)
              .takes_value(true)
+             .possible_values(&BUFFERS)
              .conflicts_with_all(&["input", "COMMAND"])
              .help("Select specific logd buffers. Defaults to main, events, kernel and crash"))
+        .arg(Arg::with_name("all_devices")
+             .long("all-devices")
+             .conflicts_with_all(&["serial", "input", "COMMAND", "listen"])
+             .help("Capture logcat of all connected devices and label each record with the device serial"))
+        .arg(Arg::with_name("app")
+             .long("app")
+             .takes_value(true)
+             .help("Filter on the pids of a package. The pids are resolved with \"adb shell pidof\" on startup"))
+        .arg(Arg::with_name("auto_width")
+             .long("auto-width")
+             .takes_value(true)
+             .min_values(0)
+             .max_values(1)
+             .require_equals(true)
+             .value_name("N")
+             .conflicts_with_all(&["output", "tag_width"])
+             .help("Buffer the first n records (default 100) and use the width of the longest tag (at most 40) as tag width in human format. Later longer tags are truncated"))
+        .arg(Arg::with_name("case_sensitive")
+             .long("case-sensitive")
+             .help("Match the patterns of -t/--tag and --tag-ignore case sensitive. Tags match case insensitive by default"))
+        .arg(Arg::with_name("clear")
+             .short("c")
+             .long("clear")
+             .conflicts_with_all(&["input", "COMMAND"])
+             .help("Clear the selected buffers before logging"))
+        .arg(Arg::with_name("clear_only")
+             .long("clear-only")
+             .requires("clear")
+             .help("Exit after clearing the buffers with --clear"))
+        .arg(Arg::with_name("coalesce_traces")
+             .long("coalesce-traces")
+             .help("Merge the lines of java stack traces into the message of the preceding record"))
         .arg(Arg::with_name("color")
              .long("color")
              .takes_value(true)
              .possible_values(&["auto", "always", "never"])
              .conflicts_with_all(&["highlight", "output"])
              .help("Terminal coloring option"))
+        .arg(Arg::with_name("compact")
+             .long("compact")
+             .conflicts_with_all(&["level_style", "output", "show_date"])
+             .help("Print only time, level, tag and message in the human format e.g \"10:00:00 I Tag: message\". Tags are padded and truncated with --tag-width only"))
+        .arg(Arg::with_name("connect")
+             .long("connect")
+             .takes_value(true)
+             .value_name("HOST:PORT")
+             .help("Connect to a device over tcp with \"adb connect\" before logging and disconnect on exit"))
+        .arg(Arg::with_name("connection_events")
+             .long("connection-events")
+             .help("Emit \"device disconnected\" and \"device reconnected\" records with level error and tag rogcat when adb loses the device"))
+        .arg(Arg::with_name("context")
+             .short("C")
+             .long("context")
+             .takes_value(true)
+             .requires("grep")
+             .help("Print n records before and after each match of --grep"))
+        .arg(Arg::with_name("crashes")
+             .long("crashes")
+             .conflicts_with("profile")
+             .help("Show crashes only. Selects the built in profile \"crashes\": Level error and above of the tags AndroidRuntime, DEBUG, libc and System.err. Implies --coalesce-traces. Other filters are applied on top"))
+        .arg(Arg::with_name("dedup")
+             .long("dedup")
+             .help("Collapse consecutive records with the same tag and message and print the number of repetitions"))
+        .arg(Arg::with_name("dedup_strict")
+             .long("dedup-strict")
+             .requires("dedup")
+             .help("Compare timestamp, pid and tid too when collapsing records with --dedup"))
+        .arg(Arg::with_name("diff")
+             .long("diff")
+             .takes_value(true)
+             .value_name("BASELINE")
+             .help("Drop records whose tag and message appear in a previous capture e.g written with --format json to show what is new since the last run. Numbers like pids, addresses and times are ignored when comparing messages"))
+        .arg(Arg::with_name("diff_exact")
+             .long("diff-exact")
+             .requires("diff")
+             .help("Compare messages with --diff exactly without ignoring numbers"))
+        .arg(Arg::with_name("drop_binary")
+             .long("drop-binary")
+             .takes_value(true)
+             .min_values(0)
+             .max_values(1)
+             .require_equals(true)
+             .value_name("RATIO")
+             .help("Drop records whose message consists of more than this ratio of non-printable characters (default 0.3)"))
         .arg(Arg::with_name("dump")
              .short("d")
              .long("dump")
              .conflicts_with_all(&["input", "COMMAND", "restart"])
              .help("Dump the log and then exit (don't block)"))
+        .arg(Arg::with_name("duration")
+             .long("duration")
+             .takes_value(true)
+             .value_name("DURATION")
+             .help("Stop after the duration e.g 30, 10m or 1h regardless of the input. Plain numbers are seconds. Ends together with --head whichever is first"))
+        .arg(Arg::with_name("exit_on_empty")
+             .long("exit-on-empty")
+             .help("Exit with 1 if no record passed the filters when the input ends e.g with --dump, --input or --head"))
+        .arg(Arg::with_name("extract")
+             .long("extract")
+             .takes_value(true)
+             .help("Drop records whose message does not match the regex. Use --format extract or --format-template with named capture groups to print the captured fields only"))
+        .arg(Arg::with_name("flush_interval")
+             .long("flush-interval")
+             .takes_value(true)
+             .value_name("MS")
+             .help("Flush stdout and file output at most every n milliseconds. Buffering is faster for bulk captures. Defaults to 0 which flushes whenever no further records are ready. The human format is printed line by line"))
+        .arg(Arg::with_name("follow")
+             .long("follow")
+             .requires("input")
+             .help("Wait for data appended to the last input file like tail -f. A truncated or replaced file is read again from the start"))
+        .arg(Arg::with_name("follow_app")
+             .long("follow-app")
+             .requires("app")
+             .help("Update the pids of --app periodically to follow restarts of the app"))
+        .arg(Arg::with_name("filter")
+             .long("filter")
+             .takes_value(true)
+             .value_name("EXPR")
+             .help("Drop records that do not match the expression e.g \"tag != Foo || level >= W\". Fields are tag, level, pid and message. Operators are ==, !=, <, <=, >, >=, ~ and !~ for regex matches, &&, ||, ! and parentheses. Quote values with spaces"))
+        .arg(Arg::with_name("filterspec")
+             .long("filterspec")
+             .takes_value(true)
+             .value_name("SPEC")
+             .help("Minimum level per tag like the logcat filterspec e.g \"ActivityManager:W *:S\". The tag * sets the default of all other tags and the level S silences a tag"))
         .arg(Arg::with_name("format")
              .long("format")
              .short("f")
              .takes_value(true)
-             .possible_values(&["csv", "html", "human", "json", "raw"]).help("Output format. Defaults to human on stdout and raw on file output"))
+             .possible_values(&["csv", "extract", "html", "human", "json", "logcat", "logfmt", "raw"]).help("Output format. Defaults to human on stdout and raw on file output. Extract prints the tab separated capture groups of --extract. Logcat rebuilds uniform threadtime lines from the parsed fields. Logfmt prints key value pairs like time=.. level=.. tag=.. msg=.."))
+        .arg(Arg::with_name("exec")
+             .long("exec")
+             .takes_value(true)
+             .help("Write each line to the stdin of a command in addition to the normal output e.g \"grep -i error\". The line is formatted as csv, json, logcat, logfmt or raw depending on --format"))
+        .arg(Arg::with_name("exec_throttle")
+             .long("exec-throttle")
+             .takes_value(true)
+             .value_name("MS")
+             .requires("exec")
+             .help("Wait at least n milliseconds between two lines written to the --exec command to not overwhelm slow consumers. Lines are queued and never dropped. The other output waits only if more than 1024 lines are queued"))
+        .arg(Arg::with_name("format_template")
+             .long("format-template")
+             .takes_value(true)
+             .conflicts_with("format")
+             .help("Output layout with the placeholders {time}, {level}, {pid}, {tid}, {tag}, {message} and {raw} e.g \"{time} {level} {tag}: {message}\". The named capture groups of --extract are valid placeholders, too"))
         .arg(Arg::with_name("filename_format")
              .long("filename-format")
              .short("a")
@@ -71,12 +211,20 @@ pub fn cli() -> App<'static, 'static> {
              .help( "Select a format for output file names. By passing 'single' the filename provided with the '-o' option is used (default).\
                     'enumerate' appends a file sequence number after the filename passed with '-o' option whenever a new file is created \
                     (see 'records-per-file' option). 'date' will prefix the output filename with the current local date when a new file is created"))
+        .arg(Arg::with_name("grep")
+             .long("grep")
+             .takes_value(true)
+             .help("Regex matched against the whole line. Records without a line are matched as \"tag: message\""))
+        .arg(Arg::with_name("gzip")
+             .long("gzip")
+             .requires("output")
+             .help("Compress the output file with gzip. Implied if the output file name ends with .gz"))
         .arg(Arg::with_name("head")
              .short("H")
              .long("head")
              .takes_value(true)
              .conflicts_with_all(&["tail", "restart"])
-             .help( "Read n records and exit"))
+             .help( "Read n records and exit. Records rejected by filters are not counted"))
         .arg(Arg::with_name("highlight")
              .short("h")
              .long("highlight")
@@ -84,17 +232,66 @@ pub fn cli() -> App<'static, 'static> {
              .multiple(true)
              .conflicts_with_all(&["output"])
              .help( "Highlight messages that match this pattern in RE2. The prefix '!' inverts the match"))
+        .arg(Arg::with_name("highlight_color")
+             .long("highlight-color")
+             .takes_value(true)
+             .conflicts_with("output")
+             .help("Color of highlighted message parts, e.g red or a ansi256 number. Defaults to yellow"))
+        .arg(Arg::with_name("idle_timeout")
+             .long("idle-timeout")
+             .takes_value(true)
+             .value_name("SECS")
+             .help("Exit if no line is read for n seconds e.g to stop an automated capture once the device is quiet"))
         .arg(Arg::with_name("input")
              .short("i")
              .long("input")
              .takes_value(true)
              .multiple(true)
-             .help( "Read from file instead of command. Use 'serial://COM0@115200,8N1 or similiar for reading a serial port"))
+             .help( "Read from file instead of command. Multiple files are read one after another. Quoted wildcards like \"trace*\" are expanded sorted by name. Pass \"-\" to read from stdin. Use 'serial://COM0@115200,8N1 or similiar for reading a serial port"))
+        .arg(Arg::with_name("input_format")
+             .long("input-format")
+             .takes_value(true)
+             .possible_values(InputFormat::values())
+             .help("Input format. Must match the logcat -v option if used. Defaults to auto detection"))
+        .arg(Arg::with_name("invert")
+             .short("v")
+             .long("invert")
+             .help("Invert the tag, message and regex filters like grep -v: Records that match them are dropped and all others pass. Level, time, pid and expression filters are applied as usual. Without tag, message or regex filters nothing is inverted"))
         .arg(Arg::with_name("level")
              .short("l")
              .long("level")
              .takes_value(true)
-             .possible_values(Level::values()).help("Minimum level"))
+             .possible_values(Level::values()).help("Minimum level. Records without a level are not filtered"))
+        .arg(Arg::with_name("level_style")
+             .long("level-style")
+             .takes_value(true)
+             .possible_values(&["letter", "word", "emoji"])
+             .help("Level column of the human format. Letter prints V, D, I, W, E, F. Word spells out the level padded to a common width. Emoji prints symbols like \u{26a0} for warnings and \u{2716} for errors. Defaults to letter"))
+        .arg(Arg::with_name("line_numbers")
+             .long("line-numbers")
+             .help("Prefix each record that passed the filters with its number starting at 1. Json, csv and logfmt get an \"n\" field instead. Html always shows the numbers. There is no short -n because it selects the records per file"))
+        .arg(Arg::with_name("listen")
+             .long("listen")
+             .takes_value(true)
+             .value_name("ADDR:PORT")
+             .conflicts_with_all(&["input", "COMMAND"])
+             .help("Listen for tcp connections and read lines from all clients e.g \"0.0.0.0:5000\""))
+        .arg(Arg::with_name("logcat_args")
+             .long("logcat-args")
+             .takes_value(true)
+             .allow_hyphen_values(true)
+             .value_name("ARGS")
+             .conflicts_with_all(&["buffer", "dump", "input", "start_time", "tail", "COMMAND"])
+             .help("Replace the arguments rogcat passes to logcat e.g \"-v threadtime -b crash\". Arguments are split like a shell does. The adb path and serial are kept. Conflicts with --buffer, --dump, --tail and --start-time. Restarts are disabled if the arguments contain -d or -t"))
+        .arg(Arg::with_name("max_message_len")
+             .long("max-message-len")
+             .takes_value(true)
+             .value_name("N")
+             .help("Truncate messages longer than n characters with an ellipsis"))
+        .arg(Arg::with_name("merge_stderr")
+             .long("merge-stderr")
+             .conflicts_with("input")
+             .help("Parse stderr of commands as records instead of printing it as status"))
         .arg(Arg::with_name("message")
              .short("m")
              .long("message")
@@ -107,10 +304,32 @@ pub fn cli() -> App<'static, 'static> {
              .takes_value(true)
              .multiple(true)
              .help("Same as -m/--message but case insensitive"))
+        .arg(Arg::with_name("message_ignore")
+             .long("message-ignore")
+             .takes_value(true)
+             .multiple(true)
+             .help("Drop records with messages matching this RE2 pattern. Same as -m/--message with the prefix '!'"))
         .arg(Arg::with_name("no_dimm")
              .long("no-dimm")
              .conflicts_with("output")
              .help("Use white as dimm color"))
+        .arg(Arg::with_name("no_env_expand")
+             .long("no-env-expand")
+             .requires("COMMAND")
+             .help("Pass $VAR and ${VAR} in COMMAND literally instead of expanding them with the environment"))
+        .arg(Arg::with_name("no_interactive")
+             .long("no-interactive")
+             .help("Disable the keys on terminals. Space pauses and resumes the output and / edits a message filter regex. Enter applies and escape cancels the edit"))
+        .arg(Arg::with_name("no_restart")
+             .long("no-restart")
+             .help("Never restart the command on exit. Wins over --restart and the configuration"))
+        .arg(Arg::with_name("no_restart_marker")
+             .long("no-restart-marker")
+             .help("Do not emit a \"--- reconnected ---\" record when a command is restarted"))
+        .arg(Arg::with_name("no_tag_color")
+             .long("no-tag-color")
+             .conflicts_with("output")
+             .help("Do not color tags"))
         .arg(Arg::with_name("bright_colors")
              .long("bright-colors")
              .conflicts_with("output")
@@ -119,6 +338,9 @@ pub fn cli() -> App<'static, 'static> {
              .long("hide-timestamp")
              .conflicts_with("output")
              .help("Hide timestamp in terminal output"))
+        .arg(Arg::with_name("hostclock")
+             .long("hostclock")
+             .help("Prepend the local time of the host to each line. Useful if the device clock is unset. Defaults the terminal format to raw"))
         .arg(Arg::with_name("output")
              .short("o")
              .long("output")
@@ -134,64 +356,275 @@ pub fn cli() -> App<'static, 'static> {
              .long("profiles-path")
              .takes_value(true)
              .help("Manually specify profile file (overrules ROGCAT_PROFILES)"))
+        .arg(Arg::with_name("pid")
+             .long("pid")
+             .takes_value(true)
+             .multiple(true)
+             .help("Pid filter. A single pid is passed to logcat with --pid=<pid> if the device supports it. Multiple pids and older devices are filtered by rogcat"))
+        .arg(Arg::with_name("prefix")
+             .long("prefix")
+             .takes_value(true)
+             .value_name("STR")
+             .allow_hyphen_values(true)
+             .help("Write a string before each output line. Supports the escapes \\t and \\n. Status messages are not prefixed"))
+        .arg(Arg::with_name("pretty")
+             .long("pretty")
+             .requires("format")
+             .help("Indent json output across multiple lines with a blank line after each record. Lines written with --exec stay compact"))
         .arg(Arg::with_name("profile")
              .short("p")
              .long("profile")
              .takes_value(true)
              .help("Select profile"))
+        .arg(Arg::with_name("quiet")
+             .short("q")
+             .long("quiet")
+             .help("Do not print status messages, progress and the stderr output of commands. Errors are still printed"))
+        .arg(Arg::with_name("raw")
+             .long("raw")
+             .conflicts_with_all(&["app", "case_sensitive", "coalesce_traces", "crashes", "dedup", "diff", "drop_binary", "extract", "filter", "filterspec", "format", "format_template", "grep", "highlight", "hostclock", "input_format", "invert", "level", "max_message_len", "message", "message-ignore-case", "message_ignore", "pid", "profile", "regex_filter", "resolve_pids", "sample", "since", "skip_first", "stats", "tag", "tag_ignore", "tag-ignore-case", "trigger", "until", "utc"])
+             .help("Pass lines to the output without parsing and filtering for maximum throughput. Conflicts with all filter and format options"))
+        .arg(Arg::with_name("rate")
+             .long("rate")
+             .help("Show the number of records per second and the total count on stderr if stderr is a terminal"))
+        .arg(Arg::with_name("read_buffer")
+             .long("read-buffer")
+             .takes_value(true)
+             .value_name("BYTES")
+             .help("Capacity of the buffers reading the output of adb or a command. Use k, M, G suffixes or a plain number. Defaults to 64KiB"))
         .arg(Arg::with_name("records_per_file")
              .short("n")
              .long("records-per-file")
              .takes_value(true)
              .requires("output")
              .help( "Write n records per file. Use k, M, G suffixes or a plain number"))
+        .arg(Arg::with_name("no_header")
+             .long("no-header")
+             .help("Do not write a header line in csv format"))
+        .arg(Arg::with_name("rotate_size")
+             .long("rotate-size")
+             .takes_value(true)
+             .requires("output")
+             .conflicts_with_all(&["records_per_file", "filename_format"])
+             .help("Rotate the output file when it exceeds n bytes. The current file is renamed to <output>.1. Use k, M, G suffixes or a plain number"))
+        .arg(Arg::with_name("rotate_interval")
+             .long("rotate-interval")
+             .takes_value(true)
+             .value_name("DURATION")
+             .requires("output")
+             .help("Rotate the output file on a schedule e.g 1h or 10m. Rotated files are named <output>.YYYYMMDD-HHMMSS by the start of the file. Combines with --rotate-size whichever triggers first. All rotated files are kept unless --rotate-count is set"))
+        .arg(Arg::with_name("rotate_count")
+             .long("rotate-count")
+             .takes_value(true)
+             .requires("rotate")
+             .help("Number of rotated files to keep. Defaults to 5 with --rotate-size and to all with --rotate-interval"))
+        .group(ArgGroup::with_name("rotate")
+             .args(&["rotate_size", "rotate_interval"])
+             .multiple(true))
         .arg(Arg::with_name("regex_filter")
              .long("regex")
              .short("r")
              .takes_value(true)
              .multiple(true)
              .help("Regex filter on tag, pid, thread and message."))
+        .arg(Arg::with_name("replay_max_gap")
+             .long("replay-max-gap")
+             .takes_value(true)
+             .value_name("DURATION")
+             .requires("replay_speed")
+             .help("Longest pause between two records with --replay-speed e.g 500ms or 2s. Defaults to 10s"))
+        .arg(Arg::with_name("replay_speed")
+             .long("replay-speed")
+             .takes_value(true)
+             .value_name("FACTOR")
+             .requires("input")
+             .help("Emit the records of the input files with the time between their timestamps divided by FACTOR e.g 1.0 for real time or 2.0 for twice as fast. Records without a timestamp are emitted immediately. Defaults to 0 which does not wait"))
+        .arg(Arg::with_name("resolve_pids")
+             .long("resolve-pids")
+             .help("Resolve pids to process names with \"adb shell ps\" and show them next to the pid. The names are included in json and csv output. Unknown pids are shown as is"))
+        .arg(Arg::with_name("resolve_pids_interval")
+             .long("resolve-pids-interval")
+             .takes_value(true)
+             .value_name("SECS")
+             .requires("resolve_pids")
+             .help("Interval of process name updates with --resolve-pids. Defaults to 5 seconds"))
         .arg(Arg::with_name("restart")
              .long("restart")
              .conflicts_with_all(&["dump", "input", "tail"])
-             .help("Restart command on exit"))
-        .arg(Arg::with_name("skip")
+             .help("Restart command on exit. Logcat is restarted by default unless disabled in the configuration"))
+        .arg(Arg::with_name("restart_backoff")
+             .long("restart-backoff")
+             .takes_value(true)
+             .help("Initial delay in ms before a command is restarted. Doubles with each restart without output up to 5s. Defaults to 100"))
+        .arg(Arg::with_name("sample")
+             .long("sample")
+             .takes_value(true)
+             .value_name("P")
+             .help("Pass each filtered record with the probability p between 0.0 and 1.0 to estimate the composition of high volume logs"))
+        .arg(Arg::with_name("seed")
+             .long("seed")
+             .takes_value(true)
+             .value_name("N")
+             .requires("sample")
+             .help("Seed of --sample for reproducible runs. Defaults to the current time"))
+        .arg(Arg::with_name("serial")
              .short("s")
+             .long("serial")
+             .takes_value(true)
+             .global(true)
+             .help("Use device with given serial. Defaults to ANDROID_SERIAL if set"))
+        .arg(Arg::with_name("skip")
              .long("skip")
              .help("Skip records on a command restart until the last received last record is received again. Use with caution!"))
+        .arg(Arg::with_name("skip_first")
+             .long("skip-first")
+             .takes_value(true)
+             .help("Drop the first n records that passed all filters. Applied before --head"))
+        .arg(Arg::with_name("show_buffer")
+             .long("show-buffer")
+             .conflicts_with_all(&["input", "COMMAND", "listen", "logcat_args"])
+             .help("Run one logcat per buffer and label each record with the buffer it was read from. \"all\" is expanded to the single buffers. The buffer is shown as a column and included in json and csv output"))
         .arg(Arg::with_name("show_date")
              .long("show-date")
              .conflicts_with("output")
              .help("Show month and day in terminal output"))
+        .arg(Arg::with_name("show_dropped")
+             .long("show-dropped")
+             .help("Replace the chatty notices of the device about dropped identical or expired lines with a warning of rogcat that tells how many lines of which process are missing. The notices are shown regardless of the filters"))
      //    .arg(Arg::with_name("show_time_diff")
      //         .long("show-time-diff")
      //         .conflicts_with("output")
      //         .help( "Show the time difference between the occurence of equal tags in terminal output"))
+        .arg(Arg::with_name("stats")
+             .long("stats")
+             .help("Print record counts per level, of the most frequent tags and of the lines dropped by the device to stderr on exit. Dropped lines are counted regardless of the filters"))
+        .arg(Arg::with_name("stats_inline")
+             .long("stats-inline")
+             .requires("stats_interval")
+             .help("Overwrite the line of --stats-interval on each update instead of appending a new one. Shares the line with --rate"))
+        .arg(Arg::with_name("stats_interval")
+             .long("stats-interval")
+             .takes_value(true)
+             .value_name("SECS")
+             .help("Print the number of records per level since the last update to stderr every SECS seconds"))
+        .arg(Arg::with_name("strict_level")
+             .long("strict-level")
+             .requires("level")
+             .help("Drop records without a level when filtering with --level"))
+        .arg(Arg::with_name("suffix")
+             .long("suffix")
+             .takes_value(true)
+             .value_name("STR")
+             .allow_hyphen_values(true)
+             .help("Write a string after each output line before the newline. Supports the escapes \\t and \\n"))
+        .arg(Arg::with_name("syslog")
+             .long("syslog")
+             .help("Send each record to the local syslog in addition to the normal output. The level is mapped to the severity and the tag to the app name"))
+        .arg(Arg::with_name("syslog_server")
+             .long("syslog-server")
+             .takes_value(true)
+             .value_name("HOST:PORT")
+             .help("Send the records to a remote syslog server over UDP in RFC 5424 format instead of the local syslog"))
         .arg(Arg::with_name("tag")
              .short("t")
              .long("tag")
              .takes_value(true)
-             .multiple(true).help("Tag filters in RE2. The prefix '!' inverts the match"))
+             .multiple(true).help("Tag filters in RE2. The prefix '!' inverts the match. Case insensitive unless --case-sensitive is passed"))
+        .arg(Arg::with_name("tag_ignore")
+             .long("tag-ignore")
+             .takes_value(true)
+             .multiple(true)
+             .help("Drop records with tags matching this RE2 pattern. Same as -t/--tag with the prefix '!'"))
         .arg(Arg::with_name("tag-ignore-case")
              .short("T")
              .long("Tag")
              .takes_value(true)
              .multiple(true)
-             .help("Same as -t/--tag but case insensitive"))
+             .help("Same as -t/--tag but case insensitive also with --case-sensitive"))
+        .arg(Arg::with_name("since")
+             .long("since")
+             .takes_value(true)
+             .help("Drop records before the given time. Use \"MM-DD hh:mm:ss\" e.g \"03-14 10:00:00\""))
+        .arg(Arg::with_name("start_time")
+             .long("start-time")
+             .takes_value(true)
+             .value_name("TIME")
+             .conflicts_with_all(&["input", "COMMAND", "listen", "tail"])
+             .help("Print records of logcat since the given time. Passed as -T to logcat. Use \"MM-DD hh:mm:ss.mmm\" e.g \"03-14 10:00:00.000\". Logcat is not restarted to not print the records again"))
+        .arg(Arg::with_name("strict_time")
+             .long("strict-time")
+             .help("Drop records without a timestamp when filtering with --since or --until"))
+        .arg(Arg::with_name("strip_ansi")
+             .long("strip-ansi")
+             .help("Remove ANSI escape sequences e.g colors from input lines before parsing"))
+        .arg(Arg::with_name("tag_width")
+             .long("tag-width")
+             .takes_value(true)
+             .conflicts_with("output")
+             .help("Width of the tag column in human format. Longer tags are truncated. Defaults to an estimation based on the terminal width"))
         .arg(Arg::with_name("tail")
              .long("tail")
              .takes_value(true)
-             .conflicts_with_all(&["input", "COMMAND", "restart"])
-             .help("Dump only the most recent <COUNT> lines (implies --dump)"))
+             .conflicts_with("restart")
+             .help("Dump only the most recent <COUNT> lines (implies --dump). Records of files and commands are tailed after filtering"))
+        .arg(Arg::with_name("tee_raw")
+             .long("tee-raw")
+             .takes_value(true)
+             .value_name("PATH")
+             .help("Write the unmodified input lines to a file before any filtering. Unlike --output the file contains all lines of the input regardless of filters and formats. Escape sequences are removed already with --strip-ansi"))
+        .arg(Arg::with_name("theme")
+             .long("theme")
+             .takes_value(true)
+             .possible_values(&["dark", "light"])
+             .help("Color theme of the human format. Colors can be overwritten in the theme section of the configuration file. Defaults to dark"))
+        .arg(Arg::with_name("timestamp_monotonic")
+             .long("timestamp-monotonic")
+             .help("Stamp each line with the seconds since the start of rogcat when it is received e.g \"[  12.345]\". Consistent even if the device clock jumps. Works for unparsed lines, too. Wins over --hostclock. Replaces the device time in human format and prefixes the lines of the other text formats. Csv, json and logfmt get a field received"))
+        .arg(Arg::with_name("trigger")
+             .long("trigger")
+             .takes_value(true)
+             .help("Stop when a line matches this regex e.g to capture until a crash occurs"))
+        .arg(Arg::with_name("trigger_tail")
+             .long("trigger-tail")
+             .takes_value(true)
+             .requires("trigger")
+             .help("Keep running for n records after the --trigger matched"))
+        .arg(Arg::with_name("tz")
+             .long("tz")
+             .takes_value(true)
+             .requires("utc")
+             .allow_hyphen_values(true)
+             .help("UTC offset of the device time zone used by --utc e.g \"+02:00\". Defaults to the zone of the host"))
+        .arg(Arg::with_name("until")
+             .long("until")
+             .takes_value(true)
+             .help("Drop records after the given time. Use \"MM-DD hh:mm:ss\" e.g \"03-14 11:00:00\""))
+        .arg(Arg::with_name("utc")
+             .long("utc")
+             .help("Convert timestamps to UTC and print them in ISO-8601 format e.g \"2019-03-14T08:00:00.123Z\""))
+        .arg(Arg::with_name("window")
+             .long("window")
+             .takes_value(true)
+             .value_name("N")
+             .conflicts_with_all(&["output", "format", "format_template", "raw"])
+             .help("Repaint the last n records in place instead of scrolling like a fixed pane. Only if stdout is a terminal"))
+        .arg(Arg::with_name("wrap")
+             .long("wrap")
+             .takes_value(true)
+             .possible_values(&["off", "char", "word"])
+             .help("Wrap messages that exceed the terminal width. Defaults to char. Only applies if stdout is a terminal"))
         .arg(Arg::with_name("COMMAND")
-             .help( "Optional command to run and capture stdout and stdderr from. Pass \"-\" to d capture stdin'. If omitted, rogcat will run \"adb logcat -b all\" and restarts this commmand if 'adb' terminates",))
+             .help( "Optional command to run and capture stdout and stdderr from. Arguments are split like a shell does e.g \"sh -c 'echo a b'\". $VAR and ${VAR} are expanded with the environment unless single quoted. Undefined variables are empty. Pass \"-\" to d capture stdin'. If omitted, rogcat will run \"adb logcat -b all\" and restarts this commmand if 'adb' terminates",))
         .subcommand(SubCommand::with_name("bugreport")
                 .about("Capture bugreport. This is only works for Android versions < 7.")
                 .arg(Arg::with_name("zip").short("z").long("zip").help("Zip report"))
                 .arg(Arg::with_name("overwrite").long("overwrite").help("Overwrite report file if present"))
-                .arg(Arg::with_name("file").help("Output file name - defaults to <now>-bugreport")))
+                .arg(Arg::with_name("file").help("Output file name - defaults to <now>-bugreport. Names ending in .zip are pulled as zip by adb on Android 7 and newer")))
+        .subcommand(SubCommand::with_name("capture")
+                .about("Capture logcat, device properties and dmesg into a timestamped directory until ctrl-c")
+                .arg(Arg::with_name("DIR").help("Parent directory of the capture - defaults to the current directory")))
         .subcommand(SubCommand::with_name("completions")
-                .about("Generates completion scripts")
+                .about("Generates completion scripts for bash, fish or zsh e.g \"rogcat completions bash\"")
                 .arg(Arg::with_name("shell")
                         .required(true)
                         .possible_values(&["bash", "fish", "zsh"])
@@ -203,9 +636,16 @@ pub fn cli() -> App<'static, 'static> {
                          .long("buffer")
                          .multiple(true)
                          .takes_value(true)
+                         .possible_values(&BUFFERS)
                          .help("Select specific log buffers to clear. Defaults to main, events, kernel and crash")))
         .subcommand(SubCommand::with_name("devices")
-                .about("List available devices"))
+                .about("List available devices")
+                .arg(Arg::with_name("format")
+                        .short("f")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["json", "table"])
+                        .help("Output format. Defaults to a table with the serial, state, model and product")))
         .subcommand(SubCommand::with_name("log")
                 .about("Add log message(s) log buffer")
                 .arg(Arg::with_name("tag")
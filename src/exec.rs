@@ -0,0 +1,311 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+    terminal::status,
+    utils::{csv_columns, format_template, split_command},
+    LogSink,
+};
+use clap::ArgMatches;
+use failure::{err_msg, format_err, Error};
+use futures::{try_ready, Async, AsyncSink, Future, Poll, Sink, StartSend};
+use rogcat::record::{CsvColumns, Format, Record};
+use std::{
+    collections::VecDeque,
+    io,
+    process::{Command, Stdio},
+    str::FromStr,
+    time::{Duration, Instant},
+};
+use tokio::{
+    codec::{FramedWrite, LinesCodec},
+    timer::Delay,
+};
+use tokio_process::{Child, ChildStdin, CommandExt};
+
+/// Sink that forwards records to another sink and writes each line to the
+/// stdin of a command
+pub struct Exec {
+    sink: LogSink,
+    cmd: Vec<String>,
+    format: Format,
+    child: Option<Child>,
+    stdin: Option<FramedWrite<ChildStdin, LinesCodec>>,
+    /// Lines not yet accepted by the stdin of the child
+    queue: VecDeque<String>,
+    spawned: bool,
+    /// Minimum time between two lines written to the child
+    throttle: Option<Duration>,
+    /// Earliest time the next line is written with --exec-throttle
+    delay: Option<Delay>,
+}
+
+/// Lines held back for a slow or throttled command before the output waits
+/// for it
+const QUEUE_LEN: usize = 1024;
+
+/// Wrap sink if a command is passed with --exec
+pub fn try_from(args: &ArgMatches, sink: LogSink) -> Result<LogSink, Error> {
+    let cmd = match args.value_of("exec") {
+        Some(cmd) => split_command(cmd)?,
+        None => return Ok(sink),
+    };
+    if cmd.is_empty() {
+        return Err(err_msg("Invalid exec command"));
+    }
+
+    // Human and html are terminal and file only. Pass the raw line instead
+    let format = match format_template(args)? {
+        Some(template) => template,
+        None => match args.value_of("format").map(Format::from_str) {
+            Some(Ok(Format::Csv(_))) => csv_columns(args, Format::Csv(CsvColumns::default())),
+            Some(Ok(Format::Extract)) => Format::Extract,
+            Some(Ok(Format::Json)) => Format::Json,
+            Some(Ok(Format::Logcat)) => Format::Logcat,
+            Some(Ok(Format::Logfmt)) => Format::Logfmt,
+            _ => Format::Raw,
+        },
+    };
+    let throttle = args
+        .value_of("exec_throttle")
+        .map(|v| u64::from_str(v).map_err(|e| format_err!("Invalid exec throttle {}: {}", v, e)))
+        .transpose()?
+        .filter(|ms| *ms > 0)
+        .map(Duration::from_millis);
+
+    Ok(Box::new(Exec {
+        sink,
+        cmd,
+        format,
+        child: None,
+        stdin: None,
+        queue: VecDeque::new(),
+        spawned: false,
+        throttle,
+        delay: None,
+    }))
+}
+
+impl Exec {
+    fn spawn(&mut self) -> Result<(), Error> {
+        let mut child = Command::new(&self.cmd[0])
+            .args(&self.cmd[1..])
+            .stdin(Stdio::piped())
+            .spawn_async()
+            .map_err(|e| format_err!("Failed to spawn \"{}\": {}", self.cmd.join(" "), e))?;
+        let stdin = child.stdin().take().expect("Failed to get stdin");
+        self.stdin = Some(FramedWrite::new(stdin, LinesCodec::new()));
+        self.child = Some(child);
+        self.spawned = true;
+        Ok(())
+    }
+
+    /// The command exited or closed its stdin. Continue without it
+    fn closed(&mut self, e: &io::Error) {
+        status(&format!(
+            "Command \"{}\" failed to read input: {}. Continuing without it",
+            self.cmd.join(" "),
+            e
+        ));
+        self.stdin = None;
+        self.queue.clear();
+    }
+
+    /// Pass the queued lines to stdin of the child. Each line is flushed to
+    /// not stall line buffered consumers. A full pipe or the throttle keeps
+    /// the lines queued. Ready once the queue is empty
+    fn poll_queue(&mut self) -> Async<()> {
+        while !self.queue.is_empty() {
+            if let Some(ref mut delay) = self.delay {
+                if let Ok(Async::NotReady) = delay.poll() {
+                    return Async::NotReady;
+                }
+            }
+            let result = match self.stdin {
+                Some(ref mut stdin) => match self.queue.pop_front() {
+                    Some(line) => match stdin.start_send(line) {
+                        Ok(AsyncSink::NotReady(line)) => {
+                            self.queue.push_front(line);
+                            return Async::NotReady;
+                        }
+                        Ok(AsyncSink::Ready) => stdin.poll_complete().map(|_| ()),
+                        Err(e) => Err(e),
+                    },
+                    None => Ok(()),
+                },
+                None => Ok(()),
+            };
+
+            if let Err(e) = result {
+                self.closed(&e);
+            } else {
+                self.delay = self
+                    .throttle
+                    .map(|throttle| Delay::new(Instant::now() + throttle));
+            }
+        }
+        Async::Ready(())
+    }
+}
+
+impl Sink for Exec {
+    type SinkItem = Record;
+    type SinkError = Error;
+
+    fn start_send(&mut self, record: Record) -> StartSend<Record, Error> {
+        if !self.spawned {
+            self.spawn()?;
+        }
+
+        // The output waits for the child only if the queue is full
+        self.poll_queue();
+        if self.queue.len() >= QUEUE_LEN {
+            return Ok(AsyncSink::NotReady(record));
+        }
+
+        let line = if self.stdin.is_some() {
+            Some(self.format.fmt_record(&record)?)
+        } else {
+            None
+        };
+
+        match self.sink.start_send(record)? {
+            AsyncSink::Ready => {
+                self.queue.extend(line);
+                self.poll_queue();
+                Ok(AsyncSink::Ready)
+            }
+            not_ready => Ok(not_ready),
+        }
+    }
+
+    /// Complete the output and wait for the child to accept the queued lines
+    fn poll_complete(&mut self) -> Poll<(), Error> {
+        let sink = self.sink.poll_complete()?;
+        if self.poll_queue().is_not_ready() {
+            return Ok(Async::NotReady);
+        }
+        let stdin = match self.stdin {
+            Some(ref mut stdin) => stdin.poll_complete(),
+            None => Ok(Async::Ready(())),
+        };
+        match stdin {
+            Ok(Async::Ready(())) => (),
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(e) => self.closed(&e),
+        }
+        Ok(sink)
+    }
+
+    /// Close stdin and wait for the child to process the remaining input
+    fn close(&mut self) -> Poll<(), Error> {
+        try_ready!(self.poll_complete());
+        self.stdin.take();
+        if let Some(ref mut child) = self.child {
+            if let Ok(Async::NotReady) = child.poll() {
+                return Ok(Async::NotReady);
+            }
+        }
+        self.child.take();
+        self.sink.close()
+    }
+}
+
+impl Drop for Exec {
+    /// Kill the child if the sink is not closed e.g on ctrl-c
+    fn drop(&mut self) {
+        self.stdin.take();
+        if let Some(mut child) = self.child.take() {
+            child.kill().ok();
+        }
+    }
+}
+
+#[test]
+#[cfg(unix)]
+fn exec_slow_consumer() {
+    use futures::{stream::iter_ok, Stream};
+    use std::{
+        fs::read_to_string,
+        sync::{Arc, Mutex},
+    };
+    use tokio::runtime::Runtime;
+
+    let dir = crate::utils::TempDir::new("exec");
+    let path = dir.join("output");
+    let path = path.to_str().unwrap().to_owned();
+    let forwarded = Arc::new(Mutex::new(Vec::new()));
+    let run = |cmd: String, lines: usize, throttle: Option<Duration>| {
+        let times = forwarded.clone();
+        let sink = Vec::new().sink_map_err(|_| err_msg("sink")).with(
+            move |r: Record| -> Result<Record, Error> {
+                times.lock().unwrap().push(Instant::now());
+                Ok(r)
+            },
+        );
+        let exec = Exec {
+            sink: Box::new(sink),
+            cmd: split_command(&cmd).unwrap(),
+            format: Format::Raw,
+            child: None,
+            stdin: None,
+            queue: VecDeque::new(),
+            spawned: false,
+            throttle,
+            delay: None,
+        };
+        let records = (0..lines)
+            .map(|n| Record {
+                raw: format!("{:0100}", n),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        let start = Instant::now();
+        let (_records, _exec) = Runtime::new()
+            .unwrap()
+            .block_on(iter_ok::<_, Error>(records).forward(exec))
+            .unwrap();
+        start.elapsed()
+    };
+
+    // The consumer starts reading late. More than a pipe buffer of lines is
+    // held back instead of dropped
+    run(format!("sh -c 'sleep 0.3; cat > {}'", path), 2000, None);
+    let output = read_to_string(&path).unwrap();
+    assert_eq!(output.lines().count(), 2000);
+    assert!(output
+        .lines()
+        .enumerate()
+        .all(|(n, l)| *l == format!("{:0100}", n)));
+
+    forwarded.lock().unwrap().clear();
+    let start = Instant::now();
+    let elapsed = run(
+        format!("sh -c 'cat > {}'", path),
+        5,
+        Some(Duration::from_millis(50)),
+    );
+    assert!(elapsed >= Duration::from_millis(200));
+    assert_eq!(read_to_string(&path).unwrap().lines().count(), 5);
+    // The throttle delays the command but not the output
+    let forwarded = forwarded.lock().unwrap();
+    assert_eq!(forwarded.len(), 5);
+    assert!(forwarded[4] - start < Duration::from_millis(50));
+}
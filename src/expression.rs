@@ -0,0 +1,406 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use failure::{format_err, Error};
+use regex::Regex;
+use rogcat::record::{Level, Record};
+use std::{cmp::Ordering, str::FromStr};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Field {
+    Level,
+    Message,
+    Pid,
+    Tag,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Match,
+    NotMatch,
+}
+
+#[derive(Debug)]
+enum Value {
+    Level(Level),
+    Number(u64),
+    Regex(Regex),
+    Text(String),
+}
+
+#[derive(Debug)]
+enum Node {
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    Compare(Field, Op, Value),
+}
+
+/// Predicate over a record compiled from an expression like
+/// `tag != Foo || level >= W`
+#[derive(Debug)]
+pub struct Expression(Node);
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+    Op(Op),
+    Word(String),
+}
+
+/// Split the expression into tokens with their position
+fn tokenize(s: &str) -> Result<Vec<(usize, Token)>, Error> {
+    let mut tokens = Vec::new();
+    let mut chars = s.char_indices().peekable();
+    while let Some((pos, c)) = chars.next() {
+        let next = chars.peek().map(|(_, c)| *c);
+        let token = match (c, next) {
+            (c, _) if c.is_whitespace() => continue,
+            ('&', Some('&')) => Token::And,
+            ('|', Some('|')) => Token::Or,
+            ('=', Some('=')) => Token::Op(Op::Eq),
+            ('!', Some('=')) => Token::Op(Op::Ne),
+            ('!', Some('~')) => Token::Op(Op::NotMatch),
+            ('<', Some('=')) => Token::Op(Op::Le),
+            ('>', Some('=')) => Token::Op(Op::Ge),
+            ('<', _) => Token::Op(Op::Lt),
+            ('>', _) => Token::Op(Op::Gt),
+            ('~', _) => Token::Op(Op::Match),
+            ('!', _) => Token::Not,
+            ('(', _) => Token::Open,
+            (')', _) => Token::Close,
+            ('"', _) => {
+                let mut word = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c)) => word.push(c),
+                            None => return Err(error(s.len(), "unterminated string")),
+                        },
+                        Some((_, c)) => word.push(c),
+                        None => return Err(error(s.len(), "unterminated string")),
+                    }
+                }
+                tokens.push((pos, Token::Word(word)));
+                continue;
+            }
+            ('&', _) | ('|', _) | ('=', _) => {
+                return Err(error(pos, &format!("unexpected \"{}\"", c)))
+            }
+            (c, _) => {
+                let mut word = c.to_string();
+                while let Some((_, c)) = chars.peek() {
+                    if c.is_whitespace() || "&|=!<>~()\"".contains(*c) {
+                        break;
+                    }
+                    word.push(*c);
+                    chars.next();
+                }
+                tokens.push((pos, Token::Word(word)));
+                continue;
+            }
+        };
+        // Two character tokens
+        match token {
+            Token::And
+            | Token::Or
+            | Token::Op(Op::Eq)
+            | Token::Op(Op::Ne)
+            | Token::Op(Op::NotMatch)
+            | Token::Op(Op::Le)
+            | Token::Op(Op::Ge) => {
+                chars.next();
+            }
+            _ => (),
+        }
+        tokens.push((pos, token));
+    }
+    Ok(tokens)
+}
+
+fn error(pos: usize, msg: &str) -> Error {
+    format_err!("Invalid filter expression at position {}: {}", pos + 1, msg)
+}
+
+/// Recursive descent parser. `!` binds stronger than `&&`, `&&` binds
+/// stronger than `||`
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    /// Length of the expression for errors at the end
+    len: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    /// Position of the current token in the expression
+    fn position(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .map(|(p, _)| *p)
+            .unwrap_or(self.len)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(_, t)| t.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Node, Error> {
+        let mut left = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            left = Node::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Node, Error> {
+        let mut left = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            left = Node::And(Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Node, Error> {
+        let pos = self.position();
+        match self.next() {
+            Some(Token::Not) => Ok(Node::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let expression = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expression),
+                    _ => Err(error(pos, "unbalanced parenthesis")),
+                }
+            }
+            Some(Token::Word(field)) => self.compare(pos, &field),
+            Some(_) => Err(error(pos, "expected field or \"(\"")),
+            None => Err(error(pos, "unexpected end of expression")),
+        }
+    }
+
+    fn compare(&mut self, pos: usize, field: &str) -> Result<Node, Error> {
+        let field = match field {
+            "level" => Field::Level,
+            "message" => Field::Message,
+            "pid" => Field::Pid,
+            "tag" => Field::Tag,
+            f => return Err(error(pos, &format!("unknown field \"{}\"", f))),
+        };
+        let op_pos = self.position();
+        let op = match self.next() {
+            Some(Token::Op(op)) => op,
+            _ => return Err(error(op_pos, "expected comparison operator")),
+        };
+        let value_pos = self.position();
+        let value = match self.next() {
+            Some(Token::Word(value)) => value,
+            _ => return Err(error(value_pos, "expected value")),
+        };
+        let value = match (field, op) {
+            (_, Op::Match) | (_, Op::NotMatch) => Regex::new(&value)
+                .map(Value::Regex)
+                .map_err(|e| error(value_pos, &format!("invalid regex: {}", e)))?,
+            (Field::Level, _) => match Level::from(value.as_str()) {
+                Level::None => {
+                    return Err(error(value_pos, &format!("invalid level \"{}\"", value)))
+                }
+                level => Value::Level(level),
+            },
+            (Field::Pid, Op::Lt)
+            | (Field::Pid, Op::Le)
+            | (Field::Pid, Op::Gt)
+            | (Field::Pid, Op::Ge) => u64::from_str(&value)
+                .map(Value::Number)
+                .map_err(|_| error(value_pos, &format!("invalid pid \"{}\"", value)))?,
+            (_, Op::Eq) | (_, Op::Ne) => Value::Text(value),
+            _ => {
+                return Err(error(
+                    op_pos,
+                    "ordering is only supported for level and pid",
+                ))
+            }
+        };
+        Ok(Node::Compare(field, op, value))
+    }
+}
+
+impl FromStr for Expression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Expression, Error> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+            len: s.len(),
+        };
+        let expression = parser.or()?;
+        if parser.peek().is_some() {
+            return Err(error(parser.position(), "unexpected token"));
+        }
+        Ok(Expression(expression))
+    }
+}
+
+impl Expression {
+    pub fn matches(&self, record: &Record) -> bool {
+        self.0.matches(record)
+    }
+}
+
+impl Node {
+    fn matches(&self, record: &Record) -> bool {
+        match self {
+            Node::And(a, b) => a.matches(record) && b.matches(record),
+            Node::Or(a, b) => a.matches(record) || b.matches(record),
+            Node::Not(e) => !e.matches(record),
+            Node::Compare(field, op, value) => {
+                let text = match field {
+                    Field::Level => None,
+                    Field::Message => Some(record.message.as_str()),
+                    Field::Pid => Some(record.process.as_str()),
+                    Field::Tag => Some(record.tag.as_str()),
+                };
+                let ordering = match (value, text) {
+                    (Value::Regex(r), Some(text)) => return r.is_match(text) == (*op == Op::Match),
+                    (Value::Regex(r), None) => {
+                        return r.is_match(&record.level.to_string()) == (*op == Op::Match)
+                    }
+                    (Value::Level(level), _) => record.level.partial_cmp(level),
+                    // Records without a numeric pid never match an ordering
+                    (Value::Number(n), Some(text)) => u64::from_str(text).ok().map(|p| p.cmp(n)),
+                    (Value::Text(value), Some(text)) => Some(text.cmp(value.as_str())),
+                    _ => None,
+                };
+                match ordering {
+                    Some(ordering) => match op {
+                        Op::Eq => ordering == Ordering::Equal,
+                        Op::Ne => ordering != Ordering::Equal,
+                        Op::Lt => ordering == Ordering::Less,
+                        Op::Le => ordering != Ordering::Greater,
+                        Op::Gt => ordering == Ordering::Greater,
+                        Op::Ge => ordering != Ordering::Less,
+                        Op::Match | Op::NotMatch => false,
+                    },
+                    None => false,
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn expression_precedence() {
+    let record = |tag: &str, level| Record {
+        tag: tag.to_owned(),
+        level,
+        ..Default::default()
+    };
+    let e = |s: &str| Expression::from_str(s).unwrap();
+
+    // && binds stronger than ||
+    let expression = e("tag == a || tag == b && level >= W");
+    assert!(expression.matches(&record("a", Level::Debug)));
+    assert!(!expression.matches(&record("b", Level::Debug)));
+    assert!(expression.matches(&record("b", Level::Error)));
+
+    let expression = e("(tag == a || tag == b) && level >= W");
+    assert!(!expression.matches(&record("a", Level::Debug)));
+    assert!(expression.matches(&record("a", Level::Warn)));
+
+    // ! binds stronger than &&
+    let expression = e("!tag == a && level == I");
+    assert!(expression.matches(&record("b", Level::Info)));
+    assert!(!expression.matches(&record("a", Level::Info)));
+    assert!(!expression.matches(&record("b", Level::Debug)));
+    assert!(e("!(tag == a && level == I)").matches(&record("a", Level::Debug)));
+}
+
+#[test]
+fn expression_realistic() {
+    let record = |tag: &str, level, pid: &str, message: &str| Record {
+        tag: tag.to_owned(),
+        level,
+        process: pid.to_owned(),
+        message: message.to_owned(),
+        ..Default::default()
+    };
+
+    // Everything except Foo below warn
+    let expression = Expression::from_str("tag!=Foo || level>=W").unwrap();
+    assert!(expression.matches(&record("Bar", Level::Debug, "1", "")));
+    assert!(!expression.matches(&record("Foo", Level::Debug, "1", "")));
+    assert!(expression.matches(&record("Foo", Level::Error, "1", "")));
+
+    let expression = Expression::from_str(
+        r#"pid > 1000 && message ~ "^Start(ing)? " && !(tag == "Activity Manager")"#,
+    )
+    .unwrap();
+    assert!(expression.matches(&record("am", Level::Info, "1234", "Starting service")));
+    assert!(!expression.matches(&record("am", Level::Info, "999", "Starting service")));
+    assert!(!expression.matches(&record("am", Level::Info, "1234", "Stopping service")));
+    assert!(!expression.matches(&record("Activity Manager", Level::Info, "1234", "Start x")));
+}
+
+#[test]
+fn expression_errors() {
+    let error = |s: &str| Expression::from_str(s).unwrap_err().to_string();
+    assert_eq!(
+        error("tag == a ||"),
+        "Invalid filter expression at position 12: unexpected end of expression"
+    );
+    assert_eq!(
+        error("tog == a"),
+        "Invalid filter expression at position 1: unknown field \"tog\""
+    );
+    assert_eq!(
+        error("(level >= X"),
+        "Invalid filter expression at position 11: invalid level \"X\""
+    );
+    assert_eq!(
+        error("(tag == a"),
+        "Invalid filter expression at position 1: unbalanced parenthesis"
+    );
+    assert_eq!(
+        error("tag < a"),
+        "Invalid filter expression at position 5: ordering is only supported for level and pid"
+    );
+    assert_eq!(
+        error("tag == a b"),
+        "Invalid filter expression at position 10: unexpected token"
+    );
+}
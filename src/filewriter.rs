@@ -18,20 +18,33 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::LogSink;
+use crate::{
+    terminal::quiet,
+    utils::{csv_columns, format_template, parse_duration, parse_size, pretty, Affix},
+    LogSink,
+};
 use clap::ArgMatches;
 use failure::{err_msg, format_err, Error};
-use futures::{Async, AsyncSink, Poll, Sink, StartSend};
-use indicatif::{ProgressBar, ProgressStyle};
+use flate2::{write::GzEncoder, Compression};
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use lazy_static::lazy_static;
 use regex::Regex;
 use rogcat::record::{Format, Record};
 use std::{
-    fs::{DirBuilder, File},
-    io::Write,
+    fs::{self, DirBuilder, File},
+    io::{self, BufWriter, Write},
     path::{Path, PathBuf},
     str::FromStr,
+    time::{Duration, Instant},
 };
-use time::{now, strftime};
+use time::{now, strftime, Tm};
+use tokio::timer::Interval;
+
+lazy_static! {
+    /// Suffix of files rotated with --rotate-interval
+    static ref TIMESTAMPED: Regex = Regex::new(r"^(\d{8}-\d{6})(?:-(\d+))?$").unwrap();
+}
 
 /// Filename format
 #[derive(Clone)]
@@ -42,67 +55,193 @@ enum FilenameFormat {
 }
 
 /// Textfile with format
-struct Textfile {
-    file: File,
+struct Textfile<W: Output = BufWriter<File>> {
+    file: W,
     format: Format,
+    affix: Affix,
+}
+
+/// Byte stream written to the output file
+trait Output: Write + Sized {
+    fn from_file(file: File) -> Self;
+    /// Finish the stream on completion of a file
+    fn finish(&mut self) -> io::Result<()>;
+    /// Pass buffered data to the file
+    fn sync(&mut self) -> io::Result<()>;
+}
+
+impl Output for BufWriter<File> {
+    fn from_file(file: File) -> BufWriter<File> {
+        BufWriter::new(file)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.flush()
+    }
 }
 
-struct FileWriter<T> {
+impl Output for GzEncoder<File> {
+    fn from_file(file: File) -> GzEncoder<File> {
+        GzEncoder::new(file, Compression::default())
+    }
+
+    /// Write the gzip footer. Each file is a standalone gzip stream
+    fn finish(&mut self) -> io::Result<()> {
+        self.try_finish()
+    }
+
+    /// Flushing the encoder degrades the compression. The file is complete
+    /// once finished
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct FileWriter<T: Writer> {
     current_filename: PathBuf,
     file_size: usize,
     filename: PathBuf,
     filename_format: FilenameFormat,
     index: usize,
     format: Format,
+    affix: Affix,
     progress: ProgressBar,
+    /// Rotate after n bytes and keep m rotated files
+    rotate: Option<(u64, usize)>,
+    /// Rotate on a schedule with --rotate-interval. Rotated files are named
+    /// by the time the file was started
+    rotate_interval: Option<Interval>,
+    /// Number of timestamped files to keep. All are kept if none
+    rotate_count: Option<usize>,
+    /// Start of the current file
+    opened: Tm,
+    bytes: u64,
+    header: bool,
     writer: Option<Box<T>>,
 }
 
 trait Writer {
-    fn with_file_format(filename: &Path, format: &Format) -> Result<Self, Error>
+    fn with_file_format(filename: &Path, format: &Format, affix: &Affix) -> Result<Self, Error>
     where
         Self: Sized;
-    fn write(&mut self, record: &Record, index: usize) -> Result<(), Error>;
+    /// Write record and return the number of bytes written to the file
+    fn write(&mut self, record: &Record, index: usize) -> Result<usize, Error>;
+    /// Write the header of the format if any and return the number of bytes written
+    fn write_header(&mut self) -> Result<usize, Error> {
+        Ok(0)
+    }
     fn flush(&mut self) -> Result<(), Error> {
         Ok(())
     }
+    /// Pass buffered records to the file without finishing it
+    fn sync(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
 }
 
+/// Number of rotated files kept if not set with --rotate-count
+const DEFAULT_ROTATE_COUNT: usize = 5;
+
 /// Crate a new log sink for given arguments
 pub fn try_from(args: &ArgMatches) -> Result<LogSink, Error> {
-    let format = args
-        .value_of("format")
-        .and_then(|f| Format::from_str(f).ok())
-        .unwrap_or(Format::Raw);
+    let format = match format_template(args)? {
+        Some(template) => template,
+        None => args
+            .value_of("format")
+            .and_then(|f| Format::from_str(f).ok())
+            .unwrap_or(Format::Raw),
+    };
+    let format = pretty(args, csv_columns(args, format))?;
+
+    let gzip = args.is_present("gzip")
+        || args
+            .value_of("output")
+            .map(|o| Path::new(o).extension() == Some("gz".as_ref()))
+            .unwrap_or(false);
 
     Ok(match format {
-        Format::Csv | Format::Json | Format::Raw => {
+        Format::Csv(_)
+        | Format::Extract
+        | Format::Json
+        | Format::JsonPretty
+        | Format::Logcat
+        | Format::Logfmt
+        | Format::Raw
+        | Format::Template(_)
+            if gzip =>
+        {
+            Box::new(FileWriter::<Textfile<GzEncoder<File>>>::from_args(
+                args, format,
+            )?) as LogSink
+        }
+        Format::Csv(_)
+        | Format::Extract
+        | Format::Json
+        | Format::JsonPretty
+        | Format::Logcat
+        | Format::Logfmt
+        | Format::Raw
+        | Format::Template(_) => {
             Box::new(FileWriter::<Textfile>::from_args(args, format)?) as LogSink
         }
+        Format::Html if gzip => return Err(err_msg("Compressed output is not supported for html")),
         Format::Html => Box::new(FileWriter::<html::Html>::from_args(args, format)?) as LogSink,
         Format::Human => panic!("Unsupported format human in output file"),
     })
 }
 
-impl Writer for Textfile {
-    fn with_file_format(filename: &Path, format: &Format) -> Result<Textfile, Error> {
+impl<W: Output> Writer for Textfile<W> {
+    fn with_file_format(
+        filename: &Path,
+        format: &Format,
+        affix: &Affix,
+    ) -> Result<Textfile<W>, Error> {
         let file = File::create(filename).map_err(|e| {
             format_err!("Failed to create output file {}: {}", filename.display(), e)
         })?;
         Ok(Textfile {
-            file,
+            file: W::from_file(file),
             format: format.clone(),
+            affix: affix.clone(),
         })
     }
 
-    fn write(&mut self, record: &Record, _index: usize) -> Result<(), Error> {
+    fn write(&mut self, record: &Record, _index: usize) -> Result<usize, Error> {
+        let line = self.affix.apply(&self.format.fmt_record(record)?);
         self.file
-            .write(self.format.fmt_record(record)?.as_bytes())
+            .write_all(line.as_bytes())
             .map_err(|e| format_err!("Failed to write: {}", e))?;
         self.file
-            .write(b"\n")
+            .write_all(b"\n")
             .map_err(|e| format_err!("Failed to write: {}", e))?;
-        Ok(())
+        Ok(line.len() + 1)
+    }
+
+    fn write_header(&mut self) -> Result<usize, Error> {
+        match self.format.header() {
+            Some(header) => {
+                writeln!(self.file, "{}", header)
+                    .map_err(|e| format_err!("Failed to write: {}", e))?;
+                Ok(header.len() + 1)
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.file
+            .finish()
+            .map_err(|e| format_err!("Failed to write: {}", e))
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        self.file
+            .sync()
+            .map_err(|e| format_err!("Failed to write: {}", e))
     }
 }
 
@@ -113,28 +252,44 @@ impl<'a, T: Writer> FileWriter<T> {
             .map(PathBuf::from)
             .ok_or_else(|| err_msg("Invalid output filename!"))?;
 
-        let records_per_file = args.value_of("records_per_file").and_then(|l| {
-            Regex::new(r"^(\d+)([kMG])$")
-                .unwrap()
-                .captures(l)
-                .and_then(|caps| {
-                    caps.get(1)
-                        .map(|m| m.as_str())
-                        .and_then(|size| usize::from_str(size).ok())
-                        .map(|size| (size, caps.get(2).map(|m| m.as_str())))
-                })
-                .and_then(|(size, suffix)| match suffix {
-                    Some("k") => Some(1_000 * size),
-                    Some("M") => Some(1_000_000 * size),
-                    Some("G") => Some(1_000_000_000 * size),
-                    _ => None,
-                })
-                .or_else(|| usize::from_str(l).ok())
-        });
+        let records_per_file = args.value_of("records_per_file").and_then(parse_size);
+
+        let rotate = match args.value_of("rotate_size") {
+            Some(size) => {
+                let size =
+                    parse_size(size).ok_or_else(|| format_err!("Invalid rotate size: {}", size))?;
+                let count = args
+                    .value_of("rotate_count")
+                    .map(|c| {
+                        usize::from_str(c).map_err(|e| format_err!("Invalid rotate count: {}", e))
+                    })
+                    .transpose()?
+                    .unwrap_or(DEFAULT_ROTATE_COUNT);
+                Some((size as u64, count))
+            }
+            None => None,
+        };
+
+        let rotate_interval = args
+            .value_of("rotate_interval")
+            .map(|i| {
+                parse_duration(i)
+                    .filter(|d| *d > Duration::from_millis(0))
+                    .ok_or_else(|| format_err!("Invalid rotate interval: {}", i))
+            })
+            .transpose()?
+            .map(|d| Interval::new(Instant::now() + d, d));
+        let rotate_count = match rotate {
+            Some((_, count)) => Some(count),
+            None => args
+                .value_of("rotate_count")
+                .map(|c| usize::from_str(c).map_err(|e| format_err!("Invalid rotate count: {}", e)))
+                .transpose()?,
+        };
 
         let overwrite = args.is_present("overwrite");
 
-        let records = records_per_file.unwrap_or(std::usize::MAX);
+        let records = records_per_file.unwrap_or(usize::MAX);
         let filename_format = match args.value_of("filename_format") {
             Some("enumerate") => FilenameFormat::Enumerate(overwrite, records),
             Some("date") => FilenameFormat::Date(overwrite, records),
@@ -158,7 +313,7 @@ impl<'a, T: Writer> FileWriter<T> {
                 )
             } else {
                 (
-                    ProgressBar::new(std::u64::MAX),
+                    ProgressBar::new(u64::MAX),
                     " • ",
                     "{spinner:.yellow} Writing {msg:.dim.bold} {pos:>7.dim} {elapsed_precise:.dim}",
                 )
@@ -168,6 +323,9 @@ impl<'a, T: Writer> FileWriter<T> {
                     .template(template)
                     .progress_chars(chars),
             );
+            if quiet() {
+                pb.set_draw_target(ProgressDrawTarget::hidden());
+            }
             pb
         };
 
@@ -178,11 +336,119 @@ impl<'a, T: Writer> FileWriter<T> {
             filename_format,
             index: 0,
             format,
+            affix: Affix::from_args(args),
             progress,
+            rotate,
+            rotate_interval,
+            rotate_count,
+            opened: now(),
+            bytes: 0,
+            header: !args.is_present("no_header"),
             writer: None,
         })
     }
 
+    /// Shift the rotated files and move the current file to `filename.1`. The
+    /// oldest file is removed if there are more than `count` rotated files.
+    fn rotate(&self, count: usize) -> Result<(), Error> {
+        let rotated = |i| PathBuf::from(format!("{}.{}", self.filename.display(), i));
+        let rename = |from: &Path, to: &Path| {
+            fs::rename(from, to).map_err(|e| {
+                format_err!(
+                    "Failed to rotate {} to {}: {}",
+                    from.display(),
+                    to.display(),
+                    e
+                )
+            })
+        };
+
+        if count == 0 {
+            return fs::remove_file(&self.filename).map_err(Into::into);
+        }
+
+        let oldest = rotated(count);
+        if oldest.exists() {
+            fs::remove_file(&oldest)?;
+        }
+        for i in (1..count).rev() {
+            let from = rotated(i);
+            if from.exists() {
+                rename(&from, &rotated(i + 1))?;
+            }
+        }
+        rename(&self.filename, &rotated(1))
+    }
+
+    /// Move the current file to `filename.YYYYMMDD-HHMMSS` named by its
+    /// start time and remove the oldest of these files beyond the rotate count
+    fn rotate_timestamped(&self) -> Result<(), Error> {
+        let stamp = strftime("%Y%m%d-%H%M%S", &self.opened)?;
+        let mut rotated = PathBuf::from(format!("{}.{}", self.filename.display(), stamp));
+        // Intervals shorter than a second
+        for n in 1.. {
+            if !rotated.exists() {
+                break;
+            }
+            rotated = PathBuf::from(format!("{}.{}-{}", self.filename.display(), stamp, n));
+        }
+        fs::rename(&self.filename, &rotated).map_err(|e| {
+            format_err!(
+                "Failed to rotate {} to {}: {}",
+                self.filename.display(),
+                rotated.display(),
+                e
+            )
+        })?;
+
+        if let Some(count) = self.rotate_count {
+            let dir = match self.filename.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            let prefix = format!(
+                "{}.",
+                self.filename
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .ok_or_else(|| err_msg("Invalid path"))?
+            );
+            // Files of the same second are ordered by their suffix
+            let mut rotated = fs::read_dir(dir)?
+                .filter_map(Result::ok)
+                .map(|e| e.path())
+                .filter_map(|p| {
+                    let name = p.file_name()?.to_str()?.strip_prefix(prefix.as_str())?;
+                    let caps = TIMESTAMPED.captures(name)?;
+                    let n = caps.get(2).and_then(|n| n.as_str().parse::<u32>().ok());
+                    Some(((caps[1].to_owned(), n.unwrap_or(0)), p.clone()))
+                })
+                .collect::<Vec<_>>();
+            rotated.sort();
+            let excess = rotated.len().saturating_sub(count);
+            for (_, file) in &rotated[..excess] {
+                fs::remove_file(file)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Rotate if the interval elapsed and records were written since the
+    /// last rotation
+    fn poll_rotate_interval(&mut self) -> Result<(), Error> {
+        let mut elapsed = false;
+        if let Some(ref mut interval) = self.rotate_interval {
+            while let Async::Ready(Some(_)) = interval.poll()? {
+                elapsed = true;
+            }
+        }
+        if elapsed && self.writer.is_some() {
+            self.flush()?;
+            self.rotate_timestamped()?;
+        }
+        Ok(())
+    }
+
     fn next_file(&self) -> Result<PathBuf, Error> {
         match self.filename_format {
             FilenameFormat::Single(overwrite) => {
@@ -279,15 +545,21 @@ impl<'a, T: Writer> FileWriter<T> {
     fn write(&mut self, record: &Record) -> Result<(), Error> {
         match self.writer {
             Some(ref mut writer) => {
-                writer.write(record, self.index)?;
+                self.bytes += writer.write(record, self.index)? as u64;
                 self.index += 1;
             }
             None => {
                 self.current_filename = self.next_file()?;
-                let mut writer = T::with_file_format(&self.current_filename, &self.format)?;
+                let mut writer =
+                    T::with_file_format(&self.current_filename, &self.format, &self.affix)?;
                 let message = format!("Writing {}", self.current_filename.display());
                 self.progress.set_message(&message);
-                writer.write(record, self.index)?;
+                self.opened = now();
+                self.bytes = 0;
+                if self.header {
+                    self.bytes += writer.write_header()? as u64;
+                }
+                self.bytes += writer.write(record, self.index)? as u64;
                 self.index += 1;
                 self.writer = Some(Box::new(writer));
             }
@@ -296,6 +568,17 @@ impl<'a, T: Writer> FileWriter<T> {
         self.file_size += 1;
         self.progress.set_position(self.file_size as u64);
 
+        if let Some((size, count)) = self.rotate {
+            if self.bytes >= size {
+                self.flush()?;
+                return if self.rotate_interval.is_some() {
+                    self.rotate_timestamped()
+                } else {
+                    self.rotate(count)
+                };
+            }
+        }
+
         match self.filename_format {
             FilenameFormat::Enumerate(_, n) | FilenameFormat::Date(_, n) => {
                 if self.file_size >= n {
@@ -322,21 +605,168 @@ impl<'a, T: Writer> FileWriter<T> {
     }
 }
 
+impl<T: Writer> Drop for FileWriter<T> {
+    /// Finish the current file e.g when interrupted by ctrl-c
+    fn drop(&mut self) {
+        if self.writer.is_some() {
+            self.flush().ok();
+        }
+    }
+}
+
 impl<T: Writer> Sink for FileWriter<T> {
     type SinkItem = Record;
     type SinkError = Error;
 
     fn start_send(&mut self, record: Record) -> StartSend<Record, Error> {
+        self.poll_rotate_interval()?;
         self.write(&record).map(|_| AsyncSink::Ready)
     }
 
     fn poll_complete(&mut self) -> Poll<(), Error> {
+        self.poll_rotate_interval()?;
+        if let Some(ref mut writer) = self.writer {
+            writer.sync()?;
+        }
         Ok(Async::Ready(()))
     }
 }
 
+#[test]
+fn rotate_size() {
+    let dir = crate::utils::TempDir::new("rotate");
+    let filename = dir.join("log.txt");
+    let args = crate::cli::args(&[
+        "rogcat",
+        "-o",
+        filename.to_str().unwrap(),
+        "--rotate-size",
+        "1k",
+        "--rotate-count",
+        "3",
+    ]);
+    let mut writer = FileWriter::<Textfile>::from_args(&args, Format::Raw).unwrap();
+    for i in 0..1000 {
+        let record = Record {
+            raw: format!("record {:04}", i),
+            ..Default::default()
+        };
+        writer.write(&record).unwrap();
+    }
+    FileWriter::flush(&mut writer).unwrap();
+
+    let files = fs::read_dir(dir.path()).unwrap().count();
+    assert!(filename.exists());
+    for i in 1..=3 {
+        assert!(dir.join(format!("log.txt.{}", i)).exists());
+    }
+    assert_eq!(files, 4);
+}
+
+#[test]
+fn rotate_interval() {
+    use tokio::runtime::Runtime;
+
+    let dir = crate::utils::TempDir::new("rotate-interval");
+    let filename = dir.join("log.txt");
+    let args = crate::cli::args(&[
+        "rogcat",
+        "-o",
+        filename.to_str().unwrap(),
+        "--rotate-interval",
+        "200ms",
+    ]);
+    let writer = FileWriter::<Textfile>::from_args(&args, Format::Raw).unwrap();
+    // A record every 50ms for 1s
+    let records = Interval::new_interval(Duration::from_millis(50))
+        .take(20)
+        .map_err(Error::from)
+        .map(|_| Record {
+            raw: "record".to_owned(),
+            ..Default::default()
+        });
+    let (_records, writer) = Runtime::new()
+        .unwrap()
+        .block_on(records.forward(writer))
+        .unwrap();
+    drop(writer);
+
+    let files = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    let rotated = files
+        .iter()
+        .filter_map(|f| f.strip_prefix("log.txt."))
+        .collect::<Vec<_>>();
+    assert!(rotated.len() >= 3, "{:?}", files);
+    assert!(rotated.iter().all(|stamp| TIMESTAMPED.is_match(stamp)));
+    let lines = files
+        .iter()
+        .map(|f| fs::read_to_string(dir.join(f)).unwrap().lines().count())
+        .sum::<usize>();
+    assert_eq!(lines, 20);
+
+    // The number of files is limited without --rotate-size, too
+    let args = crate::cli::args(&[
+        "rogcat",
+        "-o",
+        filename.to_str().unwrap(),
+        "--rotate-interval",
+        "1h",
+        "--rotate-count",
+        "2",
+    ]);
+    let writer = FileWriter::<Textfile>::from_args(&args, Format::Raw).unwrap();
+    assert_eq!(writer.rotate_count, Some(2));
+    assert!(crate::cli::cli()
+        .get_matches_from_safe(vec!["rogcat", "-o", "log.txt", "--rotate-count", "2"])
+        .is_err());
+}
+
+#[test]
+fn gzip_round_trip() {
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    let dir = crate::utils::TempDir::new("gzip");
+    let filename = dir.join("log.gz");
+    let args = crate::cli::args(&[
+        "rogcat",
+        "-o",
+        filename.to_str().unwrap(),
+        "--rotate-size",
+        "1k",
+    ]);
+    let mut writer =
+        FileWriter::<Textfile<GzEncoder<File>>>::from_args(&args, Format::Raw).unwrap();
+    for i in 0..100 {
+        let record = Record {
+            raw: format!("record {:04}", i),
+            ..Default::default()
+        };
+        writer.write(&record).unwrap();
+    }
+    FileWriter::flush(&mut writer).unwrap();
+
+    let read = |name: &str| {
+        let mut content = String::new();
+        GzDecoder::new(File::open(dir.join(name)).unwrap())
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    };
+    // 100 records of 12 bytes are rotated once after 1k uncompressed bytes
+    let content = read("log.gz.1") + &read("log.gz");
+    let expected = (0..100)
+        .map(|i| format!("record {:04}\n", i))
+        .collect::<String>();
+    assert_eq!(content, expected);
+}
+
 mod html {
     use super::Writer;
+    use crate::utils::Affix;
     use crc::{crc32, Hasher32};
     use failure::{format_err, Error};
     use handlebars::{
@@ -413,19 +843,19 @@ mod html {
     }
 
     impl Writer for Html {
-        fn with_file_format(filename: &Path, _: &Format) -> Result<Html, Error> {
+        fn with_file_format(filename: &Path, _: &Format, _: &Affix) -> Result<Html, Error> {
             Ok(Html {
                 filename: filename.to_owned(),
                 records: Vec::new(),
             })
         }
 
-        fn write(&mut self, record: &Record, index: usize) -> Result<(), Error> {
+        fn write(&mut self, record: &Record, index: usize) -> Result<usize, Error> {
             self.records.push(HtmlRecord {
                 index,
                 record: record.clone(),
             });
-            Ok(())
+            Ok(0)
         }
     }
 
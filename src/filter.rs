@@ -18,32 +18,267 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::profiles::Profile;
+use crate::{expression::Expression, profiles::Profile, terminal::status, utils::adb_argv};
 use clap::ArgMatches;
 use failure::{format_err, Error};
-use regex::Regex;
-use rogcat::record::{Level, Record};
+use futures::{Future, Stream};
+use regex::{Regex, RegexBuilder};
+use rogcat::record::{year, Level, Record};
+use std::{
+    collections::HashMap,
+    process::Command,
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use time::{strptime, Tm};
+use tokio::timer::Interval;
+use tokio_process::CommandExt;
+
+/// Interval of pid updates with --follow-app
+const FOLLOW_APP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Comparable point in time: year, month, day, hour, minute, second, nanosecond
+type TimeKey = (i32, i32, i32, i32, i32, i32, i32);
 
 /// Configured filters
 #[derive(Debug)]
 pub struct Filter {
     level: Level,
+    strict_level: bool,
+    filterspec: Option<FilterSpec>,
+    expression: Option<Expression>,
     tag: FilterGroup,
     tag_ignore_case: FilterGroup,
     message: FilterGroup,
     message_ignore_case: FilterGroup,
     regex: FilterGroup,
+    /// Invert the tag, message and regex filters with --invert
+    invert: bool,
+    since: Option<TimeKey>,
+    until: Option<TimeKey>,
+    strict_time: bool,
+    now: Tm,
+    pid: Vec<String>,
+    app: Option<AppPids>,
+    follow_app: bool,
+}
+
+/// Minimum level per tag like the logcat filterspec e.g "ActivityManager:W *:S".
+/// A level of `None` silences the tag
+#[derive(Debug, Default, PartialEq)]
+struct FilterSpec {
+    tags: HashMap<String, Option<Level>>,
+    default: Option<Option<Level>>,
+}
+
+impl FromStr for FilterSpec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<FilterSpec, Error> {
+        let mut spec = FilterSpec::default();
+        for entry in s.split_whitespace() {
+            let (tag, level) = match entry.rfind(':') {
+                Some(n) if n > 0 => (&entry[..n], &entry[n + 1..]),
+                _ => {
+                    return Err(format_err!(
+                        "Invalid filterspec entry \"{}\": expected TAG:LEVEL",
+                        entry
+                    ))
+                }
+            };
+            let level = match level {
+                "S" | "silent" => None,
+                l => match Level::from(l) {
+                    Level::None => {
+                        return Err(format_err!(
+                            "Invalid level \"{}\" in filterspec entry \"{}\"",
+                            l,
+                            entry
+                        ))
+                    }
+                    level => Some(level),
+                },
+            };
+            if tag == "*" {
+                spec.default = Some(level);
+            } else {
+                spec.tags.insert(tag.to_owned(), level);
+            }
+        }
+        if spec.tags.is_empty() && spec.default.is_none() {
+            return Err(format_err!("Invalid filterspec: \"{}\"", s));
+        }
+        Ok(spec)
+    }
+}
+
+impl FilterSpec {
+    /// Tags without an entry and without a wildcard pass
+    fn filter(&self, record: &Record, strict_level: bool) -> bool {
+        let level = match self.tags.get(&record.tag) {
+            Some(level) => level,
+            None => match self.default {
+                Some(ref level) => level,
+                None => return true,
+            },
+        };
+        match level {
+            Some(level) => record.level >= *level || (record.level == Level::None && !strict_level),
+            None => false,
+        }
+    }
+}
+
+/// Pids of an app resolved with `adb shell pidof`
+#[derive(Clone, Debug)]
+struct AppPids {
+    cmd: Vec<String>,
+    package: String,
+    pids: Arc<RwLock<Vec<String>>>,
+}
+
+impl AppPids {
+    fn new(args: &ArgMatches, package: &str) -> Result<AppPids, Error> {
+        let mut cmd = adb_argv(args)?;
+        cmd.extend(vec!["shell".into(), "pidof".into(), package.into()]);
+        let output = Command::new(&cmd[0])
+            .args(&cmd[1..])
+            .output()
+            .map_err(|e| format_err!("Failed to run {}: {}", cmd.join(" "), e))?;
+        Ok(AppPids {
+            cmd,
+            package: package.to_owned(),
+            pids: Arc::new(RwLock::new(Self::parse(&output.stdout))),
+        })
+    }
+
+    /// Parse the output of pidof. pidof prints nothing if the app is not running
+    fn parse(output: &[u8]) -> Vec<String> {
+        String::from_utf8_lossy(output)
+            .split_whitespace()
+            .map(ToOwned::to_owned)
+            .collect()
+    }
+
+    fn contains(&self, pid: &str) -> bool {
+        self.pids
+            .read()
+            .map(|pids| pids.iter().any(|p| p == pid))
+            .unwrap_or(false)
+    }
+
+    /// Periodically resolve the pids of the app. Failed updates keep the
+    /// last known pids
+    fn follow(self) -> impl Future<Item = (), Error = ()> {
+        Interval::new_interval(FOLLOW_APP_INTERVAL)
+            .map_err(|_| ())
+            .for_each(move |_| {
+                let app = self.clone();
+                Command::new(&self.cmd[0])
+                    .args(&self.cmd[1..])
+                    .output_async()
+                    .map(move |output| {
+                        let pids = Self::parse(&output.stdout);
+                        if let Ok(mut current) = app.pids.write() {
+                            if *current != pids {
+                                status(&format!("Pids of {}: {}", app.package, pids.join(" ")));
+                                *current = pids;
+                            }
+                        }
+                    })
+                    .or_else(|_| Ok(()))
+            })
+    }
+}
+
+/// Key of a timestamp. The year is inferred if missing
+fn time_key(tm: &Tm, now: &Tm) -> TimeKey {
+    (
+        year(tm, now),
+        tm.tm_mon,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec,
+        tm.tm_nsec,
+    )
+}
+
+/// Parse a --since or --until argument in logcat format e.g "03-14 10:00:00"
+fn time_arg(args: &ArgMatches, name: &str, now: &Tm) -> Result<Option<TimeKey>, Error> {
+    args.value_of(name)
+        .map(|t| {
+            strptime(t, "%m-%d %H:%M:%S.%f")
+                .or_else(|_| strptime(t, "%m-%d %H:%M:%S"))
+                .map(|tm| time_key(&tm, now))
+                .map_err(|_| format_err!("Invalid {} time: {}. Use \"MM-DD hh:mm:ss\"", name, t))
+        })
+        .transpose()
 }
 
 pub fn from_args_profile<'a>(args: &ArgMatches<'a>, profile: &Profile) -> Result<Filter, Error> {
-    let tag = profile.tag.iter().map(String::as_str);
+    let tag_ignore = args
+        .values_of("tag_ignore")
+        .map(|v| v.map(ToOwned::to_owned).collect::<Vec<String>>())
+        .unwrap_or_default()
+        .iter()
+        .chain(profile.tag_ignore.iter())
+        .map(|t| format!("!{}", t))
+        .collect::<Vec<String>>();
+    let tag = profile
+        .tag
+        .iter()
+        .chain(tag_ignore.iter())
+        .map(String::as_str);
     let tag_ignorecase = profile.tag_ignore_case.iter().map(String::as_str);
-    let message = profile.message.iter().map(String::as_str);
+    let message_ignore = args
+        .values_of("message_ignore")
+        .map(|v| v.map(|m| format!("!{}", m)).collect::<Vec<String>>())
+        .unwrap_or_default();
+    let message = profile
+        .message
+        .iter()
+        .chain(message_ignore.iter())
+        .map(String::as_str);
     let message_ignorecase = profile.message_ignore_case.iter().map(String::as_str);
     let regex = profile.regex.iter().map(String::as_str);
+    let app = args
+        .value_of("app")
+        .map(|package| AppPids::new(args, package))
+        .transpose()?;
+    let follow_app = args.is_present("follow_app");
+    if let Some(ref app) = app {
+        if !follow_app && app.pids.read().map(|p| p.is_empty()).unwrap_or(true) {
+            return Err(format_err!("App {} is not running", app.package));
+        }
+    }
+
+    let now = time::now();
+    // The level on the command line overrules the level of the profile
+    let level = match args.value_of("level") {
+        Some(level) => Level::from(level),
+        None => match profile.level {
+            Some(ref level) => match Level::from(level.as_str()) {
+                Level::None => return Err(format_err!("Invalid level in profile: {}", level)),
+                level => level,
+            },
+            None => Level::None,
+        },
+    };
     let filter = Filter {
-        level: Level::from(args.value_of("level").unwrap_or("")),
-        tag: FilterGroup::from_args(args, "tag", tag, false)?,
+        level,
+        strict_level: args.is_present("strict_level"),
+        filterspec: args
+            .value_of("filterspec")
+            .map(FilterSpec::from_str)
+            .transpose()?,
+        expression: args
+            .value_of("filter")
+            .map(Expression::from_str)
+            .transpose()?,
+        // Tags match case insensitive unless --case-sensitive is passed
+        tag: FilterGroup::from_args(args, "tag", tag, !args.is_present("case_sensitive"))?,
         tag_ignore_case: FilterGroup::from_args(args, "tag-ignore-case", tag_ignorecase, true)?,
         message: FilterGroup::from_args(args, "message", message, false)?,
         message_ignore_case: FilterGroup::from_args(
@@ -53,31 +288,107 @@ pub fn from_args_profile<'a>(args: &ArgMatches<'a>, profile: &Profile) -> Result
             true,
         )?,
         regex: FilterGroup::from_args(args, "regex_filter", regex, false)?,
+        invert: args.is_present("invert"),
+        since: time_arg(args, "since", &now)?,
+        until: time_arg(args, "until", &now)?,
+        strict_time: args.is_present("strict_time"),
+        now,
+        pid: args
+            .values_of("pid")
+            .map(|v| v.map(ToOwned::to_owned).collect())
+            .unwrap_or_default(),
+        app,
+        follow_app,
     };
 
     Ok(filter)
 }
 
 impl Filter {
+    /// Future that updates the pids of --app if --follow-app is set
+    pub fn follow_app(&self) -> Option<impl Future<Item = (), Error = ()>> {
+        match self.app {
+            Some(ref app) if self.follow_app => Some(app.clone().follow()),
+            _ => None,
+        }
+    }
+
     pub fn filter(&self, record: &Record) -> bool {
-        if record.level < self.level {
+        // Records without a level pass unless strict level filtering is enabled
+        if record.level < self.level && (record.level != Level::None || self.strict_level) {
             return false;
         }
 
-        self.message.filter(&record.message)
+        if let Some(ref spec) = self.filterspec {
+            if !spec.filter(record, self.strict_level) {
+                return false;
+            }
+        }
+
+        if let Some(ref expression) = self.expression {
+            if !expression.matches(record) {
+                return false;
+            }
+        }
+
+        if !self.pid.is_empty() || self.app.is_some() {
+            let pid = record.process.as_str();
+            if !self.pid.iter().any(|p| p == pid)
+                && !self.app.as_ref().map(|a| a.contains(pid)).unwrap_or(false)
+            {
+                return false;
+            }
+        }
+
+        if self.since.is_some() || self.until.is_some() {
+            // Records without a timestamp pass unless strict time filtering is enabled
+            match record.timestamp {
+                Some(ref t) => {
+                    let key = time_key(t, &self.now);
+                    if self.since.map(|s| key < s).unwrap_or(false)
+                        || self.until.map(|u| key > u).unwrap_or(false)
+                    {
+                        return false;
+                    }
+                }
+                None if self.strict_time => return false,
+                None => (),
+            }
+        }
+
+        let matched = self.message.filter(&record.message)
             && self.message_ignore_case.filter(&record.message)
             && self.tag.filter(&record.tag)
             && self.tag_ignore_case.filter(&record.tag)
             && (self.regex.filter(&record.process)
                 || self.regex.filter(&record.thread)
                 || self.regex.filter(&record.tag)
-                || self.regex.filter(&record.message))
+                || self.regex.filter(&record.message));
+
+        // --invert applies to the tag, message and regex filters only
+        if self.invert && !self.patterns_empty() {
+            !matched
+        } else {
+            matched
+        }
+    }
+
+    /// True if there are no tag, message or regex filters
+    fn patterns_empty(&self) -> bool {
+        [
+            &self.message,
+            &self.message_ignore_case,
+            &self.tag,
+            &self.tag_ignore_case,
+            &self.regex,
+        ]
+        .iter()
+        .all(|g| g.positive.is_empty() && g.negative.is_empty())
     }
 }
 
 #[derive(Debug)]
 struct FilterGroup {
-    ignore_case: bool,
     positive: Vec<Regex>,
     negative: Vec<Regex>,
 }
@@ -95,54 +406,255 @@ impl FilterGroup {
             .unwrap_or_default();
         filters.extend(merge);
 
+        let regex = |r: &str| {
+            RegexBuilder::new(r)
+                .case_insensitive(ignore_case)
+                .build()
+                .map_err(|e| format_err!("Invalid regex string: {}: {}", r, e))
+        };
         let mut positive = vec![];
         let mut negative = vec![];
-        for r in filters.iter().map(|f| {
-            if ignore_case {
-                f.to_lowercase()
-            } else {
-                (*f).to_string()
-            }
-        }) {
-            if let Some(r) = r.strip_prefix('!') {
-                let r =
-                    Regex::new(r).map_err(|e| format_err!("Invalid regex string: {}: {}", r, e))?;
-                negative.push(r);
+        for f in filters {
+            if let Some(r) = f.strip_prefix('!') {
+                negative.push(regex(r)?);
             } else {
-                let r = Regex::new(&r)
-                    .map_err(|e| format_err!("Invalid regex string: {}: {}", r, e))?;
-                positive.push(r);
+                positive.push(regex(f)?);
             }
         }
 
-        Ok(FilterGroup {
-            ignore_case,
-            positive,
-            negative,
-        })
+        Ok(FilterGroup { positive, negative })
     }
 
     fn filter(&self, item: &str) -> bool {
-        if !self.positive.is_empty() {
-            if self.ignore_case {
-                let item = item.to_lowercase();
-                if !self.positive.iter().any(|m| m.is_match(&item)) {
-                    return false;
-                }
-            } else if !self.positive.iter().any(|m| m.is_match(item)) {
-                return false;
-            }
+        if !self.positive.is_empty() && !self.positive.iter().any(|m| m.is_match(item)) {
+            return false;
         }
+        !self.negative.iter().any(|m| m.is_match(item))
+    }
+}
 
-        if !self.negative.is_empty() {
-            if self.ignore_case {
-                let item = item.to_lowercase();
-                return !self.negative.iter().any(|m| m.is_match(&item));
-            } else {
-                return !self.negative.iter().any(|m| m.is_match(item));
-            }
-        }
+#[cfg(test)]
+fn filter(args: &[&str]) -> Filter {
+    let mut argv = vec!["rogcat"];
+    argv.extend(args);
+    let args = crate::cli::args(&argv);
+    from_args_profile(&args, &Profile::default()).unwrap()
+}
+
+#[cfg(test)]
+fn record(level: Level) -> Record {
+    Record {
+        level,
+        ..Default::default()
+    }
+}
 
-        true
+#[cfg(test)]
+fn tagged(tag: &str) -> Record {
+    Record {
+        tag: tag.to_owned(),
+        ..Default::default()
     }
 }
+
+#[test]
+fn filter_level() {
+    let f = filter(&["--level", "W"]);
+    assert!(!f.filter(&record(Level::Debug)));
+    assert!(!f.filter(&record(Level::Info)));
+    assert!(f.filter(&record(Level::Warn)));
+    assert!(f.filter(&record(Level::Error)));
+    assert!(f.filter(&record(Level::Fatal)));
+}
+
+#[test]
+fn filter_level_none() {
+    let f = filter(&["--level", "W"]);
+    assert!(f.filter(&record(Level::None)));
+    let f = filter(&["--level", "W", "--strict-level"]);
+    assert!(!f.filter(&record(Level::None)));
+    assert!(f.filter(&record(Level::Warn)));
+}
+
+#[test]
+fn filter_tag() {
+    let f = filter(&["-t", "^Activity", "-t", "^Window"]);
+    assert!(f.filter(&tagged("ActivityManager")));
+    assert!(f.filter(&tagged("WindowManager")));
+    assert!(!f.filter(&tagged("PackageManager")));
+}
+
+#[test]
+fn filter_tag_case() {
+    let f = filter(&["-t", "^activity", "--tag-ignore", "WINDOW"]);
+    assert!(f.filter(&tagged("ActivityManager")));
+    assert!(!f.filter(&tagged("activitywindow")));
+    assert!(!f.filter(&tagged("PackageManager")));
+
+    let f = filter(&[
+        "-t",
+        "^activity",
+        "--tag-ignore",
+        "WINDOW",
+        "--case-sensitive",
+    ]);
+    assert!(!f.filter(&tagged("ActivityManager")));
+    assert!(f.filter(&tagged("activitywindow")));
+    assert!(!f.filter(&tagged("activityWINDOW")));
+
+    // Escapes keep their meaning
+    let f = filter(&["-t", "^\\W"]);
+    assert!(!f.filter(&tagged("Tag")));
+    assert!(f.filter(&tagged("[Tag]")));
+}
+
+#[test]
+fn filter_tag_ignore() {
+    let f = filter(&["-t", "Manager", "--tag-ignore", "^Window"]);
+    assert!(f.filter(&tagged("ActivityManager")));
+    assert!(!f.filter(&tagged("WindowManager")));
+    assert!(!f.filter(&tagged("vold")));
+
+    let f = filter(&["--tag-ignore", "^Window", "--tag-ignore", "^vold$"]);
+    assert!(f.filter(&tagged("ActivityManager")));
+    assert!(!f.filter(&tagged("WindowManager")));
+    assert!(!f.filter(&tagged("vold")));
+}
+
+#[test]
+fn filter_invert() {
+    let message = |level, message: &str| Record {
+        level,
+        message: message.to_owned(),
+        ..Default::default()
+    };
+    let f = filter(&["-v", "-m", "^GC_", "-m", "heartbeat"]);
+    assert!(!f.filter(&message(Level::Info, "GC_CONCURRENT freed 1K")));
+    assert!(!f.filter(&message(Level::Info, "heartbeat")));
+    assert!(f.filter(&message(Level::Info, "Start proc")));
+
+    // The level gate is not inverted
+    let f = filter(&["--invert", "-m", "heartbeat", "-l", "warn"]);
+    assert!(f.filter(&message(Level::Error, "Start proc")));
+    assert!(!f.filter(&message(Level::Info, "Start proc")));
+    assert!(!f.filter(&message(Level::Error, "heartbeat")));
+
+    // Negated patterns pass what they would drop
+    let f = filter(&["--invert", "-m", "!heartbeat"]);
+    assert!(f.filter(&message(Level::Info, "heartbeat")));
+    assert!(!f.filter(&message(Level::Info, "Start proc")));
+
+    // Nothing to invert without patterns
+    assert!(filter(&["--invert"]).filter(&message(Level::Info, "Start proc")));
+
+    let f = filter(&["--message-ignore", "heartbeat", "--message-ignore", "^GC_"]);
+    assert!(!f.filter(&message(Level::Info, "heartbeat")));
+    assert!(!f.filter(&message(Level::Info, "GC_CONCURRENT freed 1K")));
+    assert!(f.filter(&message(Level::Info, "Start proc")));
+}
+
+#[test]
+fn filter_pid() {
+    let process = |pid: &str| Record {
+        process: pid.to_owned(),
+        ..Default::default()
+    };
+    let f = filter(&["--pid", "42", "--pid", "1"]);
+    assert!(f.filter(&process("42")));
+    assert!(f.filter(&process("1")));
+    assert!(!f.filter(&process("4")));
+    assert!(!f.filter(&process("")));
+    assert!(filter(&[]).filter(&process("4")));
+}
+
+#[test]
+fn filter_profile() {
+    let args = crate::cli::args(&["rogcat"]);
+    let profile = Profile {
+        level: Some("warn".to_owned()),
+        tag_ignore: vec!["^Chatty$".to_owned()],
+        ..Default::default()
+    };
+    let f = from_args_profile(&args, &profile).unwrap();
+    assert!(!f.filter(&record(Level::Info)));
+    assert!(f.filter(&record(Level::Warn)));
+    assert!(!f.filter(&tagged("Chatty")));
+
+    let args = crate::cli::args(&["rogcat", "-l", "info"]);
+    let f = from_args_profile(&args, &profile).unwrap();
+    assert!(f.filter(&record(Level::Info)));
+}
+
+#[test]
+fn filter_invalid_regex() {
+    let args = crate::cli::args(&["rogcat", "--tag-ignore", "("]);
+    assert!(from_args_profile(&args, &Profile::default()).is_err());
+}
+
+#[test]
+fn filter_time() {
+    let timestamped = |t: &str| Record {
+        timestamp: Some(rogcat::record::Timestamp::new(
+            strptime(t, "%m-%d %H:%M:%S").unwrap(),
+        )),
+        ..Default::default()
+    };
+    let f = filter(&["--since", "03-14 10:00:00", "--until", "03-14 11:00:00"]);
+    assert!(!f.filter(&timestamped("03-14 09:59:59")));
+    assert!(f.filter(&timestamped("03-14 10:00:00")));
+    assert!(f.filter(&timestamped("03-14 11:00:00")));
+    assert!(!f.filter(&timestamped("03-15 10:30:00")));
+    assert!(f.filter(&record(Level::None)));
+
+    let f = filter(&["--since", "03-14 10:00:00", "--strict-time"]);
+    assert!(!f.filter(&record(Level::None)));
+}
+
+#[test]
+fn filter_time_new_year() {
+    let tm = |t| strptime(t, "%m-%d %H:%M:%S").unwrap();
+    let mut now = tm("01-01 00:30:00");
+    now.tm_year = 119;
+
+    let since = time_key(&tm("12-31 23:00:00"), &now);
+    let before = time_key(&tm("12-31 22:00:00"), &now);
+    let after = time_key(&tm("01-01 00:10:00"), &now);
+    assert_eq!(since.0, 118);
+    assert_eq!(after.0, 119);
+    assert!(before < since);
+    assert!(since < after);
+}
+
+#[test]
+fn filter_filterspec() {
+    let record = |tag: &str, level: Level| Record {
+        tag: tag.to_owned(),
+        level,
+        ..Default::default()
+    };
+    // The wildcard sets the default of all tags without an entry
+    let f = filter(&["--filterspec", "*:W"]);
+    assert!(!f.filter(&record("vold", Level::Info)));
+    assert!(f.filter(&record("vold", Level::Warn)));
+    assert!(f.filter(&record("vold", Level::None)));
+
+    // An explicit tag overrides the silenced wildcard
+    let f = filter(&["--filterspec", "ActivityManager:I *:S"]);
+    assert!(f.filter(&record("ActivityManager", Level::Info)));
+    assert!(!f.filter(&record("ActivityManager", Level::Debug)));
+    assert!(!f.filter(&record("vold", Level::Error)));
+
+    // Without a wildcard other tags pass
+    let f = filter(&["--filterspec", "vold:E"]);
+    assert!(!f.filter(&record("vold", Level::Warn)));
+    assert!(f.filter(&record("netd", Level::Verbose)));
+}
+
+#[test]
+fn filter_filterspec_invalid() {
+    assert!(FilterSpec::from_str("vold").is_err());
+    assert!(FilterSpec::from_str(":W").is_err());
+    assert!(FilterSpec::from_str("vold:X").is_err());
+    assert!(FilterSpec::from_str("").is_err());
+    assert!(FilterSpec::from_str("a:b:W").is_ok());
+}
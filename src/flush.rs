@@ -0,0 +1,145 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::LogSink;
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use futures::{try_ready, Async, AsyncSink, Poll, Sink, StartSend, Stream};
+use rogcat::record::Record;
+use std::{str::FromStr, time::Duration};
+use tokio::timer::Interval;
+
+/// Sink that flushes the wrapped sink at most once per interval
+pub struct Flush {
+    sink: LogSink,
+    interval: Interval,
+    /// Records were passed since the last flush
+    dirty: bool,
+    /// Flush of the wrapped sink is not yet complete
+    flushing: bool,
+}
+
+/// Wrap sink if a nonzero --flush-interval is passed. Without the sink is
+/// flushed whenever no further records are ready
+pub fn try_from(args: &ArgMatches, sink: LogSink) -> Result<LogSink, Error> {
+    let ms = args
+        .value_of("flush_interval")
+        .map(|v| u64::from_str(v).map_err(|e| format_err!("Invalid flush interval {}: {}", v, e)))
+        .transpose()?
+        .unwrap_or(0);
+    if ms == 0 {
+        Ok(sink)
+    } else {
+        Ok(Box::new(Flush::new(sink, Duration::from_millis(ms))))
+    }
+}
+
+impl Flush {
+    pub fn new(sink: LogSink, interval: Duration) -> Flush {
+        Flush {
+            sink,
+            interval: Interval::new_interval(interval),
+            dirty: false,
+            flushing: false,
+        }
+    }
+}
+
+impl Sink for Flush {
+    type SinkItem = Record;
+    type SinkError = Error;
+
+    fn start_send(&mut self, record: Record) -> StartSend<Record, Error> {
+        let result = self.sink.start_send(record)?;
+        if let AsyncSink::Ready = result {
+            self.dirty = true;
+        }
+        Ok(result)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Error> {
+        // Poll until not ready to get notified on the next tick
+        while let Async::Ready(Some(_)) = self.interval.poll()? {
+            if self.dirty {
+                self.flushing = true;
+            }
+        }
+        if self.flushing {
+            try_ready!(self.sink.poll_complete());
+            self.flushing = false;
+            self.dirty = false;
+        }
+        Ok(Async::Ready(()))
+    }
+
+    /// Final flush on completion of the stream
+    fn close(&mut self) -> Poll<(), Error> {
+        self.sink.close()
+    }
+}
+
+#[test]
+fn flush_interval() {
+    use futures::future::lazy;
+    use std::sync::{Arc, Mutex};
+
+    /// Sink whose records are visible to the test once flushed like the
+    /// reading end of a pipe
+    #[derive(Default)]
+    struct Pipe {
+        buffered: Vec<String>,
+        visible: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Sink for Pipe {
+        type SinkItem = Record;
+        type SinkError = Error;
+
+        fn start_send(&mut self, record: Record) -> StartSend<Record, Error> {
+            self.buffered.push(record.raw);
+            Ok(AsyncSink::Ready)
+        }
+
+        fn poll_complete(&mut self) -> Poll<(), Error> {
+            self.visible.lock().unwrap().append(&mut self.buffered);
+            Ok(Async::Ready(()))
+        }
+    }
+
+    let record = |raw: &str| Record {
+        raw: raw.to_owned(),
+        ..Default::default()
+    };
+
+    // Records are buffered until the interval elapses or the sink is closed
+    let pipe = Pipe::default();
+    let visible = pipe.visible.clone();
+    let mut sink = Flush::new(Box::new(pipe), Duration::from_secs(3600));
+    let (buffered, visible) = tokio::runtime::current_thread::block_on_all(lazy(move || {
+        sink.start_send(record("a"))?;
+        sink.poll_complete()?;
+        let buffered = visible.lock().unwrap().len();
+        sink.close()?;
+        Ok::<_, Error>((buffered, visible))
+    }))
+    .unwrap();
+    assert_eq!(buffered, 0);
+    assert_eq!(*visible.lock().unwrap(), vec!["a"]);
+}
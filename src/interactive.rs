@@ -0,0 +1,408 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::terminal::status;
+use clap::ArgMatches;
+use futures::{
+    sync::mpsc::{unbounded, UnboundedReceiver},
+    Async, Poll, Stream,
+};
+use regex::Regex;
+use rogcat::record::Record;
+use std::{
+    collections::VecDeque,
+    io::{self, Read},
+    thread,
+};
+
+/// Maximum number of records buffered while paused. The oldest records are
+/// dropped beyond
+const PAUSE_BUFFER: usize = 100_000;
+
+/// Stream adapter that reads keys from a terminal. Space pauses and resumes
+/// the output and '/' edits the message filter. Records are buffered while
+/// paused or editing
+pub struct Interactive<S> {
+    stream: S,
+    keys: Option<UnboundedReceiver<u8>>,
+    _terminal: Option<RawMode>,
+    paused: bool,
+    /// Message regex being typed after '/'
+    edit: Option<Vec<u8>>,
+    message: Option<Regex>,
+    buffer: VecDeque<Record>,
+    dropped: usize,
+    done: bool,
+}
+
+/// Enable the interactive mode if stdin and stdout are terminals and no
+/// --no-interactive is passed. Stdin is no terminal for keys if it is read
+/// with --input - or COMMAND -
+pub fn interactive<S: Stream<Item = Record>>(stream: S, args: &ArgMatches) -> Interactive<S> {
+    let stdin = args
+        .values_of("input")
+        .map(|mut i| i.any(|i| i == "-"))
+        .unwrap_or(false)
+        || args.value_of("COMMAND") == Some("-");
+    let enabled = !args.is_present("no_interactive")
+        && !args.is_present("output")
+        && !stdin
+        && atty::is(atty::Stream::Stdin)
+        && atty::is(atty::Stream::Stdout);
+    let terminal = if enabled { RawMode::enable() } else { None };
+    match terminal {
+        Some(terminal) => {
+            let mut interactive = Interactive::new(stream, Some(keys()));
+            interactive._terminal = Some(terminal);
+            interactive
+        }
+        None => Interactive::new(stream, None),
+    }
+}
+
+/// Read stdin byte by byte on a thread. Blocking reads do not fit into the
+/// reactor
+fn keys() -> UnboundedReceiver<u8> {
+    let (tx, rx) = unbounded();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for key in stdin.lock().bytes() {
+            match key {
+                Ok(key) if tx.unbounded_send(key).is_ok() => (),
+                _ => break,
+            }
+        }
+    });
+    rx
+}
+
+impl<S: Stream<Item = Record>> Interactive<S> {
+    fn new(stream: S, keys: Option<UnboundedReceiver<u8>>) -> Interactive<S> {
+        Interactive {
+            stream,
+            keys,
+            _terminal: None,
+            paused: false,
+            edit: None,
+            message: None,
+            buffer: VecDeque::new(),
+            dropped: 0,
+            done: false,
+        }
+    }
+
+    fn poll_keys(&mut self) {
+        loop {
+            let key = match self.keys {
+                Some(ref mut keys) => keys.poll(),
+                None => return,
+            };
+            match key {
+                Ok(Async::Ready(Some(key))) => self.key(key),
+                Ok(Async::NotReady) => return,
+                // Stdin closed. Do not wait for keys anymore
+                _ => {
+                    self.keys = None;
+                    if self.edit.take().is_some() {
+                        eprint!("\r\x1b[K");
+                    }
+                    self.paused = false;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn key(&mut self, key: u8) {
+        match self.edit.take() {
+            Some(mut edit) => match key {
+                b'\r' | b'\n' => {
+                    eprint!("\r\x1b[K");
+                    self.apply(&String::from_utf8_lossy(&edit));
+                }
+                // Escape cancels the edit
+                0x1b => eprint!("\r\x1b[K"),
+                0x08 | 0x7f => {
+                    // Remove continuation bytes of multibyte characters, too
+                    while let Some(b) = edit.pop() {
+                        if b & 0xc0 != 0x80 {
+                            break;
+                        }
+                    }
+                    self.prompt(edit);
+                }
+                k if k >= 0x20 && k != 0x7f => {
+                    edit.push(k);
+                    self.prompt(edit);
+                }
+                _ => self.edit = Some(edit),
+            },
+            None => match key {
+                b' ' if self.paused => {
+                    self.paused = false;
+                    if self.dropped > 0 {
+                        status(&format!("Resumed. Dropped {} records", self.dropped));
+                        self.dropped = 0;
+                    } else {
+                        status("Resumed");
+                    }
+                }
+                b' ' => {
+                    self.paused = true;
+                    status("Paused. Press space to resume");
+                }
+                b'/' => self.prompt(Vec::new()),
+                _ => (),
+            },
+        }
+    }
+
+    fn prompt(&mut self, edit: Vec<u8>) {
+        eprint!("\r\x1b[K/{}", String::from_utf8_lossy(&edit));
+        self.edit = Some(edit);
+    }
+
+    /// An empty regex clears the message filter. Invalid regexes keep the
+    /// current filter
+    fn apply(&mut self, regex: &str) {
+        if regex.is_empty() {
+            self.message = None;
+            status("Message filter cleared");
+            return;
+        }
+        match Regex::new(regex) {
+            Ok(r) => {
+                self.message = Some(r);
+                status(&format!("Message filter: {}", regex));
+            }
+            Err(e) => status(&format!("Invalid regex: {}: {}", regex, e)),
+        }
+    }
+
+    fn holding(&self) -> bool {
+        self.paused || self.edit.is_some()
+    }
+
+    fn matches(&self, record: &Record) -> bool {
+        self.message
+            .as_ref()
+            .map(|m| m.is_match(&record.message))
+            .unwrap_or(true)
+    }
+}
+
+impl<S: Stream<Item = Record>> Stream for Interactive<S> {
+    type Item = Record;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Record>, S::Error> {
+        self.poll_keys();
+        loop {
+            if !self.holding() {
+                // Flush the records buffered while paused
+                if let Some(record) = self.buffer.pop_front() {
+                    if self.matches(&record) {
+                        return Ok(Async::Ready(Some(record)));
+                    }
+                    continue;
+                }
+                if self.done {
+                    return Ok(Async::Ready(None));
+                }
+            } else if self.done {
+                // Wait for a key to resume
+                return Ok(Async::NotReady);
+            }
+
+            match self.stream.poll()? {
+                Async::Ready(Some(record)) => {
+                    if self.holding() {
+                        if self.buffer.len() == PAUSE_BUFFER {
+                            self.buffer.pop_front();
+                            self.dropped += 1;
+                        }
+                        self.buffer.push_back(record);
+                    } else if self.matches(&record) {
+                        return Ok(Async::Ready(Some(record)));
+                    }
+                }
+                Async::Ready(None) => self.done = true,
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+/// Terminal without line buffering and echo. Signals like ctrl-c are still
+/// handled by the terminal. The original settings are restored on drop
+struct RawMode;
+
+#[cfg(unix)]
+mod raw {
+    use lazy_static::lazy_static;
+    use std::{mem, sync::Mutex};
+
+    lazy_static! {
+        /// Settings of stdin before entering the raw mode
+        static ref ORIGINAL: Mutex<Option<libc::termios>> = Mutex::new(None);
+    }
+
+    pub fn enable() -> bool {
+        unsafe {
+            // Changing the settings from a background job stops rogcat with
+            // SIGTTOU
+            if libc::tcgetpgrp(libc::STDIN_FILENO) != libc::getpgrp() {
+                return false;
+            }
+            let mut termios: libc::termios = mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                return false;
+            }
+            let original = termios;
+            termios.c_lflag &= !(libc::ICANON | libc::ECHO);
+            termios.c_cc[libc::VMIN] = 1;
+            termios.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) != 0 {
+                return false;
+            }
+            if let Ok(mut o) = ORIGINAL.lock() {
+                *o = Some(original);
+            }
+        }
+        true
+    }
+
+    pub fn restore() {
+        if let Ok(mut original) = ORIGINAL.lock() {
+            if let Some(termios) = original.take() {
+                unsafe {
+                    libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod raw {
+    pub fn enable() -> bool {
+        false
+    }
+
+    pub fn restore() {}
+}
+
+impl RawMode {
+    fn enable() -> Option<RawMode> {
+        if raw::enable() {
+            Some(RawMode)
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        raw::restore();
+    }
+}
+
+/// Restore the terminal settings. Use before exiting without unwinding
+pub fn restore() {
+    raw::restore();
+}
+
+#[test]
+fn interactive_pause() {
+    use futures::{future::lazy, stream::iter_ok, Future};
+    lazy(|| {
+        let records = ["a", "b", "c"].iter().map(|m| Record {
+            message: (*m).to_owned(),
+            ..Default::default()
+        });
+        let (tx, rx) = unbounded();
+        let mut s = Interactive::new(iter_ok::<_, ()>(records), Some(rx));
+        tx.unbounded_send(b' ').unwrap();
+        assert_eq!(s.poll(), Ok(Async::NotReady));
+        assert_eq!(s.buffer.len(), 3);
+        tx.unbounded_send(b' ').unwrap();
+        // The keys are not closed yet
+        let messages = s.by_ref().take(3).map(|r| r.message).collect().wait();
+        assert_eq!(messages.unwrap(), vec!["a", "b", "c"]);
+        assert_eq!(s.poll(), Ok(Async::Ready(None)));
+        Ok::<_, ()>(())
+    })
+    .wait()
+    .unwrap();
+}
+
+#[test]
+fn interactive_message_filter() {
+    use futures::{future::lazy, stream::iter_ok, Future};
+    let records = |messages: &'static [&'static str]| {
+        iter_ok::<_, ()>(messages.iter().map(|m| Record {
+            message: (*m).to_owned(),
+            ..Default::default()
+        }))
+    };
+    lazy(|| {
+        let (tx, rx) = unbounded();
+        let s = Interactive::new(records(&["foo", "bar", "foobar"]), Some(rx));
+        for key in b"/fox\x7fo\n" {
+            tx.unbounded_send(*key).unwrap();
+        }
+        drop(tx);
+        let messages = s.map(|r| r.message).collect().wait().unwrap();
+        assert_eq!(messages, vec!["foo", "foobar"]);
+        // Records are held back while typing
+        let (tx, rx) = unbounded();
+        let mut s = Interactive::new(records(&["foo", "bar"]), Some(rx));
+        tx.unbounded_send(b'/').unwrap();
+        assert_eq!(s.poll(), Ok(Async::NotReady));
+        tx.unbounded_send(0x1b).unwrap();
+        drop(tx);
+        let messages = s.map(|r| r.message).collect().wait().unwrap();
+        assert_eq!(messages, vec!["foo", "bar"]);
+        Ok::<_, ()>(())
+    })
+    .wait()
+    .unwrap();
+}
+
+#[test]
+fn interactive_stdin_input() {
+    use futures::{stream::iter_ok, Future};
+    // Stdin is read as input and no keys are read from it
+    for argv in &[
+        vec!["rogcat", "--input", "-"],
+        vec!["rogcat", "-"],
+        vec!["rogcat", "--no-interactive"],
+    ] {
+        let args = crate::cli::args(argv);
+        let record = Record {
+            message: "message".to_owned(),
+            ..Default::default()
+        };
+        let s = interactive(iter_ok::<_, ()>(vec![record]), &args);
+        assert!(s.keys.is_none());
+        assert_eq!(s.collect().wait().unwrap().len(), 1);
+    }
+}
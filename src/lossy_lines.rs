@@ -1,23 +1,79 @@
 // Copyright (c) 2018 Tokio Contributors
 
+use crate::terminal::status;
 use bytes::{BufMut, BytesMut};
 use futures::{Poll, Stream};
 use std::{
     cmp,
     io::{self, BufRead},
-    usize,
 };
 use tokio::{
     codec::{Decoder, Encoder},
     io::AsyncRead,
 };
 
+/// Default maximum line length. Longer lines are split into chunks
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 1024 * 1024;
+
 /// Combinator created by the top-level `lossy_lines` method which is a stream over
 /// the lines of text on an I/O object.
 #[derive(Debug)]
 pub struct LossyLines<A> {
     io: A,
     buffer: Vec<u8>,
+    max_length: usize,
+    is_splitting: bool,
+    ansi: Option<Ansi>,
+}
+
+/// Print a warning when a line is split
+fn warn_split(max_length: usize) {
+    status(&format!(
+        "Line exceeds {} bytes and is split into chunks",
+        max_length
+    ));
+}
+
+/// Convert s replacing invalid UTF-8 with U+FFFD
+fn lossy(s: &[u8]) -> String {
+    String::from_utf8_lossy(s).into_owned()
+}
+
+/// Remove escape sequences from a line or chunk if enabled. A sequence that
+/// continues in the next chunk of a split line is removed there as well
+fn strip(ansi: &mut Option<Ansi>, line: String, split: bool) -> String {
+    match ansi {
+        Some(state) => {
+            let line = state.strip(&line);
+            if !split {
+                *state = Ansi::Text;
+            }
+            line
+        }
+        None => line,
+    }
+}
+
+/// Length of s without a trailing incomplete UTF-8 sequence. Chunks of split
+/// lines end here to keep multi-byte characters in one piece. Returns the
+/// full length if the sequence is the only content of s
+fn complete_utf8(s: &[u8]) -> usize {
+    // A character is at most four bytes. Find the start of the last one
+    for i in (s.len().saturating_sub(4)..s.len()).rev() {
+        let width = match s[i] {
+            0x80..=0xbf => continue,
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => 1,
+        };
+        return if i > 0 && i + width > s.len() {
+            i
+        } else {
+            s.len()
+        };
+    }
+    s.len()
 }
 
 /// Creates a new stream from the I/O object given representing the lines of
@@ -25,14 +81,26 @@ pub struct LossyLines<A> {
 ///
 /// This method takes an asynchronous I/O object, `a`, and returns a `Stream` of
 /// lines that the object contains. The returned stream will reach its end once
-/// `a` reaches EOF.
-pub fn lossy_lines<A>(a: A) -> LossyLines<A>
+/// `a` reaches EOF. Lines longer than `max_length` bytes are emitted in chunks
+/// of `max_length` bytes.
+pub fn lossy_lines<A>(a: A, max_length: usize) -> LossyLines<A>
 where
     A: AsyncRead + BufRead,
 {
     LossyLines {
         io: a,
         buffer: Vec::new(),
+        max_length: cmp::max(max_length, 1),
+        is_splitting: false,
+        ansi: None,
+    }
+}
+
+impl<A> LossyLines<A> {
+    /// Remove ANSI escape sequences from the lines
+    pub fn with_strip_ansi(mut self, strip: bool) -> LossyLines<A> {
+        self.ansi = if strip { Some(Ansi::Text) } else { None };
+        self
     }
 }
 
@@ -44,29 +112,66 @@ where
     type Error = ::std::io::Error;
 
     fn poll(&mut self) -> Poll<Option<String>, ::std::io::Error> {
-        let n = match self.io.read_until(b'\n', &mut self.buffer) {
-            Ok(t) => t,
-            Err(ref e) if e.kind() == ::std::io::ErrorKind::WouldBlock => {
-                return Ok(::futures::Async::NotReady);
-            }
-            Err(e) => return Err(e),
-        };
-        if n == 0 && self.buffer.is_empty() {
-            Ok(None.into())
-        } else {
-            // Strip all \r\n occurences because on Windows "adb logcat" ends lines with "\r\r\n"
-            while self.buffer.ends_with(&[b'\r']) || self.buffer.ends_with(&[b'\n']) {
-                self.buffer.pop();
+        loop {
+            let (done, used) = {
+                let available = match self.io.fill_buf() {
+                    Ok(t) => t,
+                    Err(ref e) if e.kind() == ::std::io::ErrorKind::WouldBlock => {
+                        return Ok(::futures::Async::NotReady);
+                    }
+                    Err(e) => return Err(e),
+                };
+                if available.is_empty() {
+                    if self.buffer.is_empty() {
+                        return Ok(None.into());
+                    }
+                    (true, 0)
+                } else {
+                    let limit = cmp::min(available.len(), self.max_length - self.buffer.len());
+                    match available[..limit].iter().position(|b| *b == b'\n') {
+                        Some(n) => {
+                            self.buffer.extend_from_slice(&available[..=n]);
+                            (true, n + 1)
+                        }
+                        None => {
+                            self.buffer.extend_from_slice(&available[..limit]);
+                            (self.buffer.len() >= self.max_length, limit)
+                        }
+                    }
+                }
+            };
+            self.io.consume(used);
+
+            if done {
+                let split = !self.buffer.ends_with(b"\n") && self.buffer.len() >= self.max_length;
+                if !split {
+                    self.is_splitting = false;
+                } else if !self.is_splitting {
+                    self.is_splitting = true;
+                    warn_split(self.max_length);
+                }
+                // Strip all \r\n occurences because on Windows "adb logcat" ends lines with "\r\r\n"
+                while self.buffer.ends_with(b"\r") || self.buffer.ends_with(b"\n") {
+                    self.buffer.pop();
+                }
+                // Keep the start of a character split at max_length for the
+                // next chunk
+                let rest = if split {
+                    let n = complete_utf8(&self.buffer);
+                    self.buffer.split_off(n)
+                } else {
+                    Vec::new()
+                };
+                let line = lossy(&self.buffer);
+                self.buffer = rest;
+                return Ok(Some(strip(&mut self.ansi, line, split)).into());
             }
-            let line = String::from_utf8_lossy(&self.buffer).into();
-            self.buffer.clear();
-            Ok(Some(line).into())
         }
     }
 }
 
 /// A simple `Codec` implementation that splits up data into lines.
-#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct LossyLinesCodec {
     // Stored index of the next index to examine for a `\n` character.
     // This is used to optimize searching.
@@ -76,48 +181,34 @@ pub struct LossyLinesCodec {
     // only look at `de\n` before returning.
     next_index: usize,
 
-    /// The maximum length for a given line. If `usize::MAX`, lines will be
-    /// read until a `\n` character is reached.
+    /// The maximum length for a given line. Longer lines are split into
+    /// chunks of `max_length` bytes.
     max_length: usize,
 
-    /// Are we currently discarding the remainder of a line which was over
-    /// the length limit?
-    is_discarding: bool,
+    /// Are we currently splitting a line which was over the length limit?
+    is_splitting: bool,
+
+    /// State of the escape sequence stripper if escape sequences are removed
+    ansi: Option<Ansi>,
 }
 
 impl LossyLinesCodec {
-    /// Returns a `LossyLinesCodec` for splitting up data into lines.
-    ///
-    /// # Note
-    ///
-    /// The returned `LossyLinesCodec` will not have an upper bound on the length
-    /// of a buffered line. See the documentation for [`new_with_max_length`]
-    /// for information on why this could be a potential security risk.
-    ///
-    /// [`new_with_max_length`]: #method.new_with_max_length
-    pub fn new() -> LossyLinesCodec {
+    /// Returns a `LossyLinesCodec` for splitting up data into lines of at most
+    /// `max_length` bytes. Without a limit a stream without newlines would be
+    /// buffered until memory is exhausted.
+    pub fn new_with_max_length(max_length: usize) -> LossyLinesCodec {
         LossyLinesCodec {
             next_index: 0,
-            max_length: usize::MAX,
-            is_discarding: false,
+            max_length: cmp::max(max_length, 1),
+            is_splitting: false,
+            ansi: None,
         }
     }
 
-    fn discard(&mut self, newline_offset: Option<usize>, read_to: usize, buf: &mut BytesMut) {
-        let discard_to = if let Some(offset) = newline_offset {
-            // If we found a newline, discard up to that offset and
-            // then stop discarding. On the next iteration, we'll try
-            // to read a line normally.
-            self.is_discarding = false;
-            offset + self.next_index + 1
-        } else {
-            // Otherwise, we didn't find a newline, so we'll discard
-            // everything we read. On the next iteration, we'll continue
-            // discarding up to max_len bytes unless we find a newline.
-            read_to
-        };
-        buf.advance(discard_to);
-        self.next_index = 0;
+    /// Remove ANSI escape sequences from the lines
+    pub fn with_strip_ansi(mut self, strip: bool) -> LossyLinesCodec {
+        self.ansi = if strip { Some(Ansi::Text) } else { None };
+        self
     }
 }
 
@@ -131,49 +222,41 @@ fn without_carriage_return(s: &[u8]) -> &[u8] {
 
 impl Decoder for LossyLinesCodec {
     type Item = String;
-    // TODO: in the next breaking change, this should be changed to a custom
-    // error type that indicates the "max length exceeded" condition better.
     type Error = io::Error;
 
     fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<String>, io::Error> {
-        loop {
-            // Determine how far into the buffer we'll search for a newline. If
-            // there's no max_length set, we'll read to the end of the buffer.
-            let read_to = cmp::min(self.max_length.saturating_add(1), buf.len());
-
-            let newline_offset = buf[self.next_index..read_to]
-                .iter()
-                .position(|b| *b == b'\n');
-
-            if self.is_discarding {
-                self.discard(newline_offset, read_to, buf);
-            } else {
-                return if let Some(offset) = newline_offset {
-                    // Found a line!
-                    let newline_index = offset + self.next_index;
-                    self.next_index = 0;
-                    let line = buf.split_to(newline_index + 1);
-                    let line = &line[..line.len() - 1];
-                    let line = without_carriage_return(line);
-                    let line = String::from_utf8_lossy(line);
-
-                    Ok(Some(line.to_string()))
-                } else if buf.len() > self.max_length {
-                    // Reached the maximum length without finding a
-                    // newline, return an error and start discarding on the
-                    // next call.
-                    self.is_discarding = true;
-                    Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        "line length limit exceeded",
-                    ))
-                } else {
-                    // We didn't find a line or reach the length limit, so the next
-                    // call will resume searching at the current offset.
-                    self.next_index = read_to;
-                    Ok(None)
-                };
+        // Determine how far into the buffer we'll search for a newline. The
+        // newline of a line of max_length bytes is at max_length.
+        let read_to = cmp::min(self.max_length.saturating_add(1), buf.len());
+
+        let newline_offset = buf[self.next_index..read_to]
+            .iter()
+            .position(|b| *b == b'\n');
+
+        if let Some(offset) = newline_offset {
+            // Found a line!
+            let newline_index = offset + self.next_index;
+            self.next_index = 0;
+            self.is_splitting = false;
+            let line = buf.split_to(newline_index + 1);
+            let line = &line[..line.len() - 1];
+            let line = without_carriage_return(line);
+            Ok(Some(strip(&mut self.ansi, lossy(line), false)))
+        } else if buf.len() > self.max_length {
+            // Reached the maximum length without finding a newline. Emit
+            // the chunk as a line and continue with the remainder.
+            if !self.is_splitting {
+                self.is_splitting = true;
+                warn_split(self.max_length);
             }
+            self.next_index = 0;
+            let line = buf.split_to(complete_utf8(&buf[..self.max_length]));
+            Ok(Some(strip(&mut self.ansi, lossy(&line), true)))
+        } else {
+            // We didn't find a line or reach the length limit, so the next
+            // call will resume searching at the current offset.
+            self.next_index = read_to;
+            Ok(None)
         }
     }
 
@@ -187,9 +270,8 @@ impl Decoder for LossyLinesCodec {
                 } else {
                     let line = buf.take();
                     let line = without_carriage_return(&line);
-                    let line = String::from_utf8_lossy(line);
                     self.next_index = 0;
-                    Some(line.to_string())
+                    Some(strip(&mut self.ansi, lossy(line), false))
                 }
             }
         })
@@ -207,3 +289,169 @@ impl Encoder for LossyLinesCodec {
         Ok(())
     }
 }
+
+/// States of the escape sequence stripper
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Ansi {
+    Text,
+    /// After ESC
+    Escape,
+    /// Control sequence e.g ESC [ 1 ; 31 m
+    Csi,
+    /// Operating system command terminated by BEL or ESC \
+    Osc,
+    /// ESC within an operating system command
+    OscEscape,
+}
+
+impl Ansi {
+    /// Remove ANSI escape sequences from line starting in this state.
+    /// Unterminated sequences at the end of the line are dropped
+    fn strip(&mut self, line: &str) -> String {
+        let mut stripped = String::with_capacity(line.len());
+        for c in line.chars() {
+            *self = match (*self, c) {
+                (Ansi::Text, '\x1b') => Ansi::Escape,
+                (Ansi::Text, c) => {
+                    stripped.push(c);
+                    Ansi::Text
+                }
+                (Ansi::Escape, '[') => Ansi::Csi,
+                (Ansi::Escape, ']') => Ansi::Osc,
+                (Ansi::Escape, '\x1b') => Ansi::Escape,
+                // Two character sequences e.g ESC c
+                (Ansi::Escape, '0'..='~') => Ansi::Text,
+                // A bare ESC
+                (Ansi::Escape, c) => {
+                    stripped.push(c);
+                    Ansi::Text
+                }
+                (Ansi::Csi, '@'..='~') => Ansi::Text,
+                (Ansi::Csi, _) => Ansi::Csi,
+                (Ansi::Osc, '\x07') => Ansi::Text,
+                (Ansi::Osc, '\x1b') => Ansi::OscEscape,
+                (Ansi::Osc, _) => Ansi::Osc,
+                (Ansi::OscEscape, '\\') => Ansi::Text,
+                (Ansi::OscEscape, _) => Ansi::Osc,
+            };
+        }
+        stripped
+    }
+}
+
+#[test]
+fn lossy_lines_max_length() {
+    use futures::Stream;
+    let input = io::Cursor::new(vec![b'a'; 4 * DEFAULT_MAX_LINE_LENGTH]);
+    let lines = lossy_lines(input, DEFAULT_MAX_LINE_LENGTH)
+        .wait()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(lines.len(), 4);
+    assert!(lines.iter().all(|l| l.len() == DEFAULT_MAX_LINE_LENGTH));
+}
+
+#[test]
+fn lossy_lines_codec_max_length() {
+    let mut codec = LossyLinesCodec::new_with_max_length(4);
+    let mut buf = BytesMut::from(&b"aaaaaaaaaa\nbb\ncc"[..]);
+    assert_eq!(codec.decode(&mut buf).unwrap(), Some("aaaa".to_owned()));
+    assert_eq!(codec.decode(&mut buf).unwrap(), Some("aaaa".to_owned()));
+    assert_eq!(codec.decode(&mut buf).unwrap(), Some("aa".to_owned()));
+    assert_eq!(codec.decode(&mut buf).unwrap(), Some("bb".to_owned()));
+    assert_eq!(codec.decode(&mut buf).unwrap(), None);
+    assert_eq!(codec.decode_eof(&mut buf).unwrap(), Some("cc".to_owned()));
+}
+
+#[test]
+fn lossy_lines_crlf() {
+    use futures::Stream;
+    let input = io::Cursor::new(&b"lf\ncrlf\r\nadb\r\r\nlast"[..]);
+    let lines = lossy_lines(input, DEFAULT_MAX_LINE_LENGTH)
+        .wait()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(lines, vec!["lf", "crlf", "adb", "last"]);
+
+    let mut codec = LossyLinesCodec::new_with_max_length(DEFAULT_MAX_LINE_LENGTH);
+    let mut buf = BytesMut::from(&b"lf\ncrlf\r\n"[..]);
+    assert_eq!(codec.decode(&mut buf).unwrap(), Some("lf".to_owned()));
+    assert_eq!(codec.decode(&mut buf).unwrap(), Some("crlf".to_owned()));
+}
+
+#[test]
+fn lossy_lines_multibyte_split() {
+    use futures::Stream;
+    // "😀" is four and "日本" six bytes. Each chunk boundary falls into a
+    // character
+    let input = "ab😀cd日本".as_bytes();
+    let lines = lossy_lines(io::Cursor::new(input), 4)
+        .wait()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(lines, vec!["ab", "😀", "cd", "日", "本"]);
+    assert_eq!(lines.concat(), "ab😀cd日本");
+
+    let mut codec = LossyLinesCodec::new_with_max_length(4);
+    let mut buf = BytesMut::from(&b"ab"[..]);
+    buf.extend_from_slice("😀cd日本\n".as_bytes());
+    let mut chunks = Vec::new();
+    while let Some(chunk) = codec.decode(&mut buf).unwrap() {
+        chunks.push(chunk);
+    }
+    assert_eq!(chunks, vec!["ab", "😀", "cd", "日", "本"]);
+
+    // Characters wider than the limit and invalid bytes are converted lossy
+    let lines = lossy_lines(io::Cursor::new(b"\xf0\x9f\x98\x80\xff\xfe"), 2)
+        .wait()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(
+        lines,
+        vec!["\u{fffd}", "\u{fffd}\u{fffd}", "\u{fffd}\u{fffd}"]
+    );
+}
+
+#[test]
+fn strip_ansi_sequences() {
+    let strip_ansi = |line: &str| Ansi::Text.strip(line);
+    assert_eq!(
+        strip_ansi("\x1b[1;31mE\x1b[0m Tag: \x1b[38;5;243mmessage\x1b[m"),
+        "E Tag: message"
+    );
+    assert_eq!(strip_ansi("bare \x1b escape"), "bare  escape");
+    assert_eq!(strip_ansi("trailing \x1b"), "trailing ");
+    assert_eq!(strip_ansi("split \x1b[1;3"), "split ");
+    assert_eq!(
+        strip_ansi("\x1b]0;title\x07osc \x1b]2;t\x1b\\done"),
+        "osc done"
+    );
+    assert_eq!(strip_ansi("no escapes"), "no escapes");
+}
+
+#[test]
+fn strip_ansi_split_chunks() {
+    use futures::Stream;
+    // The chunk boundaries fall into the escape sequences
+    let input = &b"ab\x1b[1;31mcd\x1b[0m\n\x1b[1mef"[..];
+    let lines = lossy_lines(io::Cursor::new(input), 4)
+        .with_strip_ansi(true)
+        .wait()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(lines.concat(), "abcdef");
+
+    let mut codec = LossyLinesCodec::new_with_max_length(4).with_strip_ansi(true);
+    let mut buf = BytesMut::from(input);
+    let mut chunks = Vec::new();
+    while let Some(chunk) = codec.decode_eof(&mut buf).unwrap() {
+        chunks.push(chunk);
+    }
+    assert_eq!(chunks.concat(), "abcdef");
+
+    // An unterminated sequence does not continue on the next line
+    let mut codec = LossyLinesCodec::new_with_max_length(16).with_strip_ansi(true);
+    let mut buf = BytesMut::from(&b"a\x1b[1\nb\n"[..]);
+    assert_eq!(codec.decode(&mut buf).unwrap(), Some("a".to_owned()));
+    assert_eq!(codec.decode(&mut buf).unwrap(), Some("b".to_owned()));
+}
@@ -18,53 +18,122 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use failure::Error;
-use futures::{sync::oneshot, Future, Sink, Stream};
-use rogcat::{parser, record::Record};
-use std::{process::exit, str::FromStr};
+use crate::{filter::Filter, processes::ProcessNames};
+use clap::ArgMatches;
+use failure::{err_msg, format_err, Error};
+use futures::{future::Either, Future, Sink, Stream};
+use regex::Regex;
+use rogcat::{
+    parser::{InputFormat, Parser},
+    record::Record,
+};
+use std::{
+    process::exit,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 use tokio::runtime::Runtime;
 use tokio_signal::ctrl_c;
 use url::Url;
 
+mod adapters;
 mod cli;
+mod exec;
+mod expression;
 mod filewriter;
 mod filter;
+mod flush;
+mod interactive;
 mod lossy_lines;
+mod processes;
 mod profiles;
 mod reader;
 mod subcommands;
+mod syslog;
 mod terminal;
 mod utils;
+mod window;
 
 const DEFAULT_BUFFER: [&str; 4] = ["main", "events", "crash", "kernel"];
+const BUFFERS: [&str; 9] = [
+    "all", "crash", "events", "kernel", "main", "radio", "security", "stats", "system",
+];
 
+// Records are the common item. Boxing them would allocate once more per line
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone)]
 pub enum StreamData {
     Record(Record),
     Line(String),
+    /// Line of a logcat merged with others e.g with --all-devices. The
+    /// record is labeled with the origin after parsing
+    Labeled(reader::Origin, String),
+    /// End of an input file. The format detection restarts with the next line
+    EndOfFile,
 }
 
 type LogStream = Box<dyn Stream<Item = StreamData, Error = Error> + Send>;
 type LogSink = Box<dyn Sink<SinkItem = Record, SinkError = Error> + Send>;
+type RecordStream = Box<dyn Stream<Item = Record, Error = Error> + Send>;
+
+/// Parse line. Invalid lines are skipped with a status message
+fn parse_line(parser: &mut Parser, line: &str) -> Option<Record> {
+    match parser.try_parse(line) {
+        Ok(r) => Some(r),
+        Err(e) => {
+            terminal::status(&format!("Skipping invalid line: {}", e));
+            None
+        }
+    }
+}
 
 fn run() -> Result<(), Error> {
     let args = cli::cli().get_matches();
     utils::config_init();
+    terminal::set_quiet(args.is_present("quiet"));
     subcommands::run(&args);
 
+    // Disconnected when dropped at the end of run
+    let _connection = reader::connect(&args)?;
+
+    // logcat handles --tail itself. Other sources are tailed with a ring buffer
+    let logcat =
+        !args.is_present("input") && !args.is_present("COMMAND") && !args.is_present("listen");
+    let tail = if logcat {
+        None
+    } else {
+        args.value_of("tail")
+            .map(|v| usize::from_str(v).expect("Invalid tail argument"))
+    };
+
+    if args.is_present("clear") {
+        match reader::clear(&args) {
+            Ok(_) if args.is_present("clear_only") => return Ok(()),
+            Err(e) if args.is_present("clear_only") => return Err(e),
+            Err(e) => terminal::status(&format!("Failed to clear buffers: {}", e)),
+            Ok(_) => (),
+        }
+    }
+
     let source = {
-        if args.is_present("input") {
+        if let Some(addr) = args.value_of("listen") {
+            reader::listen(addr, args.is_present("strip_ansi"))?
+        } else if args.is_present("input") {
             reader::files(&args)?
         } else {
             match args.value_of("COMMAND") {
                 Some(c) => {
                     if c == "-" {
-                        reader::stdin()
+                        reader::stdin(args.is_present("strip_ansi"))
                     } else if let Ok(url) = Url::parse(c) {
                         match url.scheme() {
                             #[cfg(target_os = "linux")]
                             "can" => reader::can(url.host_str().expect("Invalid can device"))?,
-                            "tcp" => reader::tcp(&url)?,
+                            "tcp" => reader::tcp(&url, args.is_present("strip_ansi"))?,
                             "serial" => reader::serial(&args),
                             _ => reader::process(&args)?,
                         }
@@ -72,6 +141,7 @@ fn run() -> Result<(), Error> {
                         reader::process(&args)?
                     }
                 }
+                None if args.is_present("all_devices") => reader::all_devices(&args)?,
                 None => reader::logcat(&args)?,
             }
         }
@@ -80,56 +150,604 @@ fn run() -> Result<(), Error> {
     let profile = profiles::from_args(&args)?;
     let sink = if args.is_present("output") {
         filewriter::try_from(&args)?
+    } else if args.is_present("window") && atty::is(atty::Stream::Stdout) {
+        window::try_from(&args, &profile)?
     } else {
         terminal::try_from(&args, &profile)?
     };
-
-    // Stop process after n records if argument head is passed
-    let mut head = args
-        .value_of("head")
-        .map(|v| usize::from_str(v).expect("Invalid head arguement"));
+    let sink = flush::try_from(&args, sink)?;
+    let sink = exec::try_from(&args, sink)?;
+    let sink = syslog::try_from(&args, sink)?;
 
     let filter = filter::from_args_profile(&args, &profile)?;
-    let mut parser = parser::Parser::default();
-
     let mut runtime = Runtime::new()?;
+    if let Some(follow) = filter.follow_app() {
+        runtime.spawn(follow);
+    }
+    let process_names = processes::try_from(&args)?;
+    if let Some(ref names) = process_names {
+        runtime.spawn(names.clone().follow());
+    }
 
-    let f = source
-        .map(move |a| match a {
-            StreamData::Line(l) => parser.parse(&l),
-            StreamData::Record(r) => r,
-        })
-        .filter(move |r| filter.filter(r))
-        .take_while(move |_| {
-            Ok(match head {
-                Some(0) => false,
-                Some(n) => {
-                    head = Some(n - 1);
-                    true
-                }
-                None => true,
-            })
-        })
+    let f = records(&args, source, filter, process_names, tail)?;
+    let matched = Arc::new(AtomicBool::new(false));
+    // Numbered last to count what is written
+    let f = interactive::interactive(f, &args);
+    let f = adapters::numbers(f, args.is_present("line_numbers"));
+    let f = adapters::matched(f, matched.clone())
         .forward(sink)
-        .map(|_| exit(0))
-        .map_err(|e| eprintln!("{}", e));
-    let mut f = Some(oneshot::spawn(f, &runtime.executor()));
+        .map(|_| ());
 
     // Cancel stream processing on ctrl-c
-    runtime.block_on(ctrl_c().flatten_stream().take(1).for_each(move |()| {
-        f.take();
-        Ok(())
-    }))?;
+    let ctrl_c = ctrl_c()
+        .flatten_stream()
+        .into_future()
+        .map(|(_, signals)| signals);
 
-    Ok(())
+    // The source and sink are dropped after block_on returns. This kills
+    // child processes and flushes buffered output even on errors.
+    match runtime.block_on(f.select2(ctrl_c)) {
+        Ok(Either::A(_))
+            if args.is_present("exit_on_empty") && !matched.load(Ordering::Relaxed) =>
+        {
+            Err(err_msg("No records matched"))
+        }
+        Ok(Either::A(_)) => Ok(()),
+        Ok(Either::B((signals, f))) => {
+            // Exit immediately on a second ctrl-c while cleaning up
+            runtime.spawn(
+                signals
+                    .take(1)
+                    .for_each(|_| -> Result<(), std::io::Error> {
+                        interactive::restore();
+                        exit(1)
+                    })
+                    .map_err(|_| ()),
+            );
+            drop(f);
+            Ok(())
+        }
+        Err(Either::A((e, _))) => Err(e),
+        Err(Either::B(((e, _), _))) => Err(e.into()),
+    }
+}
+
+/// Parse, filter and decorate the records of source as selected by args
+fn records(
+    args: &ArgMatches,
+    source: LogStream,
+    filter: Filter,
+    process_names: Option<ProcessNames>,
+    tail: Option<usize>,
+) -> Result<RecordStream, Error> {
+    // Stop process after n records passed all filters if argument head is passed
+    let head = args
+        .value_of("head")
+        .map(|v| u64::from_str(v).map_err(|e| format_err!("Invalid head {}: {}", v, e)))
+        .transpose()?
+        .unwrap_or(u64::MAX);
+    // Drop the first n records passed all filters before counting
+    let skip_first = args
+        .value_of("skip_first")
+        .map(|v| u64::from_str(v).map_err(|e| format_err!("Invalid skip first {}: {}", v, e)))
+        .transpose()?
+        .unwrap_or(0);
+
+    let max_message_len = args
+        .value_of("max_message_len")
+        .map(|v| {
+            usize::from_str(v).map_err(|e| format_err!("Invalid max message len {}: {}", v, e))
+        })
+        .transpose()?;
+    // Ratio of non-printable characters above which records are dropped
+    let drop_binary = if args.is_present("drop_binary") {
+        let threshold = args
+            .value_of("drop_binary")
+            .map(|v| {
+                f64::from_str(v).map_err(|e| format_err!("Invalid drop binary ratio {}: {}", v, e))
+            })
+            .transpose()?
+            .unwrap_or(0.3);
+        if !(0.0..=1.0).contains(&threshold) {
+            return Err(format_err!("Invalid drop binary ratio: {}", threshold));
+        }
+        Some(threshold)
+    } else {
+        None
+    };
+    let mut parser = match args.value_of("input_format") {
+        Some("auto") | None => Parser::default(),
+        Some(f) => Parser::with_format(&InputFormat::from_str(f).map_err(err_msg)?),
+    };
+
+    // End the input if the source is silent for the timeout
+    let idle_timeout = args
+        .value_of("idle_timeout")
+        .map(|v| u64::from_str(v).map_err(|e| format_err!("Invalid idle timeout {}: {}", v, e)))
+        .transpose()?
+        .map(Duration::from_secs);
+    // End the input after the duration. The records read so far are written
+    // and the sink is closed like at the end of the input
+    let duration = args
+        .value_of("duration")
+        .map(|v| utils::parse_duration(v).ok_or_else(|| format_err!("Invalid duration: {}", v)))
+        .transpose()?;
+    let stats_interval = args
+        .value_of("stats_interval")
+        .map(|v| u64::from_str(v).map_err(|e| format_err!("Invalid stats interval {}: {}", v, e)))
+        .transpose()?
+        .filter(|secs| *secs > 0)
+        .map(Duration::from_secs);
+    let stats_inline = args.is_present("stats_inline");
+    let show_dropped = args.is_present("show_dropped");
+    // Chatty notices rarely pass the filters and are counted for --stats before
+    let dropped_lines = adapters::DroppedLines::default();
+    let counted = dropped_lines.clone();
+    let replay = match args.value_of("replay_speed").map(f64::from_str) {
+        Some(Ok(0.0)) => None,
+        Some(Ok(speed)) if speed > 0.0 && speed.is_finite() => {
+            let max_gap = args
+                .value_of("replay_max_gap")
+                .map(|g| {
+                    utils::parse_duration(g)
+                        .ok_or_else(|| format_err!("Invalid replay max gap: {}", g))
+                })
+                .transpose()?
+                .unwrap_or(adapters::REPLAY_MAX_GAP);
+            Some((speed, max_gap))
+        }
+        Some(_) => {
+            return Err(format_err!(
+                "Invalid replay speed: {}",
+                args.value_of("replay_speed").unwrap_or_default()
+            ))
+        }
+        None => None,
+    };
+    let baseline = args
+        .value_of("diff")
+        .map(|path| adapters::Baseline::load(path, args.is_present("diff_exact")))
+        .transpose()?;
+    // Seed from the clock unless a seed is passed for reproducible samples
+    let sample = args
+        .value_of("sample")
+        .map(|v| {
+            let p = f64::from_str(v)
+                .map_err(|e| format_err!("Invalid sample probability {}: {}", v, e))?;
+            if !(0.0..=1.0).contains(&p) {
+                return Err(format_err!("Invalid sample probability: {}", p));
+            }
+            let seed = args
+                .value_of("seed")
+                .map(|v| u64::from_str(v).map_err(|e| format_err!("Invalid seed {}: {}", v, e)))
+                .transpose()?
+                .unwrap_or_else(|| {
+                    SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map(|d| d.as_nanos() as u64)
+                        .unwrap_or_default()
+                });
+            Ok((p, adapters::Rng::new(seed)))
+        })
+        .transpose()?;
+    let source = adapters::deadline(source, duration);
+    let source = adapters::idle(source, idle_timeout);
+    let source = adapters::tee_raw(source, args.value_of("tee_raw"))?;
+    // Stamped when read from the source and not when the sink is ready
+    let start = if args.is_present("timestamp_monotonic") {
+        Some(Instant::now())
+    } else {
+        None
+    };
+    let source = adapters::received(source, start);
+    let f: RecordStream = if args.is_present("raw") {
+        // Skip parsing and filtering and pass the lines to the sink as is
+        let f =
+            source.filter_map(|(received, a)| adapters::raw(a).map(|r| Record { received, ..r }));
+        Box::new(adapters::tail(f, tail).take(head))
+    } else {
+        let f = source
+            .filter_map(move |(received, a)| {
+                let record = match a {
+                    StreamData::Line(l) => parse_line(&mut parser, &l),
+                    StreamData::Labeled(origin, l) => {
+                        parse_line(&mut parser, &l).map(|r| origin.label(r))
+                    }
+                    StreamData::Record(r) => Some(r),
+                    StreamData::EndOfFile => {
+                        parser.reset();
+                        None
+                    }
+                };
+                record.map(|r| Record { received, ..r })
+            })
+            .filter(move |r| {
+                counted.count(r);
+                // Notices of the device are shown regardless of the filters
+                (show_dropped && adapters::dropped(r).is_some()) || filter.filter(r)
+            })
+            .filter(move |r| match baseline {
+                Some(ref baseline) => !baseline.contains(r),
+                None => true,
+            })
+            .filter(move |r| match drop_binary {
+                Some(threshold) => !adapters::binary(&r.message, threshold),
+                None => true,
+            });
+        let f = adapters::coalesce(
+            f,
+            args.is_present("coalesce_traces") || args.is_present("crashes"),
+        );
+        let grep = args
+            .value_of("grep")
+            .map(|g| Regex::new(g).map_err(|e| format_err!("Invalid grep regex: {}: {}", g, e)))
+            .transpose()?;
+        let context = args
+            .value_of("context")
+            .map(|v| usize::from_str(v).map_err(|e| format_err!("Invalid context {}: {}", v, e)))
+            .transpose()?
+            .unwrap_or(0);
+        let f = adapters::grep(f, grep, context);
+        let f = adapters::dedup(f, args.is_present("dedup"), args.is_present("dedup_strict"));
+        let trigger = args
+            .value_of("trigger")
+            .map(|t| Regex::new(t).map_err(|e| format_err!("Invalid trigger regex: {}: {}", t, e)))
+            .transpose()?;
+        let trigger_tail = args
+            .value_of("trigger_tail")
+            .map(|v| {
+                usize::from_str(v).map_err(|e| format_err!("Invalid trigger tail {}: {}", v, e))
+            })
+            .transpose()?
+            .unwrap_or(0);
+        let f = adapters::trigger(f, trigger, trigger_tail);
+        let output = args.value_of("format") == Some("extract");
+        if output && !args.is_present("extract") {
+            return Err(err_msg(
+                "Format extract requires a regex passed with --extract",
+            ));
+        }
+        let extract = args
+            .value_of("extract")
+            .map(|e| {
+                let regex = Regex::new(e)
+                    .map_err(|err| format_err!("Invalid extract regex: {}: {}", e, err))?;
+                adapters::Extract::new(regex, args.value_of("format_template"), output)
+            })
+            .transpose()?;
+        let f = f.filter_map(move |r| match extract {
+            Some(ref extract) => extract.extract(r),
+            None => Some(r),
+        });
+        // Sampled are the records that pass all filters
+        let f = adapters::sample(f, sample);
+        let f = adapters::tail(f.skip(skip_first), tail).take(head);
+        // --timestamp-monotonic wins over --hostclock
+        let hostclock = args.is_present("hostclock") && !args.is_present("timestamp_monotonic");
+        let utc = if args.is_present("utc") {
+            Some(utils::tz_offset(args)?)
+        } else {
+            None
+        };
+        // Years of timestamps are inferred like for --since
+        let now = time::now();
+        let f = adapters::stats(
+            f,
+            args.is_present("stats"),
+            stats_interval,
+            stats_inline,
+            dropped_lines,
+        )
+        .map(move |r| match max_message_len {
+            Some(max) => adapters::truncate(r, max),
+            None => r,
+        })
+        .map(move |r| if hostclock { adapters::hostclock(r) } else { r })
+        .map(move |r| {
+            if show_dropped {
+                adapters::show_dropped(r)
+            } else {
+                r
+            }
+        })
+        .map(move |r| match utc {
+            Some(offset) => adapters::utc(r, offset, &now),
+            None => r,
+        })
+        .map(move |r| match process_names {
+            Some(ref names) => names.resolve(r),
+            None => r,
+        });
+        Box::new(f)
+    };
+    let f = adapters::replay(f, replay);
+    let f = adapters::rate(f, args.is_present("rate"));
+    Ok(Box::new(f))
 }
 
 fn main() {
     match run() {
         Err(e) => {
             eprintln!("{}", e);
-            exit(1)
+            exit(reader::exit_code(&e))
         }
         Ok(_) => exit(0),
     }
 }
+
+/// Records of the pipeline for argv over the lines
+#[cfg(test)]
+fn pipeline(argv: &[&str], lines: &[String]) -> Vec<Record> {
+    use futures::stream::iter_ok;
+    let lines = lines
+        .iter()
+        .cloned()
+        .map(StreamData::Line)
+        .collect::<Vec<_>>();
+    pipeline_source(argv, |_| Box::new(iter_ok(lines)))
+}
+
+/// Records of the pipeline for argv over the source created from the args
+#[cfg(test)]
+fn pipeline_source(argv: &[&str], source: impl FnOnce(&ArgMatches) -> LogStream) -> Vec<Record> {
+    let args = cli::args(argv);
+    let filter = filter::from_args_profile(&args, &profiles::Profile::default()).unwrap();
+    let source = source(&args);
+    Runtime::new()
+        .unwrap()
+        .block_on(
+            records(&args, source, filter, None, None)
+                .unwrap()
+                .collect(),
+        )
+        .unwrap()
+}
+
+/// Error of the pipeline for argv
+#[cfg(test)]
+fn pipeline_error(argv: &[&str]) -> String {
+    use futures::stream::empty;
+    let args = cli::args(argv);
+    let filter = filter::from_args_profile(&args, &profiles::Profile::default()).unwrap();
+    match records(&args, Box::new(empty()), filter, None, None) {
+        Ok(_) => panic!("Pipeline of {:?} is valid", argv),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn records_head() {
+    let lines = (0..100)
+        .map(|i| {
+            let tag = if i % 10 == 0 { "Keep" } else { "Drop" };
+            format!("03-01 02:19:45.207     1     2 I {}: {}", tag, i)
+        })
+        .collect::<Vec<_>>();
+    let messages = |argv: &[&str]| {
+        pipeline(argv, &lines)
+            .into_iter()
+            .map(|r| r.message)
+            .collect::<Vec<_>>()
+    };
+    // Rejected records are not counted for --head
+    assert_eq!(
+        messages(&["rogcat", "-t", "Keep", "--head", "3"]),
+        vec!["0", "10", "20"]
+    );
+    // Skipped are the first records that pass the filters
+    assert_eq!(
+        messages(&["rogcat", "-t", "Keep", "--skip-first", "2", "--head", "3"]),
+        vec!["20", "30", "40"]
+    );
+    assert_eq!(
+        messages(&["rogcat", "-t", "Keep", "--skip-first", "8", "--head", "3"]),
+        vec!["80", "90"]
+    );
+    assert!(messages(&["rogcat", "-t", "Keep", "--skip-first", "10"]).is_empty());
+}
+
+#[test]
+fn records_head_files() {
+    use std::fs::write;
+
+    let dir = utils::TempDir::new("head");
+    write(dir.join("log.1"), "one\ntwo\n").unwrap();
+    write(dir.join("log.2"), "three\nfour\n").unwrap();
+    let (one, two) = (dir.join("log.1"), dir.join("log.2"));
+    let argv = [
+        "rogcat",
+        "-i",
+        one.to_str().unwrap(),
+        "-i",
+        two.to_str().unwrap(),
+        "--head",
+        "3",
+    ];
+    // The end of the first file is no record
+    let raw = pipeline_source(&argv, |args| reader::files(args).unwrap())
+        .into_iter()
+        .map(|r| r.raw)
+        .collect::<Vec<_>>();
+    assert_eq!(raw, vec!["one", "two", "three"]);
+}
+
+#[test]
+fn records_tee_raw_strip_ansi() {
+    use std::fs::{read_to_string, write};
+
+    let dir = utils::TempDir::new("tee-strip");
+    write(dir.join("in"), "\x1b[31mred\x1b[0m\nplain\n").unwrap();
+    let (input, tee) = (dir.join("in"), dir.join("tee"));
+    let argv = [
+        "rogcat",
+        "-i",
+        input.to_str().unwrap(),
+        "--strip-ansi",
+        "--tee-raw",
+        tee.to_str().unwrap(),
+        "-m",
+        "nothing",
+    ];
+    // Stripped in the reader and not just in the output
+    assert!(pipeline_source(&argv, |args| reader::files(args).unwrap()).is_empty());
+    assert_eq!(read_to_string(tee).unwrap(), "red\nplain\n");
+}
+
+#[test]
+fn records_show_dropped_filtered() {
+    let lines = vec![
+        "01-01 12:00:00.000  1234  1234 I chatty  : uid=10054(u0_a54) com.example expire 2 lines"
+            .to_owned(),
+        "01-01 12:00:00.000  1234  1234 I Other   : other".to_owned(),
+        "01-01 12:00:00.000  1234  1234 I MyApp   : message".to_owned(),
+    ];
+    let messages = |argv: &[&str]| {
+        pipeline(argv, &lines)
+            .into_iter()
+            .map(|r| r.message)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(messages(&["rogcat", "-t", "MyApp"]), vec!["message"]);
+    // The notice is no record of MyApp but passes the tag filter
+    assert_eq!(
+        messages(&["rogcat", "-t", "MyApp", "--show-dropped"]),
+        vec![
+            "Device dropped 2 expired lines of com.example (uid 10054)",
+            "message"
+        ]
+    );
+}
+
+#[test]
+fn records_sample() {
+    let lines = (0..1000)
+        .map(|i| {
+            let tag = if i % 2 == 0 { "Keep" } else { "Drop" };
+            format!("03-01 02:19:45.207     1     2 I {}: {}", tag, i)
+        })
+        .collect::<Vec<_>>();
+    // The probability applies to the records that pass the grep filter
+    let records = pipeline(
+        &["rogcat", "--grep", "Keep", "--sample", "0.5", "--seed", "1"],
+        &lines,
+    );
+    assert!(records.iter().all(|r| r.tag == "Keep"));
+    assert!(records.len() > 200 && records.len() < 300);
+    assert_eq!(
+        pipeline(&["rogcat", "--grep", "Keep", "--sample", "1"], &lines).len(),
+        500
+    );
+
+    assert!(pipeline_error(&["rogcat", "--sample", "half"])
+        .starts_with("Invalid sample probability half"));
+    assert_eq!(
+        pipeline_error(&["rogcat", "--sample", "2"]),
+        "Invalid sample probability: 2"
+    );
+    assert!(
+        pipeline_error(&["rogcat", "--sample", "0.5", "--seed", "x"]).starts_with("Invalid seed x")
+    );
+}
+
+#[test]
+fn records_invalid_arguments() {
+    assert!(pipeline_error(&["rogcat", "--idle-timeout", "soon"])
+        .starts_with("Invalid idle timeout soon"));
+    assert_eq!(
+        pipeline_error(&["rogcat", "--duration", "1w"]),
+        "Invalid duration: 1w"
+    );
+    assert!(pipeline_error(&["rogcat", "--max-message-len", "long"])
+        .starts_with("Invalid max message len long"));
+    assert!(pipeline_error(&["rogcat", "--drop-binary=some"])
+        .starts_with("Invalid drop binary ratio some"));
+    assert_eq!(
+        pipeline_error(&["rogcat", "--drop-binary=2"]),
+        "Invalid drop binary ratio: 2"
+    );
+    assert!(pipeline_error(&["rogcat", "--head", "ten"]).starts_with("Invalid head ten"));
+    assert!(
+        pipeline_error(&["rogcat", "--skip-first", "ten"]).starts_with("Invalid skip first ten")
+    );
+    assert!(
+        pipeline_error(&["rogcat", "--grep", "a", "--context", "ten"])
+            .starts_with("Invalid context ten")
+    );
+    assert!(
+        pipeline_error(&["rogcat", "--trigger", "a", "--trigger-tail", "ten"])
+            .starts_with("Invalid trigger tail ten")
+    );
+}
+
+#[test]
+fn records_raw_unparsed() {
+    use rogcat::record::Level;
+
+    let line = "01-01 00:00:00.000  1  1 I tag: message";
+    let lines = vec![line.to_owned(); 10_000];
+    let records = pipeline(&["rogcat", "--raw"], &lines);
+    assert_eq!(records.len(), 10_000);
+    // Parseable lines are not parsed
+    assert!(records.iter().all(|r| r.raw == line
+        && r.message.is_empty()
+        && r.tag.is_empty()
+        && r.level == Level::None
+        && r.timestamp.is_none()));
+    assert_eq!(
+        pipeline(&["rogcat", "--raw", "--head", "3"], &lines).len(),
+        3
+    );
+}
+
+#[test]
+fn records_labeled_input_format() {
+    use futures::stream::iter_ok;
+    let origin = reader::Origin {
+        device: Some("emulator-5554".to_owned()),
+        buffer: Some("main".to_owned()),
+    };
+    let lines = vec![
+        StreamData::Labeled(
+            origin.clone(),
+            "03-01 02:19:45.207     1     2 I A: threadtime".to_owned(),
+        ),
+        StreamData::Labeled(origin, "I/B(    1): brief".to_owned()),
+    ];
+    // The lines of merged logcats are parsed like all other lines. The brief
+    // line is no threadtime record
+    let records = pipeline_source(&["rogcat", "--input-format", "threadtime"], |_| {
+        Box::new(iter_ok(lines))
+    });
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].message, "threadtime");
+    assert_eq!(records[1].tag, "");
+    assert_eq!(records[1].message, "I/B(    1): brief");
+    assert_eq!(records[0].device.as_deref(), Some("emulator-5554"));
+    assert_eq!(records[0].buffer.as_deref(), Some("main"));
+    assert_eq!(
+        records[0].raw,
+        "[emulator-5554] [main] 03-01 02:19:45.207     1     2 I A: threadtime"
+    );
+}
+
+#[test]
+fn records_timestamp_monotonic() {
+    let line = "03-01 02:19:45.207     1     2 I A: message".to_owned();
+    let records = pipeline(
+        &["rogcat", "--timestamp-monotonic"],
+        std::slice::from_ref(&line),
+    );
+    assert_eq!(records[0].raw, line);
+    assert!(records[0].received.is_some());
+    // The device timestamp is kept
+    assert!(records[0].timestamp.is_some());
+    let records = pipeline(
+        &["rogcat", "--timestamp-monotonic", "--raw"],
+        std::slice::from_ref(&line),
+    );
+    assert!(records[0].received.is_some());
+    assert!(records[0].timestamp.is_none());
+    assert!(pipeline(&["rogcat"], std::slice::from_ref(&line))[0]
+        .received
+        .is_none());
+}
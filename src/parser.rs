@@ -18,7 +18,7 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::record::{Level, Record, Timestamp};
+use crate::record::{CsvRow, JsonRow, Level, Record, Timestamp};
 use csv::ReaderBuilder;
 use failure::Fail;
 use nom::{
@@ -29,15 +29,68 @@ use nom::{
 use serde_json::from_str;
 use std::{
     convert::Into,
+    fmt::{self, Display, Formatter},
     io::{Cursor, Read},
+    iter::repeat,
+    str::FromStr,
 };
 
-use time::Tm;
+use time::{Timespec, Tm};
 
-#[derive(Fail, Debug)]
-#[fail(display = "{}", _0)]
+#[derive(Debug)]
 pub struct ParserError(String);
 
+/// Logcat output formats that can be selected explicitly
+#[derive(Clone, Debug, PartialEq)]
+pub enum InputFormat {
+    Brief,
+    /// Threadtime with seconds since the epoch (`-v epoch`)
+    Epoch,
+    /// Json as written with `--format json`. Invalid lines are rejected
+    Json,
+    Tag,
+    Threadtime,
+    Time,
+}
+
+impl InputFormat {
+    /// Possible values including "auto"
+    pub fn values() -> &'static [&'static str] {
+        &[
+            "auto",
+            "brief",
+            "epoch",
+            "json",
+            "tag",
+            "threadtime",
+            "time",
+        ]
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "brief" => Ok(InputFormat::Brief),
+            "epoch" => Ok(InputFormat::Epoch),
+            "json" => Ok(InputFormat::Json),
+            "tag" => Ok(InputFormat::Tag),
+            "threadtime" => Ok(InputFormat::Threadtime),
+            "time" => Ok(InputFormat::Time),
+            _ => Err("Input format parsing error"),
+        }
+    }
+}
+
+impl Display for ParserError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Fail for ParserError {}
+
 pub trait FormatParser: Send + Sync {
     fn try_parse_str(&self, line: &str) -> Result<Record, ParserError>;
 }
@@ -55,6 +108,17 @@ named!(
       )
 );
 
+/// Convert the fractional part of a second into nanoseconds e.g "052" to 52000000
+fn nanoseconds(fraction: CompleteStr) -> i32 {
+    fraction
+        .chars()
+        .chain(repeat('0'))
+        .take(9)
+        .collect::<String>()
+        .parse()
+        .unwrap_or(0)
+}
+
 // 2017-03-25 19:11:19.052
 // or
 // 2017-03-25 19:11:19.052321
@@ -73,7 +137,7 @@ named!(
             >> char!(':')
             >> second: flat_map!(take_until!("."), parse_to!(i32))
             >> char!('.')
-            >> nanosecond: flat_map!(take_while!(|c| is_digit(c as u8)), parse_to!(i32))
+            >> nanosecond: map!(take_while!(|c| is_digit(c as u8)), nanoseconds)
             >> utcoff:
                 opt!(complete!(do_parse!(
                     space
@@ -102,11 +166,42 @@ named!(
     )
 );
 
+// 1553541079.052 or 1553541079.052321 in local time. Exactly ten digits
+// distinguish the seconds from a month-day timestamp
+named!(
+    epoch<CompleteStr, Tm>,
+    do_parse!(
+        seconds: flat_map!(take!(10), parse_to!(i64))
+            >> char!('.')
+            >> nanosecond: map!(take_while!(|c| is_digit(c as u8)), nanoseconds)
+            >> (time::at(Timespec::new(seconds, nanosecond)))
+    )
+);
+
 named!(
     printable<CompleteStr, Record>,
     do_parse!(
-        timestamp: timestamp
-            >> many1!(space)
+        timestamp: alt!(epoch | timestamp) >> record: threadtime >> (record_at(record, timestamp))
+    )
+);
+
+named!(
+    epoch_printable<CompleteStr, Record>,
+    do_parse!(timestamp: epoch >> record: threadtime >> (record_at(record, timestamp)))
+);
+
+fn record_at(record: Record, timestamp: Tm) -> Record {
+    Record {
+        timestamp: Some(Timestamp::new(timestamp)),
+        ..record
+    }
+}
+
+// The part of a threadtime line after the timestamp
+named!(
+    threadtime<CompleteStr, Record>,
+    do_parse!(
+        many1!(space)
             >> process: hex_digit
             >> many1!(space)
             >> thread: hex_digit
@@ -117,7 +212,6 @@ named!(
             >> tag!(": ")
             >> message: opt!(rest)
             >> (Record {
-                timestamp: Some(Timestamp::new(timestamp)),
                 level,
                 tag: tag.trim().to_owned(),
                 process: process.trim().to_owned(),
@@ -179,6 +273,60 @@ named!(
     )
 );
 
+// I/ActivityManager(  585): Start proc
+named!(
+    brief<CompleteStr, Record>,
+    do_parse!(
+        level: level
+            >> char!('/')
+            >> tag: take_until!("(")
+            >> char!('(')
+            >> opt!(space)
+            >> process: hex_digit
+            >> tag!("): ")
+            >> message: opt!(rest)
+            >> (Record {
+                level,
+                tag: tag.trim().to_owned(),
+                process: process.trim().to_owned(),
+                message: message.unwrap_or(CompleteStr("")).trim().to_owned(),
+                ..Default::default()
+            })
+    )
+);
+
+// I/ActivityManager: Start proc
+named!(
+    tag_only<CompleteStr, Record>,
+    do_parse!(
+        level: level
+            >> char!('/')
+            >> tag: take_until!(": ")
+            >> tag!(": ")
+            >> message: opt!(rest)
+            >> (Record {
+                level,
+                tag: tag.trim().to_owned(),
+                message: message.unwrap_or(CompleteStr("")).trim().to_owned(),
+                ..Default::default()
+            })
+    )
+);
+
+// 03-25 19:11:19.052 I/ActivityManager(  585): Start proc
+named!(
+    time_brief<CompleteStr, Record>,
+    do_parse!(
+        timestamp: timestamp
+            >> many1!(space)
+            >> record: brief
+            >> (Record {
+                timestamp: Some(Timestamp::new(timestamp)),
+                ..record
+            })
+    )
+);
+
 named!(
     bugreport_section<CompleteStr, (String, String)>,
     do_parse!(
@@ -215,6 +363,58 @@ impl FormatParser for DefaultParser {
     }
 }
 
+pub struct EpochParser;
+
+impl FormatParser for EpochParser {
+    fn try_parse_str(&self, line: &str) -> Result<Record, ParserError> {
+        epoch_printable(CompleteStr(line))
+            .map(|(_, mut v)| {
+                v.raw = line.into();
+                v
+            })
+            .map_err(|e| ParserError(format!("{}", e)))
+    }
+}
+
+pub struct BriefParser;
+
+impl FormatParser for BriefParser {
+    fn try_parse_str(&self, line: &str) -> Result<Record, ParserError> {
+        brief(CompleteStr(line))
+            .map(|(_, mut v)| {
+                v.raw = line.into();
+                v
+            })
+            .map_err(|e| ParserError(format!("{}", e)))
+    }
+}
+
+pub struct TagParser;
+
+impl FormatParser for TagParser {
+    fn try_parse_str(&self, line: &str) -> Result<Record, ParserError> {
+        tag_only(CompleteStr(line))
+            .map(|(_, mut v)| {
+                v.raw = line.into();
+                v
+            })
+            .map_err(|e| ParserError(format!("{}", e)))
+    }
+}
+
+pub struct TimeParser;
+
+impl FormatParser for TimeParser {
+    fn try_parse_str(&self, line: &str) -> Result<Record, ParserError> {
+        time_brief(CompleteStr(line))
+            .map(|(_, mut v)| {
+                v.raw = line.into();
+                v
+            })
+            .map_err(|e| ParserError(format!("{}", e)))
+    }
+}
+
 pub struct MindroidParser;
 
 impl FormatParser for MindroidParser {
@@ -230,8 +430,8 @@ impl FormatParser for MindroidParser {
 
 pub struct CsvParser;
 
-impl FormatParser for CsvParser {
-    fn try_parse_str(&self, line: &str) -> Result<Record, ParserError> {
+impl CsvParser {
+    fn deserialize<T: serde::de::DeserializeOwned>(line: &str) -> Result<T, ParserError> {
         let reader = Cursor::new(line).chain(Cursor::new([b'\n']));
         let mut rdr = ReaderBuilder::new().has_headers(false).from_reader(reader);
         if let Some(result) = rdr.deserialize().next() {
@@ -242,17 +442,31 @@ impl FormatParser for CsvParser {
     }
 }
 
+impl FormatParser for CsvParser {
+    /// Parse the csv row format and fall back to the whole `Record` format
+    /// written by older versions
+    fn try_parse_str(&self, line: &str) -> Result<Record, ParserError> {
+        CsvParser::deserialize::<CsvRow>(line)
+            .map(|row| row.into_record(line))
+            .or_else(|_| CsvParser::deserialize(line))
+    }
+}
+
 pub struct JsonParser;
 
 impl FormatParser for JsonParser {
     fn try_parse_str(&self, line: &str) -> Result<Record, ParserError> {
-        from_str(line).map_err(|e| ParserError(format!("Failed to deserialize json: {}", e)))
+        from_str::<JsonRow>(line)
+            .map(|row| row.into_record(line))
+            .map_err(|e| ParserError(format!("Failed to deserialize json: {}", e)))
     }
 }
 
 pub struct Parser {
     parsers: Vec<Box<dyn FormatParser>>,
     last: Option<usize>,
+    /// Treat lines that cannot be parsed as message
+    fallback: bool,
 }
 
 impl Default for Parser {
@@ -260,11 +474,14 @@ impl Default for Parser {
         Parser {
             parsers: vec![
                 Box::new(DefaultParser),
+                Box::new(TimeParser),
                 Box::new(MindroidParser),
+                Box::new(BriefParser),
                 Box::new(CsvParser),
                 Box::new(JsonParser),
             ],
             last: None,
+            fallback: true,
         }
     }
 }
@@ -274,26 +491,67 @@ impl Parser {
         Parser {
             parsers: Vec::new(),
             last: None,
+            fallback: true,
+        }
+    }
+
+    /// Parser that accepts only the given format
+    pub fn with_format(format: &InputFormat) -> Self {
+        let parser: Box<dyn FormatParser> = match format {
+            InputFormat::Brief => Box::new(BriefParser),
+            InputFormat::Epoch => Box::new(EpochParser),
+            InputFormat::Json => Box::new(JsonParser),
+            InputFormat::Tag => Box::new(TagParser),
+            InputFormat::Threadtime => Box::new(DefaultParser),
+            InputFormat::Time => Box::new(TimeParser),
+        };
+        Parser {
+            parsers: vec![parser],
+            last: None,
+            fallback: *format != InputFormat::Json,
         }
     }
 
+    /// Forget the format of the last line e.g at the start of a new file
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+
     pub fn parse(&mut self, line: &str) -> Record {
+        self.try_parse(line)
+            .unwrap_or_else(|_| Self::unparsed(line))
+    }
+
+    /// Parse line. Lines that cannot be parsed are an error unless the
+    /// parser falls back to the line as message
+    pub fn try_parse(&mut self, line: &str) -> Result<Record, ParserError> {
         if let Some(last) = self.last {
             let p = &self.parsers[last];
             if let Ok(r) = p.try_parse_str(line) {
-                return r;
+                return Ok(r);
             }
         }
 
+        let mut error = None;
         for (i, p) in self.parsers.iter().map(Box::as_ref).enumerate() {
-            if let Ok(r) = p.try_parse_str(line) {
-                self.last = Some(i);
-                return r;
+            match p.try_parse_str(line) {
+                Ok(r) => {
+                    self.last = Some(i);
+                    return Ok(r);
+                }
+                Err(e) => error = Some(e),
             }
         }
 
-        // Seems that we cannot parse this record
-        // Treat the raw input as message
+        if self.fallback {
+            Ok(Self::unparsed(line))
+        } else {
+            Err(error.unwrap_or_else(|| ParserError("No parser".to_owned())))
+        }
+    }
+
+    /// Seems that we cannot parse this record. Treat the raw input as message
+    fn unparsed(line: &str) -> Record {
         Record {
             raw: String::from(line),
             message: String::from(line),
@@ -327,6 +585,95 @@ fn parse_timestamp() {
     timestamp(CompleteStr("2017-03-25 19:11:19.052123")).unwrap();
 }
 
+#[test]
+fn parse_timestamp_fraction() {
+    let t = timestamp(CompleteStr("03-25 19:11:19.052")).unwrap().1;
+    assert_eq!(t.tm_nsec, 52_000_000);
+    let t = timestamp(CompleteStr("2017-03-25 19:11:19.052123"))
+        .unwrap()
+        .1;
+    assert_eq!(t.tm_nsec, 52_123_000);
+    let t = timestamp(CompleteStr("03-25 19:11:19.5")).unwrap().1;
+    assert_eq!(t.tm_nsec, 500_000_000);
+}
+
+#[test]
+fn parse_threadtime() {
+    let t = "01-08 11:04:37.556  1463  1479 W ActivityManager: Unable to start service Intent";
+    let p = DefaultParser {};
+    let r = p.try_parse_str(t).unwrap();
+    let ts = r.timestamp.unwrap();
+    assert_eq!(ts.tm_mon, 0);
+    assert_eq!(ts.tm_mday, 8);
+    assert_eq!(ts.tm_hour, 11);
+    assert_eq!(ts.tm_min, 4);
+    assert_eq!(ts.tm_sec, 37);
+    assert_eq!(ts.tm_nsec, 556_000_000);
+    assert_eq!(r.process, "1463");
+    assert_eq!(r.thread, "1479");
+    assert_eq!(r.level, Level::Warn);
+    assert_eq!(r.tag, "ActivityManager");
+    assert_eq!(r.message, "Unable to start service Intent");
+    assert_eq!(r.raw, t);
+
+    let t = "01-08 11:04:37.601   578   578 I         vold: Starting   with   spaces";
+    let r = p.try_parse_str(t).unwrap();
+    assert_eq!(r.process, "578");
+    assert_eq!(r.thread, "578");
+    assert_eq!(r.level, Level::Info);
+    assert_eq!(r.tag, "vold");
+    assert_eq!(r.message, "Starting   with   spaces");
+}
+
+#[test]
+fn parse_epoch() {
+    let t = "1553541079.052  1463  1479 W ActivityManager: Unable to start service Intent";
+    let r = DefaultParser {}.try_parse_str(t).unwrap();
+    assert_eq!(
+        r.timestamp.unwrap().to_timespec(),
+        Timespec::new(1_553_541_079, 52_000_000)
+    );
+    assert_eq!(r.process, "1463");
+    assert_eq!(r.thread, "1479");
+    assert_eq!(r.tag, "ActivityManager");
+    assert_eq!(r.message, "Unable to start service Intent");
+
+    let r = Parser::with_format(&InputFormat::Epoch)
+        .parse("1553541079.052321  1463  1479 W ActivityManager: usec");
+    assert_eq!(
+        r.timestamp.unwrap().to_timespec(),
+        Timespec::new(1_553_541_079, 52_321_000)
+    );
+    assert_eq!(r.message, "usec");
+
+    // Month and day lines are not mistaken for seconds
+    assert!(EpochParser {}
+        .try_parse_str("01-08 11:04:37.556  1463  1479 W ActivityManager: threadtime")
+        .is_err());
+}
+
+#[test]
+fn parse_usec() {
+    let t = "01-08 11:04:37.556123  1463  1479 W ActivityManager: usec";
+    let r = DefaultParser {}.try_parse_str(t).unwrap();
+    let ts = r.timestamp.unwrap();
+    assert_eq!((ts.tm_mon, ts.tm_mday, ts.tm_sec), (0, 8, 37));
+    assert_eq!(ts.tm_nsec, 556_123_000);
+    assert_eq!(r.message, "usec");
+}
+
+#[test]
+fn parse_unparseable_keeps_raw() {
+    let t = "--------- beginning of main";
+    let r = Parser::default().parse(t);
+    assert_eq!(r.raw, t);
+    assert_eq!(r.timestamp, None);
+    assert_eq!(r.level, Level::None);
+    assert!(r.tag.is_empty());
+    assert!(r.process.is_empty());
+    assert!(r.thread.is_empty());
+}
+
 #[test]
 fn parse_printable() {
     let t = "03-01 02:19:45.207     1     2 I EXT4-fs (mmcblk3p8): mounted filesystem with \
@@ -422,6 +769,74 @@ fn test_parse_mindroid() {
     assert_eq!(r.message, "");
 }
 
+#[test]
+fn parse_brief() {
+    let p = BriefParser {};
+    let r = p
+        .try_parse_str("I/ActivityManager(  585): Start proc com.android.settings")
+        .unwrap();
+    assert_eq!(r.level, Level::Info);
+    assert_eq!(r.tag, "ActivityManager");
+    assert_eq!(r.process, "585");
+    assert_eq!(r.message, "Start proc com.android.settings");
+
+    let r = p
+        .try_parse_str("E/Some/Tag( 1234): call foo(): failed")
+        .unwrap();
+    assert_eq!(r.level, Level::Error);
+    assert_eq!(r.tag, "Some/Tag");
+    assert_eq!(r.process, "1234");
+    assert_eq!(r.message, "call foo(): failed");
+
+    assert!(p.try_parse_str("I/ActivityManager: Start proc").is_err());
+
+    let r = Parser::default().parse("I/ActivityManager(  585): Start proc");
+    assert_eq!(r.tag, "ActivityManager");
+    assert_eq!(r.process, "585");
+}
+
+#[test]
+fn parse_tag() {
+    let p = TagParser {};
+    let r = p.try_parse_str("W/Some/Tag: call foo(): failed").unwrap();
+    assert_eq!(r.level, Level::Warn);
+    assert_eq!(r.tag, "Some/Tag");
+    assert_eq!(r.process, "");
+    assert_eq!(r.message, "call foo(): failed");
+}
+
+#[test]
+fn parse_time() {
+    let t = "03-25 19:11:19.052 D/ServiceManager(  123): call foo(): failed";
+    let p = TimeParser {};
+    let r = p.try_parse_str(t).unwrap();
+    assert_eq!(r.timestamp.unwrap().tm_nsec, 52_000_000);
+    assert_eq!(r.level, Level::Debug);
+    assert_eq!(r.tag, "ServiceManager");
+    assert_eq!(r.process, "123");
+    assert_eq!(r.message, "call foo(): failed");
+    assert_eq!(r.raw, t);
+
+    let r = Parser::default().parse(t);
+    assert_eq!(r.tag, "ServiceManager");
+    assert_eq!(r.process, "123");
+}
+
+#[test]
+fn parse_with_format() {
+    let mut p = Parser::with_format(&InputFormat::Tag);
+    let r = p.parse("I/ActivityManager(  585): Start proc");
+    assert_eq!(r.tag, "ActivityManager(  585)");
+
+    let mut p = Parser::with_format(&InputFormat::Brief);
+    let r = p.parse("03-01 02:19:45.207     1     2 I Foo: threadtime");
+    assert_eq!(r.tag, "");
+    assert_eq!(
+        r.message,
+        "03-01 02:19:45.207     1     2 I Foo: threadtime"
+    );
+}
+
 #[test]
 fn parse_csv_unparseable() {
     let p = CsvParser {};
@@ -445,6 +860,70 @@ fn test_parse_csv() {
     );
 }
 
+#[test]
+fn parse_csv_row() {
+    let t = "07-01 14:13:14.446000000,Info,225,295,ThermalEngine,\"batt, \"\"therm\"\"\"";
+    let r = CsvParser {}.try_parse_str(t).unwrap();
+    assert_eq!(r.level, Level::Info);
+    assert_eq!(r.process, "225");
+    assert_eq!(r.thread, "295");
+    assert_eq!(r.tag, "ThermalEngine");
+    assert_eq!(r.message, "batt, \"therm\"");
+
+    let r = CsvParser {}.try_parse_str(",,,,,message").unwrap();
+    assert_eq!(r.timestamp, None);
+    assert_eq!(r.level, Level::None);
+    assert_eq!(r.message, "message");
+}
+
+#[test]
+fn json_round_trip() {
+    use crate::record::Format;
+    let t = "03-01 02:19:45.207     1     2 I Foo: json";
+    let r = DefaultParser {}.try_parse_str(t).unwrap();
+    let json = Format::Json.fmt_record(&r).unwrap();
+    assert_eq!(JsonParser {}.try_parse_str(&json).unwrap(), r);
+
+    let r = Record {
+        message: "json".into(),
+        ..Default::default()
+    };
+    let json = Format::Json.fmt_record(&r).unwrap();
+    assert_eq!(json, r#"{"message":"json"}"#);
+
+    // Reading a capture back with --input-format json
+    let mut p = Parser::with_format(&InputFormat::Json);
+    let r = DefaultParser {}.try_parse_str(t).unwrap();
+    let json = Format::Json.fmt_record(&r).unwrap();
+    assert_eq!(p.try_parse(&json).unwrap(), r);
+    assert!(p.try_parse("{\"message\": ").is_err());
+    assert!(p.try_parse(t).is_err());
+}
+
+#[test]
+fn logcat_canonical() {
+    use crate::record::Format;
+    let r = Parser::default().parse("I/ActivityManager(  585): Start proc com.android.settings");
+    let line = Format::Logcat.fmt_record(&r).unwrap();
+    assert_eq!(
+        line,
+        "01-01 00:00:00.000   585   585 I ActivityManager: Start proc com.android.settings"
+    );
+    let t = DefaultParser {}.try_parse_str(&line).unwrap();
+    assert_eq!(t.tag, r.tag);
+    assert_eq!(t.level, r.level);
+    assert_eq!(t.process, r.process);
+    assert_eq!(t.message, r.message);
+
+    let t = "03-01 02:19:45.207     1     2 I Foo     : message";
+    let r = DefaultParser {}.try_parse_str(t).unwrap();
+    assert_eq!(Format::Logcat.fmt_record(&r).unwrap(), t);
+
+    // Unparsed lines
+    let r = Parser::default().parse("--------- beginning of main");
+    assert_eq!(Format::Logcat.fmt_record(&r).unwrap(), r.raw);
+}
+
 #[test]
 fn parse_property() {
     let t = "[ro.build.tags]: [release-keys]";
@@ -0,0 +1,195 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{terminal::status, utils::adb_argv};
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use futures::{Future, Stream};
+use rogcat::record::Record;
+use std::{
+    collections::HashMap,
+    process::Command,
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::timer::Interval;
+use tokio_process::CommandExt;
+
+/// Default interval of pid updates with --resolve-pids
+const RESOLVE_INTERVAL: u64 = 5;
+
+/// Names of the processes on the device resolved with `adb shell ps`
+#[derive(Clone, Debug)]
+pub struct ProcessNames {
+    cmd: Vec<String>,
+    interval: Duration,
+    names: Arc<RwLock<HashMap<String, String>>>,
+}
+
+/// Resolve the process names if --resolve-pids is passed
+pub fn try_from(args: &ArgMatches) -> Result<Option<ProcessNames>, Error> {
+    if !args.is_present("resolve_pids") {
+        return Ok(None);
+    }
+    let interval = args
+        .value_of("resolve_pids_interval")
+        .map(|v| {
+            u64::from_str(v).map_err(|e| format_err!("Invalid resolve pids interval {}: {}", v, e))
+        })
+        .transpose()?
+        .unwrap_or(RESOLVE_INTERVAL);
+
+    // Toybox ps on Android 8 and later lists only the processes of the shell
+    // without -A. Older toolbox ps lists all but doesn't know -A
+    let adb = adb_argv(args)?;
+    let mut names = HashMap::new();
+    let mut cmd = Vec::new();
+    for ps in &[&["ps", "-A"][..], &["ps"][..]] {
+        cmd = adb.clone();
+        cmd.push("shell".into());
+        cmd.extend(ps.iter().map(|a| (*a).to_owned()));
+        let output = Command::new(&cmd[0])
+            .args(&cmd[1..])
+            .output()
+            .map_err(|e| format_err!("Failed to run {}: {}", cmd.join(" "), e))?;
+        names = parse(&output.stdout);
+        if !names.is_empty() {
+            break;
+        }
+    }
+    if names.is_empty() {
+        status("Failed to resolve process names");
+    }
+
+    Ok(Some(ProcessNames {
+        cmd,
+        interval: Duration::from_secs(interval.max(1)),
+        names: Arc::new(RwLock::new(names)),
+    }))
+}
+
+/// Parse the output of ps. The pid column is found in the header and the
+/// name is the last column
+fn parse(output: &[u8]) -> HashMap<String, String> {
+    let output = String::from_utf8_lossy(output);
+    let mut lines = output.lines();
+    let pid = match lines
+        .next()
+        .and_then(|header| header.split_whitespace().position(|c| c == "PID"))
+    {
+        Some(pid) => pid,
+        None => return HashMap::new(),
+    };
+    lines
+        .filter_map(|line| {
+            let columns = line.split_whitespace().collect::<Vec<_>>();
+            // The name follows the pid
+            if columns.len() > pid + 1 {
+                Some((
+                    columns[pid].to_owned(),
+                    columns[columns.len() - 1].to_owned(),
+                ))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+impl ProcessNames {
+    /// Set the process name of record. Unknown pids are shown as is
+    pub fn resolve(&self, mut record: Record) -> Record {
+        if record.process.is_empty() {
+            return record;
+        }
+        let name = self
+            .names
+            .read()
+            .ok()
+            .and_then(|names| names.get(&record.process).cloned());
+        record.process_name = Some(name.unwrap_or_else(|| record.process.clone()));
+        record
+    }
+
+    /// Periodically update the names. Failed updates keep the last known
+    /// names
+    pub fn follow(self) -> impl Future<Item = (), Error = ()> {
+        Interval::new_interval(self.interval)
+            .map_err(|_| ())
+            .for_each(move |_| {
+                let names = self.names.clone();
+                Command::new(&self.cmd[0])
+                    .args(&self.cmd[1..])
+                    .output_async()
+                    .map(move |output| {
+                        let update = parse(&output.stdout);
+                        if !update.is_empty() {
+                            if let Ok(mut names) = names.write() {
+                                *names = update;
+                            }
+                        }
+                    })
+                    .or_else(|_| Ok(()))
+            })
+    }
+}
+
+#[test]
+fn parse_ps() {
+    let toybox = b"USER           PID  PPID     VSZ    RSS WCHAN            ADDR S NAME
+root             1     0 10904448 11720 0                   0 S init
+u0_a42        4711   612 15327864 98304 0                   0 S com.android.phone
+";
+    let names = parse(toybox);
+    assert_eq!(names.len(), 2);
+    assert_eq!(names["1"], "init");
+    assert_eq!(names["4711"], "com.android.phone");
+
+    let toolbox = b"USER     PID   PPID  VSIZE  RSS     WCHAN    PC         NAME
+system    523   1     1027476 60148 ffffffff b6ed1e84 S system_server
+";
+    assert_eq!(parse(toolbox)["523"], "system_server");
+    assert!(parse(b"/system/bin/sh: ps: not found").is_empty());
+}
+
+#[test]
+fn resolve_unknown() {
+    let mut names = HashMap::new();
+    names.insert("1".to_owned(), "init".to_owned());
+    let names = ProcessNames {
+        cmd: Vec::new(),
+        interval: Duration::from_secs(1),
+        names: Arc::new(RwLock::new(names)),
+    };
+    let record = |pid: &str| Record {
+        process: pid.to_owned(),
+        ..Default::default()
+    };
+    assert_eq!(
+        names.resolve(record("1")).process_name,
+        Some("init".to_owned())
+    );
+    assert_eq!(
+        names.resolve(record("2")).process_name,
+        Some("2".to_owned())
+    );
+    assert_eq!(names.resolve(record("")).process_name, None);
+}
@@ -29,6 +29,8 @@ use std::{
 use toml::from_str;
 
 const DEFAULT_PROFILE_NAME: &str = "default";
+/// Name of the built in profile selected with --crashes
+const CRASHES_PROFILE_NAME: &str = "crashes";
 
 /// Profile definition with filters and misc
 #[derive(Clone, Debug, Default, PartialEq)]
@@ -36,10 +38,12 @@ pub struct Profile {
     pub comment: Option<String>,
     pub extends: Vec<String>,
     pub highlight: Vec<String>,
+    pub level: Option<String>,
     pub message: Vec<String>,
     pub message_ignore_case: Vec<String>,
     pub regex: Vec<String>,
     pub tag: Vec<String>,
+    pub tag_ignore: Vec<String>,
     pub tag_ignore_case: Vec<String>,
 }
 
@@ -47,37 +51,67 @@ pub struct Profile {
 /// and default if file is not present or readable
 pub fn from_args(args: &ArgMatches) -> Result<Profile, Error> {
     let file = file(Some(args))?;
-    if !file.exists() {
-        Ok(Profile::default())
+    let name = if args.is_present("crashes") {
+        Some(CRASHES_PROFILE_NAME)
     } else {
-        let mut config = String::new();
-        File::open(file.clone())
-            .map_err(|e| format_err!("Failed to open {}: {}", file.display(), e))?
-            .read_to_string(&mut config)?;
-
-        let mut config_file: ConfigurationFile = from_str(&config)
-            .map_err(|e| format_err!("Failed to parse {}: {}", file.display(), e))?;
-
-        let profiles: HashMap<String, Profile> = config_file
-            .profile
-            .drain()
-            .map(|(k, v)| (k, v.into()))
-            .collect();
-
-        let mut profile = Profile::default();
-        if let Some(n) = args.value_of("profile") {
-            profile = profiles
-                .get(n)
-                .ok_or_else(|| format_err!("Unknown profile {}", n))?
-                .clone();
-            expand(n, &mut profile, &profiles)?;
-        } else if let Some(default_profile) = profiles.get(DEFAULT_PROFILE_NAME) {
-            profile = default_profile.clone();
-            expand(DEFAULT_PROFILE_NAME, &mut profile, &profiles)?;
-        }
+        args.value_of("profile")
+    };
+    let mut profiles = builtin();
+    if !file.exists() {
+        return match name {
+            Some(n) => match profiles.remove(n) {
+                Some(profile) => Ok(profile),
+                None => Err(format_err!(
+                    "Unknown profile {}. Cannot find {}",
+                    n,
+                    file.display()
+                )),
+            },
+            None => Ok(Profile::default()),
+        };
+    }
+
+    let mut config = String::new();
+    File::open(file.clone())
+        .map_err(|e| format_err!("Failed to open {}: {}", file.display(), e))?
+        .read_to_string(&mut config)?;
 
-        Ok(profile)
+    let mut config_file: ConfigurationFile =
+        from_str(&config).map_err(|e| format_err!("Failed to parse {}: {}", file.display(), e))?;
+
+    // Profiles of the file replace built in ones of the same name
+    profiles.extend(config_file.profile.drain().map(|(k, v)| (k, v.into())));
+
+    let mut profile = Profile::default();
+    if let Some(n) = name {
+        profile = profiles
+            .get(n)
+            .ok_or_else(|| format_err!("Unknown profile {}", n))?
+            .clone();
+        expand(n, &mut profile, &profiles)?;
+    } else if let Some(default_profile) = profiles.get(DEFAULT_PROFILE_NAME) {
+        profile = default_profile.clone();
+        expand(DEFAULT_PROFILE_NAME, &mut profile, &profiles)?;
     }
+
+    Ok(profile)
+}
+
+/// Profiles compiled into the binary. They are available without a
+/// configuration file and can be extended like the profiles of the file
+fn builtin() -> HashMap<String, Profile> {
+    let crashes = Profile {
+        comment: Some("Errors of AndroidRuntime, DEBUG, libc and System.err".to_owned()),
+        level: Some("error".to_owned()),
+        tag: ["^AndroidRuntime$", "^DEBUG$", "^libc$", "^System\\.err$"]
+            .iter()
+            .map(|t| (*t).to_owned())
+            .collect(),
+        ..Default::default()
+    };
+    let mut profiles = HashMap::new();
+    profiles.insert(CRASHES_PROFILE_NAME.to_owned(), crashes);
+    profiles
 }
 
 /// Expand a profile with file content
@@ -145,10 +179,12 @@ struct ProfileFile {
     comment: Option<String>,
     extends: Option<Vec<String>>,
     highlight: Option<Vec<String>>,
+    level: Option<String>,
     message: Option<Vec<String>>,
     message_ignore_case: Option<Vec<String>>,
     regex: Option<Vec<String>>,
     tag: Option<Vec<String>>,
+    tag_ignore: Option<Vec<String>>,
     tag_ignore_case: Option<Vec<String>>,
 }
 
@@ -158,10 +194,12 @@ impl From<ProfileFile> for Profile {
             comment: f.comment,
             extends: f.extends.unwrap_or_default(),
             highlight: f.highlight.unwrap_or_default(),
+            level: f.level,
             message: f.message.unwrap_or_default(),
             message_ignore_case: f.message_ignore_case.unwrap_or_default(),
             regex: f.regex.unwrap_or_default(),
             tag: f.tag.unwrap_or_default(),
+            tag_ignore: f.tag_ignore.unwrap_or_default(),
             tag_ignore_case: f.tag_ignore_case.unwrap_or_default(),
         }
     }
@@ -180,6 +218,73 @@ impl AddAssign for Profile {
         vec_extend!(self.extends, other.extends);
         vec_extend!(self.highlight, other.highlight);
         vec_extend!(self.message, other.message);
+        vec_extend!(self.message_ignore_case, other.message_ignore_case);
+        vec_extend!(self.regex, other.regex);
         vec_extend!(self.tag, other.tag);
+        vec_extend!(self.tag_ignore, other.tag_ignore);
+        vec_extend!(self.tag_ignore_case, other.tag_ignore_case);
+
+        // The level of the extending profile wins
+        if self.level.is_none() {
+            self.level = other.level;
+        }
     }
 }
+
+#[test]
+fn parse_profiles() {
+    let config = r#"
+[profile.a]
+comment = "Warnings of tag A"
+level = "warn"
+tag = ["^A"]
+
+[profile.b]
+extends = ["a"]
+message = ["^B"]
+tag_ignore = ["^Chatty$"]
+highlight = ["error"]
+"#;
+    let mut config_file: ConfigurationFile = from_str(config).unwrap();
+    let profiles: HashMap<String, Profile> = config_file
+        .profile
+        .drain()
+        .map(|(k, v)| (k, v.into()))
+        .collect();
+    assert_eq!(profiles.len(), 2);
+
+    let mut b = profiles["b"].clone();
+    assert_eq!(b.level, None);
+    expand("b", &mut b, &profiles).unwrap();
+    assert_eq!(b.level, Some("warn".to_owned()));
+    assert_eq!(b.tag, vec!["^A"]);
+    assert_eq!(b.tag_ignore, vec!["^Chatty$"]);
+    assert_eq!(b.message, vec!["^B"]);
+    assert_eq!(b.highlight, vec!["error"]);
+
+    let mut c = Profile {
+        extends: vec!["c".to_owned()],
+        ..Default::default()
+    };
+    assert!(expand("c", &mut c, &profiles).is_err());
+}
+
+#[test]
+fn crashes_profile() {
+    use rogcat::record::{Level, Record};
+    // The profile of --crashes without a profiles.toml of the user
+    let args = crate::cli::args(&["rogcat"]);
+    let profile = builtin().remove(CRASHES_PROFILE_NAME).unwrap();
+    let filter = crate::filter::from_args_profile(&args, &profile).unwrap();
+    let record = |level, tag: &str| Record {
+        level,
+        tag: tag.to_owned(),
+        message: "FATAL EXCEPTION: main".to_owned(),
+        ..Default::default()
+    };
+    assert!(filter.filter(&record(Level::Error, "AndroidRuntime")));
+    assert!(filter.filter(&record(Level::Fatal, "DEBUG")));
+    assert!(!filter.filter(&record(Level::Info, "AndroidRuntime")));
+    assert!(!filter.filter(&record(Level::Error, "ActivityManager")));
+    assert!(!filter.filter(&record(Level::Error, "SystemXerr")));
+}
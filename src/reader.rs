@@ -19,38 +19,177 @@
 // SOFTWARE.
 
 use crate::{
-    lossy_lines::{lossy_lines, LossyLinesCodec},
-    utils::{adb, config_get},
-    LogStream, StreamData, DEFAULT_BUFFER,
+    lossy_lines::{lossy_lines, LossyLinesCodec, DEFAULT_MAX_LINE_LENGTH},
+    subcommands::parse_devices,
+    terminal::status,
+    utils::{adb, adb_argv, config_get, expand_env, parse_size, split_command},
+    LogStream, StreamData, BUFFERS, DEFAULT_BUFFER,
 };
+use bytes::BytesMut;
 use clap::{value_t, ArgMatches};
-use failure::{err_msg, format_err, Error};
-use futures::{stream::iter_ok, Async, Future, Stream};
+use failure::{err_msg, format_err, Error, Fail};
+use futures::{stream::iter_ok, try_ready, Async, Future, Poll, Stream};
+use lazy_static::lazy_static;
+use regex::Regex;
 #[cfg(target_os = "linux")]
-use rogcat::record::{Record, Timestamp};
+use rogcat::record::Timestamp;
+use rogcat::record::{Level, Record};
 use std::{
     borrow::ToOwned,
+    cmp::{max, min},
+    collections::{HashMap, VecDeque},
     convert::Into,
-    io::BufReader,
-    net::ToSocketAddrs,
+    fmt::{self, Display, Formatter},
+    io::{BufReader, Read},
+    net::{SocketAddr, ToSocketAddrs},
     path::PathBuf,
-    process::{Command, Stdio},
+    process::{Command, ExitStatus, Stdio},
+    sync::Mutex,
+    time::{Duration, Instant},
 };
+use time::strptime;
 use tokio::{
     codec::{Decoder, FramedRead},
     fs::File,
-    net::TcpStream,
+    net::{TcpListener, TcpStream},
+    timer::{Delay, Interval},
 };
-use tokio_process::{Child, CommandExt};
+use tokio_process::{Child, CommandExt, StatusAsync};
 use url::Url;
 
+lazy_static! {
+    /// Errors printed by adb if the device is not (yet) available
+    static ref DEVICE_MISSING: Regex =
+        Regex::new(r"device ('[^']*' )?(not found|offline)|no devices/emulators found").unwrap();
+    /// Result of the --pid check per adb argv i.e device
+    static ref PID_SUPPORT: Mutex<HashMap<Vec<String>, bool>> = Mutex::new(HashMap::new());
+}
+
+/// Initial delay before a command is restarted
+const DEFAULT_RESTART_BACKOFF: Duration = Duration::from_millis(100);
+/// Upper limit of the restart delay
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(5);
+/// Capacity of the buffered readers of command output
+const DEFAULT_READ_BUFFER: usize = 64 * 1024;
+
+type OutputStream = Box<dyn Stream<Item = Output, Error = Error> + Send>;
+
+/// Line read from a child process
+enum Output {
+    Stdout(String),
+    Stderr(String),
+}
+
+/// Exponential restart delay
+struct Backoff {
+    initial: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    fn new(initial: Duration) -> Backoff {
+        Backoff {
+            initial,
+            current: initial,
+        }
+    }
+
+    /// Return the current delay and double it for the next call
+    fn next(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = min(self.current * 2, max(MAX_RESTART_BACKOFF, self.initial));
+        delay
+    }
+
+    fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
 /// A spawned child process that implements LogStream
 struct Process {
     cmd: Vec<String>,
     /// Respawn cmd upone termination
     respawn: bool,
+    backoff: Backoff,
+    delay: Option<Delay>,
+    /// Command that blocks until a device is available
+    wait_for_device: Option<Vec<String>>,
+    device_missing: bool,
+    /// Pass stderr lines as records instead of printing them as status
+    merge_stderr: bool,
+    /// Capacity of the stdout and stderr readers
+    read_buffer: usize,
+    /// Emit a marker record before the output of a restarted command
+    restart_marker: bool,
+    restarted: bool,
+    /// Emit records when the device disconnects and reconnects
+    connection_events: bool,
+    /// Remove escape sequences from the output
+    strip_ansi: bool,
+    /// Records emitted before the next output of the command
+    queue: VecDeque<StreamData>,
+    wait: Option<StatusAsync>,
     child: Option<Child>,
-    stream: Option<LogStream>,
+    stream: Option<OutputStream>,
+    exited: bool,
+}
+
+/// Record emitted before the output of a restarted command
+fn restart_marker() -> Record {
+    let marker = "--- reconnected ---";
+    Record {
+        message: marker.to_owned(),
+        raw: marker.to_owned(),
+        ..Default::default()
+    }
+}
+
+/// Record emitted when the device disconnects or reconnects
+fn connection_event(event: &str) -> StreamData {
+    StreamData::Record(Record {
+        level: Level::Error,
+        tag: "rogcat".to_owned(),
+        message: event.to_owned(),
+        raw: event.to_owned(),
+        ..Default::default()
+    })
+}
+
+/// A command that is not respawned exited unsuccessfully
+#[derive(Debug)]
+pub struct ExitError {
+    cmd: String,
+    status: ExitStatus,
+}
+
+impl Display for ExitError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Command \"{}\" failed with {}", self.cmd, self.status)
+    }
+}
+
+impl Fail for ExitError {}
+
+/// Exit code for an error. The code of a failed command is passed on
+pub fn exit_code(e: &Error) -> i32 {
+    e.downcast_ref::<ExitError>()
+        .and_then(|e| e.status.code())
+        .unwrap_or(1)
+}
+
+/// Check if line is an adb error about a missing device
+pub fn device_missing(line: &str) -> bool {
+    DEVICE_MISSING.is_match(line)
+}
+
+impl Drop for Process {
+    /// Kill the child instead of leaving it running after the stream is done
+    fn drop(&mut self) {
+        if let Some(ref mut child) = self.child {
+            child.kill().ok();
+        }
+    }
 }
 
 /// Open a file and provide a stream of lines
@@ -58,25 +197,219 @@ pub fn files(args: &ArgMatches) -> Result<LogStream, Error> {
     let files = args
         .values_of("input")
         .ok_or_else(|| err_msg("Missing input argument"))?
-        .map(PathBuf::from)
+        .map(expand_glob)
+        .collect::<Result<Vec<Vec<PathBuf>>, Error>>()?
+        .into_iter()
+        .flatten()
         .collect::<Vec<PathBuf>>();
 
+    // Follow the last file. The others are read up to their end
+    let follow = if args.is_present("follow") {
+        files.last().cloned()
+    } else {
+        None
+    };
+    let strip_ansi = args.is_present("strip_ansi");
     let f = iter_ok::<_, Error>(files)
-        .map(|f| {
-            File::open(f.clone())
-                .map(|s| Decoder::framed(LossyLinesCodec::new(), s))
+        .map(move |f| -> LogStream {
+            if f.to_str() == Some("-") {
+                return stdin(strip_ansi);
+            }
+            if follow.as_ref() == Some(&f) {
+                return Box::new(Follow::new(f, strip_ansi));
+            }
+            let s = File::open(f.clone())
+                .map(move |s| Decoder::framed(line_codec(strip_ansi), s))
                 .flatten_stream()
                 .map(StreamData::Line)
                 .map_err(move |e| format_err!("Failed to open {}: {}", f.display(), e))
+                .chain(iter_ok(vec![StreamData::EndOfFile]));
+            Box::new(s)
         })
         .flatten();
 
     Ok(Box::new(f))
 }
 
+/// Interval of size checks of a followed file
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Stream of the lines of a file that waits for appended data at the end
+/// of the file like `tail -f`. A file that shrinks or is replaced e.g by log
+/// rotation is read again from the start
+struct Follow {
+    path: PathBuf,
+    file: Option<std::fs::File>,
+    /// Bytes read from the current file
+    position: u64,
+    buffer: BytesMut,
+    codec: LossyLinesCodec,
+    /// Remove escape sequences from the lines
+    strip_ansi: bool,
+    interval: Interval,
+    /// A new file was opened. Reset the format detection of the parser
+    reopened: bool,
+}
+
+impl Follow {
+    fn new(path: PathBuf, strip_ansi: bool) -> Follow {
+        Follow {
+            path,
+            file: None,
+            position: 0,
+            buffer: BytesMut::new(),
+            codec: line_codec(strip_ansi),
+            strip_ansi,
+            interval: Interval::new_interval(FOLLOW_INTERVAL),
+            reopened: false,
+        }
+    }
+
+    fn open(&mut self) -> Result<(), Error> {
+        let file = std::fs::File::open(&self.path)
+            .map_err(|e| format_err!("Failed to open {}: {}", self.path.display(), e))?;
+        if self.file.is_some() {
+            status(&format!(
+                "{} was truncated or replaced",
+                self.path.display()
+            ));
+            self.reopened = true;
+        }
+        self.file = Some(file);
+        self.position = 0;
+        self.buffer.clear();
+        self.codec = line_codec(self.strip_ansi);
+        Ok(())
+    }
+
+    /// The file at path is shorter than the data read or is another file
+    fn rotated(&self) -> bool {
+        let metadata = match std::fs::metadata(&self.path) {
+            Ok(metadata) => metadata,
+            // Wait for the file to be created again
+            Err(_) => return false,
+        };
+        if metadata.len() < self.position {
+            return true;
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            if let Some(Ok(current)) = self.file.as_ref().map(std::fs::File::metadata) {
+                return current.ino() != metadata.ino() || current.dev() != metadata.dev();
+            }
+        }
+        false
+    }
+
+    /// Read a chunk of the data available. Returns the number of bytes read
+    fn read(&mut self) -> Result<usize, Error> {
+        let file = match self.file {
+            Some(ref mut file) => file,
+            None => return Ok(0),
+        };
+        let mut chunk = [0u8; 8192];
+        loop {
+            match file.read(&mut chunk) {
+                Ok(n) => {
+                    self.buffer.extend_from_slice(&chunk[..n]);
+                    self.position += n as u64;
+                    return Ok(n);
+                }
+                Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(format_err!("Failed to read {}: {}", self.path.display(), e)),
+            }
+        }
+    }
+}
+
+impl Stream for Follow {
+    type Item = StreamData;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<StreamData>, Error> {
+        if self.file.is_none() {
+            self.open()?;
+        }
+        loop {
+            if self.reopened {
+                self.reopened = false;
+                return Ok(Async::Ready(Some(StreamData::EndOfFile)));
+            }
+            if let Some(line) = self.codec.decode(&mut self.buffer)? {
+                return Ok(Async::Ready(Some(StreamData::Line(line))));
+            }
+            if self.read()? > 0 {
+                continue;
+            }
+            if self.rotated() {
+                self.open()?;
+                continue;
+            }
+            // Wait for the next check. Incomplete lines stay buffered
+            try_ready!(self
+                .interval
+                .poll()
+                .map_err(|e| format_err!("Timer error: {}", e)));
+        }
+    }
+}
+
+/// Expand the wildcards * and ? in the file name of pattern e.g a quoted
+/// "trace*". Matches are sorted by name. Patterns without wildcards and
+/// existing files are passed as is
+fn expand_glob(pattern: &str) -> Result<Vec<PathBuf>, Error> {
+    let path = PathBuf::from(pattern);
+    let name = match path.file_name().and_then(|n| n.to_str()) {
+        Some(n) if (n.contains('*') || n.contains('?')) && !path.exists() => n.to_owned(),
+        _ => return Ok(vec![path]),
+    };
+    let dir = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let regex = name
+        .chars()
+        .map(|c| match c {
+            '*' => ".*".to_owned(),
+            '?' => ".".to_owned(),
+            c => regex::escape(&c.to_string()),
+        })
+        .collect::<String>();
+    let regex = Regex::new(&format!("^{}$", regex))?;
+    let mut files = std::fs::read_dir(&dir)
+        .map_err(|e| format_err!("Failed to read {}: {}", dir.display(), e))?
+        .filter_map(Result::ok)
+        .filter(|e| {
+            e.file_name()
+                .to_str()
+                // Like shells hidden files match only patterns starting with a dot
+                .map(|n| regex.is_match(n) && (!n.starts_with('.') || name.starts_with('.')))
+                .unwrap_or(false)
+        })
+        .map(|e| dir.join(e.file_name()))
+        .collect::<Vec<_>>();
+    if files.is_empty() {
+        return Err(format_err!("No files match {}", pattern));
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Maximum line length from the configuration file or the default of 1 MiB
+fn max_line_length() -> usize {
+    config_get("max_line_length").unwrap_or(DEFAULT_MAX_LINE_LENGTH)
+}
+
+/// Codec for the lines of a source. Escape sequences are removed with
+/// strip_ansi
+fn line_codec(strip_ansi: bool) -> LossyLinesCodec {
+    LossyLinesCodec::new_with_max_length(max_line_length()).with_strip_ansi(strip_ansi)
+}
+
 /// Open stdin and provide a stream of lines
-pub fn stdin() -> LogStream {
-    let s = FramedRead::new(tokio::io::stdin(), LossyLinesCodec::new())
+pub fn stdin(strip_ansi: bool) -> LogStream {
+    let s = FramedRead::new(tokio::io::stdin(), line_codec(strip_ansi))
         .map_err(Into::into)
         .map(StreamData::Line);
     Box::new(s)
@@ -123,13 +456,13 @@ pub fn can(dev: &str) -> Result<LogStream, Error> {
 }
 
 /// Connect to tcp socket and profile a stream of lines
-pub fn tcp(addr: &Url) -> Result<LogStream, Error> {
+pub fn tcp(addr: &Url, strip_ansi: bool) -> Result<LogStream, Error> {
     let addr = addr
         .to_socket_addrs()?
         .next()
         .ok_or_else(|| err_msg("Failed to parse addr"))?;
     let s = TcpStream::connect(&addr)
-        .map(|s| Decoder::framed(LossyLinesCodec::new(), s))
+        .map(move |s| Decoder::framed(line_codec(strip_ansi), s))
         .flatten_stream()
         .map_err(|e| format_err!("Failed to connect: {}", e))
         .map(StreamData::Line);
@@ -137,54 +470,547 @@ pub fn tcp(addr: &Url) -> Result<LogStream, Error> {
     Ok(Box::new(s))
 }
 
+/// Lines of all clients connected to a tcp listener
+struct Listener {
+    listener: TcpListener,
+    clients: VecDeque<(SocketAddr, FramedRead<TcpStream, LossyLinesCodec>)>,
+    /// Remove escape sequences from the lines of the clients
+    strip_ansi: bool,
+}
+
+/// Listen for tcp connections and provide a stream of lines of all clients
+pub fn listen(addr: &str, strip_ansi: bool) -> Result<LogStream, Error> {
+    let addr = addr
+        .to_socket_addrs()
+        .map_err(|e| format_err!("Invalid listen address {}: {}", addr, e))?
+        .next()
+        .ok_or_else(|| format_err!("Invalid listen address {}", addr))?;
+    let listener =
+        TcpListener::bind(&addr).map_err(|e| format_err!("Failed to listen on {}: {}", addr, e))?;
+    Ok(Box::new(Listener::new(listener, strip_ansi)))
+}
+
+impl Listener {
+    fn new(listener: TcpListener, strip_ansi: bool) -> Listener {
+        Listener {
+            listener,
+            clients: VecDeque::new(),
+            strip_ansi,
+        }
+    }
+}
+
+impl Stream for Listener {
+    type Item = StreamData;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<StreamData>, Error> {
+        loop {
+            match self.listener.poll_accept() {
+                Ok(Async::Ready((stream, addr))) => {
+                    status(&format!("Accepted connection from {}", addr));
+                    let codec = line_codec(self.strip_ansi);
+                    self.clients
+                        .push_back((addr, FramedRead::new(stream, codec)));
+                }
+                Ok(Async::NotReady) => break,
+                Err(e) => return Err(format_err!("Failed to accept connection: {}", e)),
+            }
+        }
+
+        // Poll the clients round robin to not starve any of them
+        for _ in 0..self.clients.len() {
+            let (addr, mut client) = self.clients.pop_front().expect("Missing client");
+            match client.poll() {
+                Ok(Async::Ready(Some(line))) => {
+                    self.clients.push_back((addr, client));
+                    return Ok(Async::Ready(Some(StreamData::Line(line))));
+                }
+                Ok(Async::Ready(None)) => status(&format!("{} disconnected", addr)),
+                Err(e) => status(&format!("{} disconnected: {}", addr, e)),
+                Ok(Async::NotReady) => self.clients.push_back((addr, client)),
+            }
+        }
+
+        Ok(Async::NotReady)
+    }
+}
+
 /// Start a process and stream it stdout
 pub fn logcat(args: &ArgMatches) -> Result<LogStream, Error> {
-    let mut cmd = vec![adb()?.display().to_string()];
+    logcat_stream(adb_argv(args)?, args)
+}
+
+/// Logcat of the device selected with the adb argv. With --show-buffer one
+/// process per buffer is started and the records are labeled with the buffer
+fn logcat_stream(adb: Vec<String>, args: &ArgMatches) -> Result<LogStream, Error> {
+    if !args.is_present("show_buffer") {
+        return Ok(Box::new(logcat_process(adb, args, None)?));
+    }
+    let buffers = concrete_buffers(buffers(args)?)
+        .into_iter()
+        .map(|buffer| {
+            let process = logcat_process(adb.clone(), args, Some(&buffer))?;
+            Ok((buffer, Box::new(process) as LogStream))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok(Box::new(Merged::new(buffers, Label::Buffer)))
+}
+
+/// Logcat process of the device selected with the adb argv. Reads the given
+/// buffer or the buffers selected in args
+fn logcat_process(
+    adb: Vec<String>,
+    args: &ArgMatches,
+    buffer: Option<&str>,
+) -> Result<Process, Error> {
+    let mut cmd = adb.clone();
     cmd.push("logcat".into());
-    let mut respawn = args.is_present("restart") | config_get::<bool>("restart").unwrap_or(true);
+    let mut respawn = restart(args, config_get::<bool>("restart").unwrap_or(true));
 
-    if args.is_present("tail") {
-        let count = value_t!(args, "tail", u32).unwrap_or_else(|e| e.exit());
-        cmd.push("-t".into());
-        cmd.push(count.to_string());
-        respawn = false;
-    };
+    if let Some(logcat_args) = args.value_of("logcat_args") {
+        // Replace the generated arguments. Logcat exits after -d and -t
+        let logcat_args = split_command(logcat_args)?;
+        if logcat_args.iter().any(|a| a == "-d" || a.starts_with("-t")) {
+            respawn = false;
+        }
+        cmd.extend(logcat_args);
+    } else {
+        if args.is_present("tail") {
+            let count = value_t!(args, "tail", u32).unwrap_or_else(|e| e.exit());
+            cmd.push("-t".into());
+            cmd.push(count.to_string());
+            respawn = false;
+        };
+
+        if args.is_present("dump") {
+            cmd.push("-d".into());
+            respawn = false;
+        }
+
+        // The time is passed as a single argument and never split. A restart
+        // would print all records since the time again
+        if let Some(time) = start_time(args)? {
+            cmd.push("-T".into());
+            cmd.push(time);
+            respawn = false;
+        }
+
+        let buffers = match buffer {
+            Some(buffer) => vec![buffer.to_owned()],
+            None => buffers(args)?,
+        };
+        for buffer in buffers {
+            cmd.push("-b".into());
+            cmd.push(buffer);
+        }
 
-    if args.is_present("dump") {
-        cmd.push("-d".into());
-        respawn = false;
+        // Logcat takes a single --pid only. Multiple pids and devices without
+        // support are filtered on the host. The host filter stays in place
+        // either way
+        if let Some(pid) = server_pid(args) {
+            if logcat_supports_pid(&adb) {
+                cmd.push(format!("--pid={}", pid));
+            }
+        }
+    }
+
+    let mut wait_for_device = adb;
+    wait_for_device.push("wait-for-device".into());
+    let mut process = Process::with_cmd(cmd, respawn, restart_backoff(args));
+    process.wait_for_device = Some(wait_for_device);
+    process.merge_stderr = args.is_present("merge_stderr");
+    process.read_buffer = read_buffer(args)?;
+    process.restart_marker = !args.is_present("no_restart_marker");
+    process.connection_events = args.is_present("connection_events");
+    process.strip_ansi = args.is_present("strip_ansi");
+    Ok(process)
+}
+
+/// Pid to pass to logcat if exactly one is given with --pid
+fn server_pid(args: &ArgMatches) -> Option<String> {
+    let mut pids = args.values_of("pid")?;
+    match (pids.next(), pids.next()) {
+        (Some(pid), None) => Some(pid.to_owned()),
+        _ => None,
+    }
+}
+
+/// Check the usage of logcat on the device for the --pid option. Each device
+/// is checked once e.g for all buffers of --show-buffer. A failed check is
+/// not remembered and pids are filtered on the host
+fn logcat_supports_pid(adb: &[String]) -> bool {
+    let mut checked = PID_SUPPORT.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(supported) = checked.get(adb) {
+        return *supported;
+    }
+    let output = Command::new(&adb[0])
+        .args(&adb[1..])
+        .args(["shell", "logcat", "--help"])
+        .stdin(Stdio::null())
+        .output();
+    match output {
+        Ok(ref output) if output.status.success() => {
+            let supported = String::from_utf8_lossy(&output.stdout).contains("--pid")
+                || String::from_utf8_lossy(&output.stderr).contains("--pid");
+            checked.insert(adb.to_vec(), supported);
+            supported
+        }
+        _ => {
+            status("Failed to check logcat of the device for --pid. Filtering pids in rogcat");
+            false
+        }
+    }
+}
+
+/// Validate the time passed with --start-time
+fn start_time(args: &ArgMatches) -> Result<Option<String>, Error> {
+    match args.value_of("start_time") {
+        Some(t) => {
+            if strptime(t, "%m-%d %H:%M:%S.%f").is_ok() || strptime(t, "%m-%d %H:%M:%S").is_ok() {
+                Ok(Some(t.to_owned()))
+            } else {
+                Err(format_err!(
+                    "Invalid start time: {}. Use \"MM-DD hh:mm:ss.mmm\"",
+                    t
+                ))
+            }
+        }
+        None => Ok(None),
+    }
+}
+
+/// Serials of the devices listed as available by "adb devices"
+fn device_serials(args: &ArgMatches) -> Result<Vec<String>, Error> {
+    let output = Command::new(adb(args)?)
+        .arg("devices")
+        .output()
+        .map_err(|e| format_err!("Failed to run adb devices: {}", e))?;
+    let serials = parse_devices(&String::from_utf8_lossy(&output.stdout))
+        .into_iter()
+        .filter(|d| d.state == "device")
+        .map(|d| d.serial)
+        .collect();
+    Ok(serials)
+}
+
+/// Logcat of multiple devices or buffers. Each child is restarted
+/// independently and unplugged devices just stop contributing. Lines are
+/// forwarded with their origin and parsed like the lines of other sources
+struct Merged {
+    children: VecDeque<(String, LogStream)>,
+    label: Label,
+}
+
+/// Device and buffer of a line of a merged logcat
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Origin {
+    pub device: Option<String>,
+    pub buffer: Option<String>,
+}
+
+impl Origin {
+    /// Prefix raw with the device serial and buffer
+    pub fn prefix(&self, raw: &str) -> String {
+        let mut prefixed = String::new();
+        for name in self.device.iter().chain(self.buffer.iter()) {
+            prefixed.push_str(&format!("[{}] ", name));
+        }
+        prefixed.push_str(raw);
+        prefixed
     }
 
-    for buffer in args
+    /// Label record with the device serial and buffer. The raw line is
+    /// prefixed for the formats without a device or buffer column
+    pub fn label(&self, mut record: Record) -> Record {
+        record.raw = self.prefix(&record.raw);
+        if self.device.is_some() {
+            record.device = self.device.clone();
+        }
+        if self.buffer.is_some() {
+            record.buffer = self.buffer.clone();
+        }
+        record
+    }
+}
+
+/// What the name of a child of `Merged` is
+#[derive(Clone, Copy)]
+enum Label {
+    /// Serial with --all-devices
+    Device,
+    /// Logd buffer with --show-buffer
+    Buffer,
+}
+
+/// Start logcat on all connected devices and merge the records
+pub fn all_devices(args: &ArgMatches) -> Result<LogStream, Error> {
+    let serials = device_serials(args)?;
+    if serials.is_empty() {
+        return Err(err_msg("No device found"));
+    }
+    let adb = adb(args)?.display().to_string();
+    let devices = serials
+        .into_iter()
+        .map(|serial| {
+            let argv = vec![adb.clone(), "-s".to_owned(), serial.clone()];
+            let stream = logcat_stream(argv, args)?;
+            Ok((serial, stream))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok(Box::new(Merged::new(devices, Label::Device)))
+}
+
+impl Merged {
+    fn new(children: Vec<(String, LogStream)>, label: Label) -> Merged {
+        Merged {
+            children: children.into_iter().collect(),
+            label,
+        }
+    }
+
+    /// Add the device serial or buffer of a child to origin
+    fn origin(&self, name: &str, mut origin: Origin) -> Origin {
+        match self.label {
+            Label::Device => origin.device = Some(name.to_owned()),
+            Label::Buffer => origin.buffer = Some(name.to_owned()),
+        }
+        origin
+    }
+}
+
+impl Stream for Merged {
+    type Item = StreamData;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<StreamData>, Error> {
+        // Poll the children round robin to not starve any of them
+        for _ in 0..self.children.len() {
+            let (name, mut stream) = self.children.pop_front().expect("Missing child");
+            match stream.poll() {
+                Ok(Async::Ready(Some(data))) => {
+                    let data = match data {
+                        StreamData::Line(line) => {
+                            StreamData::Labeled(self.origin(&name, Origin::default()), line)
+                        }
+                        // Lines of the buffers of a device
+                        StreamData::Labeled(origin, line) => {
+                            StreamData::Labeled(self.origin(&name, origin), line)
+                        }
+                        StreamData::Record(record) => {
+                            StreamData::Record(self.origin(&name, Origin::default()).label(record))
+                        }
+                        StreamData::EndOfFile => StreamData::EndOfFile,
+                    };
+                    self.children.push_back((name, stream));
+                    return Ok(Async::Ready(Some(data)));
+                }
+                // Buffers end together with the logcat of the device e.g
+                // with --dump
+                Ok(Async::Ready(None)) => {
+                    if let Label::Device = self.label {
+                        status(&format!("{} disconnected", name))
+                    }
+                }
+                Err(e) => status(&format!("{} disconnected: {}", name, e)),
+                Ok(Async::NotReady) => self.children.push_back((name, stream)),
+            }
+        }
+
+        if self.children.is_empty() {
+            Ok(Async::Ready(None))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+/// Logd buffers selected by args, config or default
+pub fn buffers(args: &ArgMatches) -> Result<Vec<String>, Error> {
+    let buffers = args
         .values_of("buffer")
         .map(|m| m.map(ToOwned::to_owned).collect::<Vec<String>>())
         .or_else(|| config_get("buffer"))
-        .unwrap_or_else(|| DEFAULT_BUFFER.iter().map(|&s| s.to_owned()).collect())
-    {
+        .unwrap_or_else(|| DEFAULT_BUFFER.iter().map(|&s| s.to_owned()).collect());
+
+    if let Some(invalid) = buffers.iter().find(|b| !BUFFERS.contains(&b.as_str())) {
+        return Err(format_err!(
+            "Invalid buffer {}. Valid buffers are {}",
+            invalid,
+            BUFFERS.join(", ")
+        ));
+    }
+
+    Ok(buffers)
+}
+
+/// Replace "all" with the buffers it stands for. Each buffer is listed once
+/// e.g for "-b all main"
+fn concrete_buffers(buffers: Vec<String>) -> Vec<String> {
+    let mut concrete: Vec<String> = Vec::new();
+    for buffer in buffers {
+        let expanded = if buffer == "all" {
+            BUFFERS
+                .iter()
+                .filter(|b| **b != "all")
+                .map(|b| (*b).to_owned())
+                .collect()
+        } else {
+            vec![buffer]
+        };
+        for buffer in expanded {
+            if !concrete.contains(&buffer) {
+                concrete.push(buffer);
+            }
+        }
+    }
+    concrete
+}
+
+/// Clear the selected logd buffers and wait for adb to finish
+pub fn clear(args: &ArgMatches) -> Result<(), Error> {
+    let mut cmd = adb_argv(args)?;
+    cmd.push("logcat".into());
+    cmd.push("-c".into());
+    for buffer in buffers(args)? {
         cmd.push("-b".into());
         cmd.push(buffer);
     }
 
-    Ok(Box::new(Process::with_cmd(cmd, respawn)))
+    let status = Command::new(&cmd[0])
+        .args(&cmd[1..])
+        .status()
+        .map_err(|e| format_err!("Failed to run {}: {}", cmd[0], e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(ExitError {
+            cmd: cmd.join(" "),
+            status,
+        }
+        .into())
+    }
+}
+
+/// Device connected with `adb connect`. Disconnected when dropped
+pub struct Connection {
+    adb: PathBuf,
+    addr: String,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        Command::new(&self.adb)
+            .args(["disconnect", &self.addr])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .ok();
+    }
+}
+
+/// Check the output of `adb connect` for a successful connection
+fn connected(output: &str) -> bool {
+    output
+        .lines()
+        .any(|l| l.starts_with("connected to") || l.starts_with("already connected to"))
+}
+
+/// Connect to a device over tcp if requested with --connect
+pub fn connect(args: &ArgMatches) -> Result<Option<Connection>, Error> {
+    let addr = match args.value_of("connect") {
+        Some(addr) => addr.to_owned(),
+        None => return Ok(None),
+    };
+    let adb = adb(args)?;
+    let output = Command::new(&adb)
+        .args(["connect", &addr])
+        .output()
+        .map_err(|e| format_err!("Failed to run {}: {}", adb.display(), e))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if connected(&stdout) {
+        Ok(Some(Connection { adb, addr }))
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(format_err!(
+            "Failed to connect to {}: {}",
+            addr,
+            format!("{}{}", stdout, stderr).trim()
+        ))
+    }
 }
 
 /// Start a process and stream it stdout
 pub fn process(args: &ArgMatches) -> Result<LogStream, Error> {
-    let respawn = args.is_present("restart");
-    let cmd = value_t!(args, "COMMAND", String)?
-        .split_whitespace()
-        .map(ToOwned::to_owned)
-        .collect();
-    Ok(Box::new(Process::with_cmd(cmd, respawn)))
+    let respawn = restart(args, false);
+    // Only the command of the user is expanded. Generated commands are not
+    let cmd = value_t!(args, "COMMAND", String)?;
+    let cmd = if args.is_present("no_env_expand") {
+        cmd
+    } else {
+        expand_env(&cmd)
+    };
+    let cmd = split_command(&cmd)?;
+    if cmd.is_empty() {
+        return Err(err_msg("Invalid command"));
+    }
+    let mut process = Process::with_cmd(cmd, respawn, restart_backoff(args));
+    process.merge_stderr = args.is_present("merge_stderr");
+    process.read_buffer = read_buffer(args)?;
+    process.restart_marker = !args.is_present("no_restart_marker");
+    process.connection_events = args.is_present("connection_events");
+    process.strip_ansi = args.is_present("strip_ansi");
+    Ok(Box::new(process))
+}
+
+/// Restart commands on exit. --no-restart wins over --restart which wins over
+/// the default of the source
+fn restart(args: &ArgMatches, default: bool) -> bool {
+    !args.is_present("no_restart") && (args.is_present("restart") || default)
+}
+
+/// Capacity of the command output readers from args
+fn read_buffer(args: &ArgMatches) -> Result<usize, Error> {
+    match args.value_of("read_buffer") {
+        Some(size) => parse_size(size)
+            .filter(|s| *s > 0)
+            .ok_or_else(|| format_err!("Invalid read buffer size: {}", size)),
+        None => Ok(DEFAULT_READ_BUFFER),
+    }
+}
+
+/// Initial restart delay from args or config
+fn restart_backoff(args: &ArgMatches) -> Duration {
+    if args.is_present("restart_backoff") {
+        let ms = value_t!(args, "restart_backoff", u64).unwrap_or_else(|e| e.exit());
+        Duration::from_millis(ms)
+    } else {
+        config_get::<u64>("restart_backoff")
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_RESTART_BACKOFF)
+    }
 }
 
 impl Process {
-    fn with_cmd(cmd: Vec<String>, respawn: bool) -> Process {
+    fn with_cmd(cmd: Vec<String>, respawn: bool, backoff: Duration) -> Process {
         Process {
             cmd,
             respawn,
+            backoff: Backoff::new(backoff),
+            delay: None,
+            wait_for_device: None,
+            device_missing: false,
+            merge_stderr: false,
+            read_buffer: DEFAULT_READ_BUFFER,
+            restart_marker: false,
+            restarted: false,
+            connection_events: false,
+            strip_ansi: false,
+            queue: VecDeque::new(),
+            wait: None,
             child: None,
             stream: None,
+            exited: false,
         }
     }
 
@@ -195,21 +1021,69 @@ impl Process {
             .stderr(Stdio::piped())
             .spawn_async()?;
 
-        let stdout = BufReader::new(child.stdout().take().unwrap());
-        let stderr = BufReader::new(child.stderr().take().unwrap());
+        let stdout = self.reader(child.stdout().take().unwrap());
+        let stderr = self.reader(child.stderr().take().unwrap());
         self.child = Some(child);
 
-        let stdout = lossy_lines(stdout)
+        let stdout = lossy_lines(stdout, max_line_length())
+            .with_strip_ansi(self.strip_ansi)
             .map_err(Into::into)
-            .map(StreamData::Line);
-        let stderr = lossy_lines(stderr)
+            .map(Output::Stdout);
+        let stderr = lossy_lines(stderr, max_line_length())
+            .with_strip_ansi(self.strip_ansi)
             .map_err(Into::into)
-            .map(StreamData::Line);
+            .map(Output::Stderr);
+
+        self.stream = Some(Box::new(stdout.select(stderr)));
+
+        if self.restarted {
+            self.restarted = false;
+            if self.restart_marker {
+                self.queue.push_back(StreamData::Record(restart_marker()));
+            }
+        }
+        self.poll()
+    }
+
+    /// Buffered reader for the output of the child
+    fn reader<R: Read>(&self, inner: R) -> BufReader<R> {
+        BufReader::with_capacity(self.read_buffer, inner)
+    }
 
-        let mut stream = stdout.select(stderr);
-        let poll = stream.poll();
-        self.stream = Some(Box::new(stream));
-        poll
+    /// Wait for the child to exit after its output ended
+    fn poll_exit(&mut self) -> Result<Async<Option<StreamData>>, Error> {
+        if let Some(ref mut child) = self.child {
+            let status = try_ready!(child.poll());
+            self.child = None;
+            self.exited = true;
+            // Commands killed by a signal e.g on ctrl-c just end the stream
+            if status.code().map(|c| c != 0).unwrap_or(false) {
+                return Err(ExitError {
+                    cmd: self.cmd.join(" "),
+                    status,
+                }
+                .into());
+            }
+        }
+        self.exited = true;
+        Ok(Async::Ready(None))
+    }
+
+    /// Spawn the wait for device command if the last run failed because of a missing device
+    fn spawn_wait_for_device(&mut self) -> Result<bool, Error> {
+        match self.wait_for_device {
+            Some(ref cmd) if self.device_missing => {
+                status("Waiting for device");
+                let wait = Command::new(cmd[0].clone())
+                    .args(&cmd[1..])
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .status_async()?;
+                self.wait = Some(wait);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
     }
 }
 
@@ -218,13 +1092,645 @@ impl Stream for Process {
     type Error = Error;
 
     fn poll(&mut self) -> Result<Async<Option<Self::Item>>, Self::Error> {
+        if let Some(data) = self.queue.pop_front() {
+            return Ok(Async::Ready(Some(data)));
+        }
+
+        if self.exited {
+            return Ok(Async::Ready(None));
+        }
+
+        if let Some(ref mut delay) = self.delay {
+            try_ready!(delay.poll());
+            self.delay = None;
+            return self.spawn();
+        }
+
+        if let Some(ref mut wait) = self.wait {
+            try_ready!(wait.poll());
+            self.wait = None;
+            self.device_missing = false;
+            self.backoff.reset();
+            if self.connection_events {
+                self.queue.push_back(connection_event("device reconnected"));
+            }
+            return self.spawn();
+        }
+
         if let Some(ref mut inner) = self.stream {
-            match inner.poll() {
-                Ok(Async::Ready(None)) if self.respawn => self.spawn(),
-                poll => poll,
+            let mut next = try_ready!(inner.poll());
+            // Diagnostics on stderr are printed as status unless merged into the records
+            while let Some(Output::Stderr(ref line)) = next {
+                if device_missing(line) {
+                    if !self.device_missing && self.connection_events {
+                        self.queue
+                            .push_back(connection_event("device disconnected"));
+                    }
+                    self.device_missing = true;
+                }
+                if self.merge_stderr {
+                    break;
+                }
+                status(line);
+                if !self.queue.is_empty() {
+                    return self.poll();
+                }
+                next = try_ready!(inner.poll());
             }
+
+            match next {
+                Some(Output::Stdout(line)) => {
+                    self.backoff.reset();
+                    Ok(Async::Ready(Some(StreamData::Line(line))))
+                }
+                Some(Output::Stderr(line)) => Ok(Async::Ready(Some(StreamData::Line(line)))),
+                None if self.respawn => {
+                    self.stream = None;
+                    self.child = None;
+                    self.restarted = true;
+                    if self.spawn_wait_for_device()? {
+                        return self.poll();
+                    }
+
+                    let delay = self.backoff.next();
+                    status(&format!(
+                        "Restarting \"{}\" in {}ms",
+                        self.cmd.join(" "),
+                        delay.as_millis()
+                    ));
+                    self.delay = Some(Delay::new(Instant::now() + delay));
+                    self.poll()
+                }
+                None => {
+                    self.stream = None;
+                    self.poll_exit()
+                }
+            }
+        } else if self.child.is_some() {
+            self.poll_exit()
         } else {
             self.spawn()
         }
     }
 }
+
+#[test]
+fn backoff_doubles() {
+    let ms = Duration::from_millis;
+    let mut backoff = Backoff::new(ms(100));
+    let delays = (0..8).map(|_| backoff.next()).collect::<Vec<_>>();
+    assert_eq!(
+        delays,
+        vec![
+            ms(100),
+            ms(200),
+            ms(400),
+            ms(800),
+            ms(1600),
+            ms(3200),
+            MAX_RESTART_BACKOFF,
+            MAX_RESTART_BACKOFF
+        ]
+    );
+    backoff.reset();
+    assert_eq!(backoff.next(), ms(100));
+
+    // An initial delay above the cap is kept
+    let mut backoff = Backoff::new(Duration::from_secs(10));
+    assert_eq!(backoff.next(), Duration::from_secs(10));
+    assert_eq!(backoff.next(), Duration::from_secs(10));
+}
+
+#[test]
+#[cfg(unix)]
+fn process_head() {
+    let cmd = vec![
+        "sh".to_owned(),
+        "-c".to_owned(),
+        "for i in 1 2 3 4; do echo $i; done; exec sleep 60".to_owned(),
+    ];
+    let start = Instant::now();
+    let process = Process::with_cmd(cmd, false, DEFAULT_RESTART_BACKOFF);
+    let lines = tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(process.take(3).collect())
+        .unwrap();
+    assert_eq!(lines.len(), 3);
+    assert!(start.elapsed() < Duration::from_secs(10));
+}
+
+#[test]
+#[cfg(unix)]
+fn process_killed_on_drop() {
+    // The shell prints its pid and is replaced by sleep
+    let cmd = vec![
+        "sh".to_owned(),
+        "-c".to_owned(),
+        "echo $$; exec sleep 60".to_owned(),
+    ];
+    let process = Process::with_cmd(cmd, false, DEFAULT_RESTART_BACKOFF);
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    let (pid, process) = runtime
+        .block_on(process.into_future())
+        .map_err(|(e, _)| e)
+        .unwrap();
+    let pid = match pid {
+        Some(StreamData::Line(pid)) => pid,
+        _ => panic!("Missing pid"),
+    };
+    // Terminated children may be zombies until they are reaped
+    let running = || {
+        let output = Command::new("ps")
+            .args(["-o", "stat=", "-p", &pid])
+            .output()
+            .unwrap();
+        let stat = String::from_utf8_lossy(&output.stdout);
+        !stat.trim().is_empty() && !stat.trim().starts_with('Z')
+    };
+    assert!(running());
+    // The stream is dropped when the output ends e.g on ctrl-c
+    drop(process);
+
+    let start = Instant::now();
+    while running() && start.elapsed() < Duration::from_secs(5) {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    assert!(!running(), "Child {} is still running", pid);
+}
+
+#[test]
+#[cfg(unix)]
+fn process_exit_status() {
+    let cmd = vec![
+        "sh".to_owned(),
+        "-c".to_owned(),
+        "echo 1; exit 2".to_owned(),
+    ];
+    let process = Process::with_cmd(cmd, false, DEFAULT_RESTART_BACKOFF);
+    let e = tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(process.collect())
+        .unwrap_err();
+    assert_eq!(exit_code(&e), 2);
+    assert!(e
+        .to_string()
+        .starts_with("Command \"sh -c echo 1; exit 2\" failed"));
+
+    let cmd = vec!["true".to_owned()];
+    let process = Process::with_cmd(cmd, false, DEFAULT_RESTART_BACKOFF);
+    let lines = tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(process.collect())
+        .unwrap();
+    assert!(lines.is_empty());
+}
+
+#[test]
+#[cfg(unix)]
+fn process_restart_marker() {
+    let cmd = vec!["echo".to_owned(), "line".to_owned()];
+    let mut process = Process::with_cmd(cmd, true, Duration::from_millis(1));
+    process.restart_marker = true;
+    let lines = tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(process.take(5).collect())
+        .unwrap()
+        .into_iter()
+        .map(|l| match l {
+            StreamData::Line(l) => l,
+            StreamData::Record(r) => r.raw,
+            StreamData::Labeled(_, _) | StreamData::EndOfFile => unreachable!(),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        lines,
+        vec![
+            "line",
+            "--- reconnected ---",
+            "line",
+            "--- reconnected ---",
+            "line"
+        ]
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn process_connection_events() {
+    let cmd = vec![
+        "sh".to_owned(),
+        "-c".to_owned(),
+        "echo 'error: no devices/emulators found' >&2; exit 1".to_owned(),
+    ];
+    let mut process = Process::with_cmd(cmd, true, Duration::from_millis(1));
+    process.wait_for_device = Some(vec!["true".to_owned()]);
+    process.connection_events = true;
+    let records = tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(process.take(3).collect())
+        .unwrap()
+        .into_iter()
+        .map(|l| match l {
+            StreamData::Record(r) => (r.level, r.tag, r.message),
+            StreamData::Line(l) | StreamData::Labeled(_, l) => panic!("Unexpected line {}", l),
+            StreamData::EndOfFile => panic!("Unexpected end of file"),
+        })
+        .collect::<Vec<_>>();
+    let event = |message: &str| (Level::Error, "rogcat".to_owned(), message.to_owned());
+    assert_eq!(
+        records,
+        vec![
+            event("device disconnected"),
+            event("device reconnected"),
+            event("device disconnected")
+        ]
+    );
+}
+
+#[test]
+fn connect_output() {
+    assert!(connected("connected to 192.168.1.2:5555\n"));
+    assert!(connected("already connected to 192.168.1.2:5555\n"));
+    assert!(!connected(
+        "unable to connect to 192.168.1.2:5555: Connection refused\n"
+    ));
+    assert!(!connected(
+        "failed to connect to '192.168.1.2:5555': Connection refused\n"
+    ));
+}
+
+#[test]
+fn listen_clients() {
+    use std::{io::Write, net};
+
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let clients = (0..2)
+        .map(|i| {
+            std::thread::spawn(move || {
+                let mut client = net::TcpStream::connect(addr).unwrap();
+                writeln!(client, "client {} line 1\nclient {} line 2", i, i).unwrap();
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let lines = tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(Listener::new(listener, false).take(4).collect())
+        .unwrap();
+    clients.into_iter().for_each(|c| c.join().unwrap());
+
+    let mut lines = lines
+        .into_iter()
+        .map(|l| match l {
+            StreamData::Line(l) => l,
+            _ => panic!("Unexpected record"),
+        })
+        .collect::<Vec<_>>();
+    lines.sort();
+    assert_eq!(
+        lines,
+        vec![
+            "client 0 line 1",
+            "client 0 line 2",
+            "client 1 line 1",
+            "client 1 line 2"
+        ]
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn process_read_buffer() {
+    use crate::cli::args;
+    let mut process = Process::with_cmd(vec!["true".to_owned()], false, Duration::from_millis(1));
+    assert_eq!(process.reader(std::io::empty()).capacity(), 64 * 1024);
+    process.read_buffer = read_buffer(&args(&["rogcat", "--read-buffer", "1M"])).unwrap();
+    assert_eq!(process.reader(std::io::empty()).capacity(), 1_000_000);
+    assert!(read_buffer(&args(&["rogcat", "--read-buffer", "0"])).is_err());
+}
+
+#[test]
+fn files_concatenated() {
+    use std::fs::write;
+
+    let dir = crate::utils::TempDir::new("files");
+    write(dir.join("log.1"), "one\ntwo\n").unwrap();
+    write(dir.join("log.2"), "three\n").unwrap();
+    let pattern = dir.join("log.?");
+    let args = crate::cli::args(&[
+        "rogcat",
+        "-i",
+        dir.join("log.2").to_str().unwrap(),
+        "-i",
+        pattern.to_str().unwrap(),
+    ]);
+    let items = tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(files(&args).unwrap().collect())
+        .unwrap()
+        .into_iter()
+        .map(|d| match d {
+            StreamData::Line(l) => l,
+            StreamData::EndOfFile => "EOF".to_owned(),
+            StreamData::Record(_) | StreamData::Labeled(_, _) => panic!("Unexpected record"),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        items,
+        vec!["three", "EOF", "one", "two", "EOF", "three", "EOF"]
+    );
+    assert!(expand_glob(dir.join("none*").to_str().unwrap()).is_err());
+}
+
+#[test]
+fn logcat_start_time() {
+    use crate::cli::args;
+    let process = logcat_process(
+        vec!["adb".to_owned()],
+        &args(&["rogcat", "--start-time", "03-14 10:00:00.123"]),
+        None,
+    )
+    .unwrap();
+    let n = process.cmd.iter().position(|a| a == "-T").unwrap();
+    assert_eq!(process.cmd[n + 1], "03-14 10:00:00.123");
+    assert!(!process.respawn);
+    let process = logcat_process(vec!["adb".to_owned()], &args(&["rogcat"]), None).unwrap();
+    assert!(process.respawn);
+
+    let args = crate::cli::args(&["rogcat", "--start-time", "10:00"]);
+    assert!(logcat_process(vec!["adb".to_owned()], &args, None).is_err());
+}
+
+#[test]
+fn logcat_args_replace() {
+    use crate::cli::args;
+    let adb = vec![
+        "adb".to_owned(),
+        "-s".to_owned(),
+        "emulator-5554".to_owned(),
+    ];
+    let process = logcat_process(
+        adb.clone(),
+        &args(&[
+            "rogcat",
+            "--logcat-args",
+            "-v threadtime -b 'main' -e \"a b\"",
+        ]),
+        None,
+    )
+    .unwrap();
+    assert_eq!(
+        process.cmd,
+        vec![
+            "adb",
+            "-s",
+            "emulator-5554",
+            "logcat",
+            "-v",
+            "threadtime",
+            "-b",
+            "main",
+            "-e",
+            "a b"
+        ]
+    );
+    assert!(process.respawn);
+
+    let process = logcat_process(
+        adb,
+        &args(&["rogcat", "--logcat-args", "-d -b crash"]),
+        None,
+    )
+    .unwrap();
+    assert!(!process.respawn);
+    assert!(crate::cli::cli()
+        .get_matches_from_safe(vec!["rogcat", "--logcat-args", "-d", "--buffer", "main"])
+        .is_err());
+}
+
+#[test]
+fn restart_precedence() {
+    use crate::cli::args;
+    assert!(restart(&args(&["rogcat"]), true));
+    assert!(!restart(&args(&["rogcat"]), false));
+    assert!(restart(&args(&["rogcat", "--restart"]), false));
+    assert!(!restart(&args(&["rogcat", "--no-restart"]), true));
+    assert!(!restart(
+        &args(&["rogcat", "--restart", "--no-restart"]),
+        true
+    ));
+}
+
+#[test]
+fn devices_merge() {
+    use futures::stream::iter_result;
+
+    let line = |l: &str| Ok(StreamData::Line(l.to_owned()));
+    let a = iter_ok(vec![
+        StreamData::Line("03-01 02:19:45.207     1     2 I A: one".to_owned()),
+        StreamData::Line("03-01 02:19:45.208     1     2 I A: two".to_owned()),
+    ]);
+    // The second device is unplugged after the first line
+    let b = iter_result(vec![
+        line("03-01 02:19:45.209     3     4 W B: one"),
+        Err(err_msg("adb exited")),
+        line("03-01 02:19:45.210     3     4 W B: two"),
+    ]);
+    let devices = Merged::new(
+        vec![
+            ("a".to_owned(), Box::new(a) as LogStream),
+            ("b".to_owned(), Box::new(b) as LogStream),
+        ],
+        Label::Device,
+    );
+
+    let mut parser = rogcat::parser::Parser::default();
+    let records = devices
+        .collect()
+        .wait()
+        .unwrap()
+        .into_iter()
+        .map(|d| match d {
+            StreamData::Labeled(origin, line) => {
+                let r = origin.label(parser.parse(&line));
+                (r.device.unwrap(), r.tag, r.message, r.raw)
+            }
+            _ => panic!("Unexpected item"),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        records,
+        vec![
+            (
+                "a".to_owned(),
+                "A".to_owned(),
+                "one".to_owned(),
+                "[a] 03-01 02:19:45.207     1     2 I A: one".to_owned()
+            ),
+            (
+                "b".to_owned(),
+                "B".to_owned(),
+                "one".to_owned(),
+                "[b] 03-01 02:19:45.209     3     4 W B: one".to_owned()
+            ),
+            (
+                "a".to_owned(),
+                "A".to_owned(),
+                "two".to_owned(),
+                "[a] 03-01 02:19:45.208     1     2 I A: two".to_owned()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn buffers_all_expanded() {
+    let args = crate::cli::args(&["rogcat", "--show-buffer", "-b", "all", "main"]);
+    let buffers = concrete_buffers(buffers(&args).unwrap());
+    assert_eq!(
+        buffers,
+        vec!["crash", "events", "kernel", "main", "radio", "security", "stats", "system"]
+    );
+    let process = logcat_process(vec!["adb".to_owned()], &args, Some(&buffers[0])).unwrap();
+    assert_eq!(process.cmd, vec!["adb", "logcat", "-b", "crash"]);
+}
+
+#[test]
+fn buffers_merge() {
+    let args = crate::cli::args(&["rogcat", "-b", "main", "crash"]);
+    let process = logcat_process(vec!["adb".to_owned()], &args, Some("crash")).unwrap();
+    assert_eq!(process.cmd, vec!["adb", "logcat", "-b", "crash"]);
+
+    let main = iter_ok(vec![StreamData::Line(
+        "03-01 02:19:45.207     1     2 I A: main".to_owned(),
+    )]);
+    let crash = iter_ok(vec![StreamData::Line(
+        "03-01 02:19:45.208     1     2 F B: crash".to_owned(),
+    )]);
+    let buffers = Merged::new(
+        vec![
+            ("main".to_owned(), Box::new(main) as LogStream),
+            ("crash".to_owned(), Box::new(crash) as LogStream),
+        ],
+        Label::Buffer,
+    );
+    let mut parser = rogcat::parser::Parser::default();
+    let records = buffers
+        .collect()
+        .wait()
+        .unwrap()
+        .into_iter()
+        .map(|d| match d {
+            StreamData::Labeled(origin, line) => origin.label(parser.parse(&line)),
+            _ => panic!("Unexpected item"),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].buffer.as_deref(), Some("main"));
+    assert_eq!(records[0].message, "main");
+    assert_eq!(records[1].buffer.as_deref(), Some("crash"));
+    assert_eq!(records[1].device, None);
+    assert_eq!(
+        records[1].raw,
+        "[crash] 03-01 02:19:45.208     1     2 F B: crash"
+    );
+
+    // The buffers of a device with --all-devices
+    let main = iter_ok(vec![StreamData::Line("line".to_owned())]);
+    let buffers = Merged::new(
+        vec![("main".to_owned(), Box::new(main) as LogStream)],
+        Label::Buffer,
+    );
+    let devices = Merged::new(
+        vec![("a".to_owned(), Box::new(buffers) as LogStream)],
+        Label::Device,
+    );
+    match devices.wait().next().unwrap().unwrap() {
+        StreamData::Labeled(origin, line) => {
+            assert_eq!(origin.device.as_deref(), Some("a"));
+            assert_eq!(origin.buffer.as_deref(), Some("main"));
+            assert_eq!(origin.prefix(&line), "[a] [main] line");
+        }
+        _ => panic!("Unexpected item"),
+    }
+    let json = rogcat::record::Format::Json
+        .fmt_record(&records[1])
+        .unwrap();
+    assert!(json.contains("\"buffer\":\"crash\""));
+}
+
+#[test]
+fn follow_appended() {
+    use std::{fs::OpenOptions, io::Write, thread};
+    use tokio::runtime::Runtime;
+
+    let dir = crate::utils::TempDir::new("follow");
+    let path = dir.join("log");
+    std::fs::write(&path, "one\n").unwrap();
+    let writer = {
+        let path = path.clone();
+        thread::spawn(move || {
+            let append = |data: &str| {
+                thread::sleep(Duration::from_millis(300));
+                let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+                file.write_all(data.as_bytes()).unwrap();
+            };
+            // Incomplete lines are held back until the newline is written
+            append("tw");
+            append("o\n");
+            // Truncate and start over
+            thread::sleep(Duration::from_millis(300));
+            std::fs::write(&path, "three\n").unwrap();
+        })
+    };
+
+    let items = Runtime::new()
+        .unwrap()
+        .block_on(Follow::new(path.clone(), false).take(4).collect())
+        .unwrap()
+        .into_iter()
+        .map(|d| match d {
+            StreamData::Line(l) => l,
+            StreamData::Record(_) | StreamData::Labeled(_, _) => "record".to_owned(),
+            StreamData::EndOfFile => "EOF".to_owned(),
+        })
+        .collect::<Vec<_>>();
+    writer.join().unwrap();
+    assert_eq!(items, vec!["one", "two", "EOF", "three"]);
+}
+
+#[test]
+#[cfg(unix)]
+fn logcat_server_pid() {
+    use crate::cli::args;
+    // "echo --pid" stands in for an adb whose logcat usage lists --pid
+    let supported = vec!["echo".to_owned(), "--pid".to_owned()];
+    let process =
+        logcat_process(supported.clone(), &args(&["rogcat", "--pid", "42"]), None).unwrap();
+    assert_eq!(process.cmd[..3], ["echo", "--pid", "logcat"]);
+    assert_eq!(process.cmd.last().unwrap(), "--pid=42");
+
+    let process = logcat_process(supported, &args(&["rogcat", "--pid", "42", "43"]), None).unwrap();
+    assert!(!process.cmd.iter().any(|a| a.starts_with("--pid=")));
+
+    let process = logcat_process(
+        vec!["true".to_owned()],
+        &args(&["rogcat", "--pid", "42"]),
+        None,
+    )
+    .unwrap();
+    assert!(!process.cmd.iter().any(|a| a.starts_with("--pid=")));
+
+    // The device is checked once. Failed checks are repeated
+    let checked = |adb: &str| {
+        PID_SUPPORT
+            .lock()
+            .unwrap()
+            .get(&vec![adb.to_owned()])
+            .cloned()
+    };
+    assert_eq!(checked("true"), Some(false));
+    assert!(!logcat_supports_pid(&["false".to_owned()]));
+    assert_eq!(checked("false"), None);
+}
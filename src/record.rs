@@ -36,19 +36,165 @@ type StdResult<T, E> = std::result::Result<T, E>;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Format {
-    Csv,
+    /// Comma separated values with the optional columns that are enabled
+    Csv(CsvColumns),
+    /// Captured groups of --extract. The groups are stored in the raw field
+    Extract,
     Html,
     Human,
     Json,
+    /// Indented json with a blank line after each record. Selected with
+    /// `--format json --pretty`
+    JsonPretty,
+    /// Canonical logcat threadtime line rebuilt from the record fields
+    Logcat,
+    /// Key value pairs e.g `time="03-14 10:00:00.000" level=info tag=Foo msg=bar`
+    Logfmt,
     Raw,
+    /// User defined layout
+    Template(Template),
+}
+
+/// Record fields that can be used in a template
+#[derive(Clone, Debug, PartialEq)]
+enum Field {
+    Time,
+    Level,
+    Pid,
+    Tid,
+    Tag,
+    Message,
+    Raw,
+    /// Named capture group of an extract regex
+    Group(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Text(String),
+    Field(Field),
+}
+
+/// Output layout with placeholders for the record fields e.g
+/// "{time} {level} {tag}: {message}". Literal braces are written as "{{" and "}}"
+#[derive(Clone, Debug, PartialEq)]
+pub struct Template {
+    tokens: Vec<Token>,
+}
+
+impl FromStr for Template {
+    type Err = Error;
+
+    fn from_str(s: &str) -> StdResult<Template, Error> {
+        Template::with_groups(s, &[])
+    }
+}
+
+impl Template {
+    /// Parse a template that may additionally contain the capture group names
+    /// in groups as placeholders
+    pub fn with_groups(s: &str, groups: &[&str]) -> StdResult<Template, Error> {
+        let mut tokens = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format_err!("Unclosed {{{} in template", name)),
+                        }
+                    }
+                    let field = match name.as_str() {
+                        "time" => Field::Time,
+                        "level" => Field::Level,
+                        "pid" => Field::Pid,
+                        "tid" => Field::Tid,
+                        "tag" => Field::Tag,
+                        "message" => Field::Message,
+                        "raw" => Field::Raw,
+                        name if groups.contains(&name) => Field::Group(name.to_owned()),
+                        _ => {
+                            return Err(format_err!("Unknown placeholder {{{}}} in template", name))
+                        }
+                    };
+                    if !text.is_empty() {
+                        tokens.push(Token::Text(text.split_off(0)));
+                    }
+                    tokens.push(Token::Field(field));
+                }
+                '}' => return Err(format_err!("Unmatched }} in template")),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            tokens.push(Token::Text(text));
+        }
+        Ok(Template { tokens })
+    }
+
+    /// Substitute the placeholders with the fields of record. Missing fields
+    /// are empty
+    pub fn render(&self, record: &Record) -> String {
+        self.render_groups(record, |_| None)
+    }
+
+    /// Same as render but group placeholders are substituted with the result
+    /// of groups
+    pub fn render_groups<'a, F>(&self, record: &Record, groups: F) -> String
+    where
+        F: Fn(&str) -> Option<&'a str>,
+    {
+        let mut line = String::new();
+        for token in &self.tokens {
+            match token {
+                Token::Text(text) => line.push_str(text),
+                Token::Field(Field::Time) => {
+                    if let Some(ref timestamp) = record.timestamp {
+                        if timestamp.utc {
+                            line.push_str(&timestamp.iso());
+                        } else {
+                            let mut time = strftime("%m-%d %H:%M:%S.%f", timestamp)
+                                .unwrap_or_else(|_| String::new());
+                            time.truncate(18);
+                            line.push_str(&time);
+                        }
+                    }
+                }
+                Token::Field(Field::Level) => {
+                    if record.level != Level::None {
+                        line.push_str(&record.level.to_string());
+                    }
+                }
+                Token::Field(Field::Pid) => line.push_str(&record.process),
+                Token::Field(Field::Tid) => line.push_str(&record.thread),
+                Token::Field(Field::Tag) => line.push_str(&record.tag),
+                Token::Field(Field::Message) => line.push_str(&record.message),
+                Token::Field(Field::Raw) => line.push_str(&record.raw),
+                Token::Field(Field::Group(name)) => line.push_str(groups(name).unwrap_or("")),
+            }
+        }
+        line
+    }
 }
 
 impl Format {
     pub fn fmt_record(&self, record: &Record) -> Result<String, Error> {
-        match self {
-            Format::Csv => {
+        let line = match self {
+            Format::Csv(columns) => {
                 let mut wtr = WriterBuilder::new().has_headers(false).from_writer(vec![]);
-                wtr.serialize(record)?;
+                wtr.serialize(CsvRow::new(record, columns))?;
                 wtr.flush()?;
                 Ok(String::from_utf8(wtr.into_inner().unwrap())?
                     .trim_end_matches('\n')
@@ -56,21 +202,172 @@ impl Format {
             }
             Format::Html => unimplemented!(),
             Format::Human => unimplemented!(),
-            Format::Json => serde_json::to_string(record)
+            Format::Json => serde_json::to_string(&JsonRow::from(record))
+                .map_err(|e| format_err!("Json serialization error: {}", e)),
+            Format::JsonPretty => serde_json::to_string_pretty(&JsonRow::from(record))
+                .map(|json| json + "\n")
                 .map_err(|e| format_err!("Json serialization error: {}", e)),
-            Format::Raw => Ok(record.raw.clone()),
+            Format::Logcat => Ok(threadtime(record)),
+            Format::Logfmt => Ok(logfmt(record)),
+            Format::Extract | Format::Raw => Ok(record.raw.clone()),
+            Format::Template(template) => Ok(template.render(record)),
+        }?;
+
+        // Structured formats have fields for the receive time and the number
+        if let Format::Csv(_) | Format::Json | Format::JsonPretty | Format::Logfmt = self {
+            return Ok(line);
+        }
+        let line = match record.received {
+            Some(seconds) => format!("{} {}", received(seconds), line),
+            None => line,
+        };
+        match record.number {
+            Some(n) => Ok(format!("{:>width$} {}", n, line, width = LINE_NUMBER_WIDTH)),
+            None => Ok(line),
+        }
+    }
+
+    /// Header line written once before the first record
+    pub fn header(&self) -> Option<String> {
+        match self {
+            Format::Csv(columns) => {
+                let mut header = CSV_HEADER.to_owned();
+                if columns.name {
+                    header.push_str(",name");
+                }
+                if columns.buffer {
+                    header.push_str(",buffer");
+                }
+                if columns.number {
+                    header.push_str(",n");
+                }
+                if columns.received {
+                    header.push_str(",received");
+                }
+                Some(header)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Receive time of --timestamp-monotonic in seconds e.g "[  12.345]"
+pub fn received(seconds: f64) -> String {
+    format!("[{:>8.3}]", seconds)
+}
+
+/// Format a record like `logcat -v threadtime`. Records without a
+/// timestamp get "01-01 00:00:00.000" and records without a tid use the pid
+/// e.g of the brief format. Records that failed to parse are passed as is
+fn threadtime(record: &Record) -> String {
+    if record.level == Level::None && record.tag.is_empty() {
+        return record.raw.clone();
+    }
+    let time = record
+        .timestamp
+        .as_ref()
+        .and_then(|t| strftime("%m-%d %H:%M:%S.%f", t).ok())
+        .map(|mut t| {
+            t.truncate(18);
+            t
+        })
+        .unwrap_or_else(|| "01-01 00:00:00.000".to_owned());
+    let pid = if record.process.is_empty() {
+        "0"
+    } else {
+        &record.process
+    };
+    let tid = if record.thread.is_empty() {
+        pid
+    } else {
+        &record.thread
+    };
+    let level = match record.level {
+        Level::None => Level::Verbose,
+        ref level => level.clone(),
+    };
+    format!(
+        "{} {:>5} {:>5} {} {:<8}: {}",
+        time, pid, tid, level, record.tag, record.message
+    )
+}
+
+/// Format a record as logfmt. Empty fields are omitted. Values with spaces,
+/// quotes, equal signs or control characters are quoted
+fn logfmt(record: &Record) -> String {
+    fn value(v: &str) -> String {
+        if !v.is_empty()
+            && !v
+                .chars()
+                .any(|c| c == ' ' || c == '"' || c == '=' || c.is_control())
+        {
+            return v.to_owned();
         }
+        let mut quoted = String::with_capacity(v.len() + 2);
+        quoted.push('"');
+        for c in v.chars() {
+            match c {
+                '"' => quoted.push_str("\\\""),
+                '\\' => quoted.push_str("\\\\"),
+                '\n' => quoted.push_str("\\n"),
+                '\r' => quoted.push_str("\\r"),
+                '\t' => quoted.push_str("\\t"),
+                c => quoted.push(c),
+            }
+        }
+        quoted.push('"');
+        quoted
     }
+
+    let time = record.timestamp.as_ref().map(|t| {
+        if t.utc {
+            t.iso()
+        } else {
+            let mut time = strftime("%m-%d %H:%M:%S.%f", t).unwrap_or_default();
+            time.truncate(18);
+            time
+        }
+    });
+    let level = match record.level {
+        Level::None => None,
+        ref level => Some(format!("{:?}", level).to_lowercase()),
+    };
+    let number = record.number.map(|n| n.to_string());
+    let received = record.received.map(|s| format!("{:.3}", s));
+    let fields = [
+        ("n", number.as_deref()),
+        ("received", received.as_deref()),
+        ("time", time.as_deref()),
+        ("level", level.as_deref()),
+        ("tag", Some(record.tag.as_str())),
+        ("pid", Some(record.process.as_str())),
+        ("tid", Some(record.thread.as_str())),
+        ("process", record.process_name.as_deref()),
+        ("device", record.device.as_deref()),
+        ("buffer", record.buffer.as_deref()),
+        ("msg", Some(record.message.as_str())),
+    ];
+    fields
+        .iter()
+        .filter_map(|(key, v)| match v {
+            Some(v) if !v.is_empty() => Some(format!("{}={}", key, value(v))),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
 impl FromStr for Format {
     type Err = &'static str;
     fn from_str(s: &str) -> StdResult<Self, Self::Err> {
         match s {
-            "csv" => Ok(Format::Csv),
+            "csv" => Ok(Format::Csv(CsvColumns::default())),
+            "extract" => Ok(Format::Extract),
             "html" => Ok(Format::Html),
             "human" => Ok(Format::Human),
             "json" => Ok(Format::Json),
+            "logcat" => Ok(Format::Logcat),
+            "logfmt" => Ok(Format::Logfmt),
             "raw" => Ok(Format::Raw),
             _ => Err("Format parsing error"),
         }
@@ -83,22 +380,28 @@ impl Display for Format {
             f,
             "{}",
             match *self {
-                Format::Csv => "csv",
+                Format::Csv(_) => "csv",
+                Format::Extract => "extract",
                 Format::Html => "html",
                 Format::Human => "human",
-                Format::Json => "json",
+                Format::Json | Format::JsonPretty => "json",
+                Format::Logcat => "logcat",
+                Format::Logfmt => "logfmt",
                 Format::Raw => "raw",
+                Format::Template(_) => "template",
             }
         )
     }
 }
 
 const LEVEL_VALUES: &[&str] = &[
-    "trace", "debug", "info", "warn", "error", "fatal", "assert", "T", "D", "I", "W", "E", "F", "A",
+    "trace", "verbose", "debug", "info", "warn", "error", "fatal", "assert", "T", "V", "D", "I",
+    "W", "E", "F", "A",
 ];
 
-#[derive(Clone, Debug, Deserialize, PartialOrd, PartialEq, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, PartialOrd, PartialEq, Serialize)]
 pub enum Level {
+    #[default]
     None,
     Trace,
     Verbose,
@@ -130,13 +433,7 @@ impl Display for Level {
     }
 }
 
-impl Default for Level {
-    fn default() -> Level {
-        Level::None
-    }
-}
-
-impl<'a> From<&'a str> for Level {
+impl From<&str> for Level {
     fn from(s: &str) -> Self {
         match s {
             "T" | "trace" => Level::Trace,
@@ -161,6 +458,8 @@ impl Level {
 #[derive(Clone, Debug, PartialEq)]
 pub struct Timestamp {
     pub tm: Tm,
+    /// Converted to UTC with --utc. Formatted as ISO-8601
+    pub utc: bool,
 }
 
 impl Deref for Timestamp {
@@ -173,11 +472,57 @@ impl Deref for Timestamp {
 
 impl Timestamp {
     pub fn new(t: Tm) -> Timestamp {
-        Timestamp { tm: t }
+        Timestamp { tm: t, utc: false }
     }
 
     pub fn now() -> Timestamp {
-        Timestamp { tm: time::now() }
+        Timestamp::new(time::now())
+    }
+
+    /// Convert a device local time to UTC. The offset of the device zone is
+    /// given in seconds or is the zone of the host if none. A missing year is
+    /// inferred from now
+    pub fn to_utc(&self, offset: Option<i32>, now: &Tm) -> Timestamp {
+        let mut tm = self.tm;
+        tm.tm_year = year(&tm, now);
+        let time = match offset {
+            Some(offset) => {
+                // A zero offset converts tm as UTC
+                tm.tm_utcoff = 0;
+                tm.to_timespec() + time::Duration::seconds(-i64::from(offset))
+            }
+            None => {
+                tm.tm_utcoff = 1;
+                tm.tm_isdst = -1;
+                tm.to_timespec()
+            }
+        };
+        Timestamp {
+            tm: time::at_utc(time),
+            utc: true,
+        }
+    }
+
+    /// ISO-8601 representation with milliseconds e.g "2019-03-14T08:00:00.123Z"
+    pub fn iso(&self) -> String {
+        format!(
+            "{}.{:03}Z",
+            strftime("%Y-%m-%dT%H:%M:%S", &self.tm).unwrap_or_default(),
+            self.tm.tm_nsec / 1_000_000
+        )
+    }
+}
+
+/// Year of tm. Logcat timestamps lack the year. Assume the current year
+/// unless month and day are after today which happens for logs that started
+/// before New Year.
+pub fn year(tm: &Tm, now: &Tm) -> i32 {
+    if tm.tm_year != 0 {
+        tm.tm_year
+    } else if (tm.tm_mon, tm.tm_mday) > (now.tm_mon, now.tm_mday) {
+        now.tm_year - 1
+    } else {
+        now.tm_year
     }
 }
 
@@ -186,6 +531,9 @@ impl Serialize for Timestamp {
     where
         S: Serializer,
     {
+        if self.utc {
+            return self.iso().serialize(serializer);
+        }
         strftime("%m-%d %H:%M:%S.%f", &self.tm)
             .map_err(|e| ::serde::ser::Error::custom(e.to_string()))?
             .serialize(serializer)
@@ -205,8 +553,10 @@ impl<'de> Deserialize<'de> for Timestamp {
             where
                 E: ::serde::de::Error,
             {
-                strptime(str_data, "%m-%d %H:%M:%S.%f")
-                    .map(Timestamp::new)
+                // UTC timestamps written with --utc
+                strptime(str_data, "%Y-%m-%dT%H:%M:%S.%fZ")
+                    .map(|tm| Timestamp { tm, utc: true })
+                    .or_else(|_| strptime(str_data, "%m-%d %H:%M:%S.%f").map(Timestamp::new))
                     .map_err(|_| {
                         ::serde::de::Error::invalid_value(
                             ::serde::de::Unexpected::Str(str_data),
@@ -224,6 +574,167 @@ impl<'de> Deserialize<'de> for Timestamp {
     }
 }
 
+/// Minimum width of the right aligned numbers of --line-numbers
+pub const LINE_NUMBER_WIDTH: usize = 6;
+
+/// Columns of the csv format that are always written
+pub const CSV_HEADER: &str = "timestamp,level,pid,tid,tag,message";
+
+/// Optional columns of the csv format. Disabled columns are omitted to keep
+/// the schema of rogcat versions without them
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CsvColumns {
+    /// Process name with --resolve-pids
+    pub name: bool,
+    /// Buffer with --show-buffer
+    pub buffer: bool,
+    /// Record number with --line-numbers
+    pub number: bool,
+    /// Seconds since the start of rogcat with --timestamp-monotonic
+    pub received: bool,
+}
+
+/// Row of the csv format. Fields that are not parsed are left empty
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CsvRow {
+    pub timestamp: Option<Timestamp>,
+    pub level: Option<Level>,
+    pub pid: String,
+    pub tid: String,
+    pub tag: String,
+    pub message: String,
+    /// Process name with --resolve-pids. Missing in rows of older versions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Buffer with --show-buffer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub buffer: Option<String>,
+    /// Number with --line-numbers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<u64>,
+    /// Receive time with --timestamp-monotonic
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub received: Option<f64>,
+}
+
+impl CsvRow {
+    /// Row of record with the optional columns that are enabled. Enabled
+    /// columns are written even if empty to keep the rows aligned
+    pub fn new(record: &Record, columns: &CsvColumns) -> CsvRow {
+        CsvRow {
+            timestamp: record.timestamp.clone(),
+            level: match record.level {
+                Level::None => None,
+                ref level => Some(level.clone()),
+            },
+            pid: record.process.clone(),
+            tid: record.thread.clone(),
+            tag: record.tag.clone(),
+            message: record.message.clone(),
+            name: if columns.name {
+                Some(record.process_name.clone().unwrap_or_default())
+            } else {
+                None
+            },
+            buffer: if columns.buffer {
+                Some(record.buffer.clone().unwrap_or_default())
+            } else {
+                None
+            },
+            n: record.number.filter(|_| columns.number),
+            received: record.received.filter(|_| columns.received),
+        }
+    }
+
+    /// Convert into a `Record` with `raw` as raw line
+    pub fn into_record(self, raw: &str) -> Record {
+        Record {
+            timestamp: self.timestamp,
+            message: self.message,
+            level: self.level.unwrap_or_default(),
+            tag: self.tag,
+            process: self.pid,
+            thread: self.tid,
+            raw: raw.to_owned(),
+            process_name: self.name,
+            buffer: self.buffer,
+            ..Default::default()
+        }
+    }
+}
+
+/// Object of the json format. Empty fields are omitted. The field names of the
+/// plain `Record` serialization are accepted as aliases
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct JsonRow {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<Timestamp>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<Level>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub tag: String,
+    #[serde(alias = "process", skip_serializing_if = "String::is_empty")]
+    pub pid: String,
+    #[serde(alias = "thread", skip_serializing_if = "String::is_empty")]
+    pub tid: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub message: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub raw: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buffer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub received: Option<f64>,
+}
+
+impl<'a> From<&'a Record> for JsonRow {
+    fn from(record: &'a Record) -> JsonRow {
+        JsonRow {
+            timestamp: record.timestamp.clone(),
+            level: match record.level {
+                Level::None => None,
+                ref level => Some(level.clone()),
+            },
+            tag: record.tag.clone(),
+            pid: record.process.clone(),
+            tid: record.thread.clone(),
+            message: record.message.clone(),
+            raw: record.raw.clone(),
+            name: record.process_name.clone(),
+            buffer: record.buffer.clone(),
+            n: record.number,
+            received: record.received,
+        }
+    }
+}
+
+impl JsonRow {
+    /// Convert into a `Record`. `line` is used as raw line if the object has none
+    pub fn into_record(self, line: &str) -> Record {
+        Record {
+            timestamp: self.timestamp,
+            message: self.message,
+            level: self.level.unwrap_or_default(),
+            tag: self.tag,
+            process: self.pid,
+            thread: self.tid,
+            raw: if self.raw.is_empty() {
+                line.to_owned()
+            } else {
+                self.raw
+            },
+            process_name: self.name,
+            buffer: self.buffer,
+            ..Default::default()
+        }
+    }
+}
+
 #[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
 pub struct Record {
     pub timestamp: Option<Timestamp>,
@@ -233,4 +744,171 @@ pub struct Record {
     pub process: String,
     pub thread: String,
     pub raw: String,
+    /// Serial of the device the record was captured from with --all-devices
+    #[serde(skip)]
+    pub device: Option<String>,
+    /// Name of the process resolved with --resolve-pids
+    #[serde(skip)]
+    pub process_name: Option<String>,
+    /// Logd buffer the record was read from with --show-buffer
+    #[serde(skip)]
+    pub buffer: Option<String>,
+    /// Number of the record in the output with --line-numbers
+    #[serde(skip)]
+    pub number: Option<u64>,
+    /// Seconds since the start of rogcat when the line was read with
+    /// --timestamp-monotonic. Unlike the device clock this never jumps
+    #[serde(skip)]
+    pub received: Option<f64>,
+}
+
+#[test]
+fn timestamp_utc() {
+    let tm = |t: &str| strptime(t, "%m-%d %H:%M:%S.%f").unwrap();
+    let now = strptime("2019-06-01", "%Y-%m-%d").unwrap();
+    let offset = Some(2 * 3600);
+
+    let ts = Timestamp::new(tm("03-14 10:00:00.123")).to_utc(offset, &now);
+    assert!(ts.utc);
+    assert_eq!(ts.iso(), "2019-03-14T08:00:00.123Z");
+    // Conversion crosses the new year
+    let ts = Timestamp::new(tm("01-01 01:00:00.000")).to_utc(offset, &now);
+    assert_eq!(ts.iso(), "2018-12-31T23:00:00.000Z");
+    // Logs that started before new year are from the last year
+    let now = strptime("2019-01-01", "%Y-%m-%d").unwrap();
+    let ts = Timestamp::new(tm("12-31 23:00:00.000")).to_utc(Some(-3600), &now);
+    assert_eq!(ts.iso(), "2019-01-01T00:00:00.000Z");
+
+    let json = serde_json::to_string(&ts).unwrap();
+    assert_eq!(json, "\"2019-01-01T00:00:00.000Z\"");
+    let parsed = serde_json::from_str::<Timestamp>(&json).unwrap();
+    assert!(parsed.utc);
+    assert_eq!(parsed.iso(), ts.iso());
+}
+
+#[test]
+fn logfmt_record() {
+    let record = Record {
+        timestamp: Some(Timestamp::new(
+            strptime("03-01 02:19:45.207", "%m-%d %H:%M:%S.%f").unwrap(),
+        )),
+        level: Level::Warn,
+        tag: "ActivityManager".to_owned(),
+        process: "1".to_owned(),
+        message: "Slow \"operation\" took=12ms\tdone".to_owned(),
+        ..Default::default()
+    };
+    assert_eq!(
+        Format::Logfmt.fmt_record(&record).unwrap(),
+        r#"time="03-01 02:19:45.207" level=warn tag=ActivityManager pid=1 msg="Slow \"operation\" took=12ms\tdone""#
+    );
+
+    let record = Record {
+        message: "unparsed".to_owned(),
+        ..Default::default()
+    };
+    assert_eq!(Format::Logfmt.fmt_record(&record).unwrap(), "msg=unparsed");
+}
+
+#[test]
+fn json_pretty_record() {
+    let record = Record {
+        level: Level::Warn,
+        tag: "ActivityManager".to_owned(),
+        message: "Slow operation".to_owned(),
+        ..Default::default()
+    };
+    let json = Format::JsonPretty.fmt_record(&record).unwrap();
+    assert!(json.starts_with("{\n  \""));
+    assert!(json.contains("\n  \"tag\": \"ActivityManager\""));
+    assert!(json.ends_with("}\n"));
+    let compact = Format::Json.fmt_record(&record).unwrap();
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&json).unwrap(),
+        serde_json::from_str::<serde_json::Value>(&compact).unwrap()
+    );
+}
+
+#[test]
+fn template() {
+    let template =
+        Template::from_str("{time} {level} {pid}/{tid} {tag}: {message} {{{raw}}}").unwrap();
+    let record = Record {
+        timestamp: Some(Timestamp::new(
+            strptime("03-01 02:19:45.207", "%m-%d %H:%M:%S.%f").unwrap(),
+        )),
+        level: Level::Info,
+        tag: "Tag".to_owned(),
+        process: "1".to_owned(),
+        thread: "2".to_owned(),
+        message: "message".to_owned(),
+        raw: "raw".to_owned(),
+        ..Default::default()
+    };
+    assert_eq!(
+        template.render(&record),
+        "03-01 02:19:45.207 I 1/2 Tag: message {raw}"
+    );
+    assert_eq!(template.render(&Record::default()), "  / :  {}");
+
+    assert!(Template::from_str("{unknown}").is_err());
+    assert!(Template::from_str("{tag").is_err());
+    assert!(Template::from_str("tag}").is_err());
+}
+
+#[test]
+fn csv_optional_columns() {
+    let record = Record {
+        tag: "Tag".to_owned(),
+        process_name: Some("system_server".to_owned()),
+        ..Default::default()
+    };
+    let columns = CsvColumns {
+        name: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        Format::Csv(columns.clone()).fmt_record(&record).unwrap(),
+        ",,,,Tag,,system_server"
+    );
+    let record = Record {
+        process_name: None,
+        ..record
+    };
+    assert_eq!(
+        Format::Csv(columns).fmt_record(&record).unwrap(),
+        ",,,,Tag,,"
+    );
+}
+
+#[test]
+fn received_formats() {
+    let record = Record {
+        tag: "Tag".to_owned(),
+        message: "message".to_owned(),
+        raw: "raw".to_owned(),
+        received: Some(12.3456),
+        ..Default::default()
+    };
+    assert_eq!(Format::Raw.fmt_record(&record).unwrap(), "[  12.346] raw");
+    assert_eq!(
+        Format::Logfmt.fmt_record(&record).unwrap(),
+        "received=12.346 tag=Tag msg=message"
+    );
+    assert!(Format::Json
+        .fmt_record(&record)
+        .unwrap()
+        .ends_with(",\"received\":12.3456}"));
+    let columns = CsvColumns {
+        received: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        Format::Csv(columns.clone()).header().unwrap(),
+        "timestamp,level,pid,tid,tag,message,received"
+    );
+    assert_eq!(
+        Format::Csv(columns).fmt_record(&record).unwrap(),
+        ",,,,Tag,message,12.3456"
+    );
 }
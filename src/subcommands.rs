@@ -20,63 +20,88 @@
 
 use crate::{
     cli::cli,
-    reader::stdin,
-    utils::{self, adb},
-    StreamData, DEFAULT_BUFFER,
+    reader::{self, device_missing, stdin},
+    terminal::status,
+    utils::{adb_argv, adb_command},
+    StreamData,
 };
-use clap::{crate_name, value_t, ArgMatches};
+use clap::{crate_name, value_t, ArgMatches, Shell};
 use failure::{err_msg, Error};
 use futures::{
-    future::ok, stream::Stream, sync::oneshot, Async, AsyncSink, Future, Poll, Sink, StartSend,
+    future::{join_all, ok},
+    stream::Stream,
+    Async, AsyncSink, Future, Poll, Sink, StartSend,
 };
 use indicatif::{ProgressBar, ProgressStyle};
 use rogcat::record::Level;
+use serde::Serialize;
 use std::{
     borrow::ToOwned,
-    fs::{DirBuilder, File},
-    io::{BufReader, Write},
+    cmp::max,
+    fs::{self, DirBuilder, File},
+    io::{BufReader, BufWriter, Write},
     path::{Path, PathBuf},
     process::{exit, Command, Stdio},
 };
 use time::{now, strftime};
-use tokio::{io::lines, runtime::Runtime};
+use tokio::{
+    codec::{BytesCodec, FramedRead},
+    io::lines,
+    runtime::Runtime,
+};
 use tokio_process::CommandExt;
+use tokio_signal::ctrl_c;
 use zip::{write::FileOptions, CompressionMethod, ZipWriter};
 
 pub fn run(args: &ArgMatches) {
     match args.subcommand() {
         ("bugreport", Some(sub_matches)) => bugreport(sub_matches),
+        ("capture", Some(sub_matches)) => capture(sub_matches),
         ("clear", Some(sub_matches)) => clear(sub_matches),
         ("completions", Some(sub_matches)) => completions(sub_matches),
-        ("devices", _) => devices(),
+        ("devices", Some(sub_matches)) => devices(sub_matches),
         ("log", Some(sub_matches)) => log(sub_matches),
         (_, _) => (),
     }
 }
 
+/// Adb command for the device selected in args. Exits if adb is not found
+fn adb(args: &ArgMatches) -> Command {
+    adb_command(args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(1)
+    })
+}
+
 pub fn completions(args: &ArgMatches) {
-    if let Err(e) = args
-        .value_of("shell")
-        .ok_or_else(|| err_msg("Required shell argument is missing"))
-        .map(str::parse)
-        .map(|s| {
-            cli().gen_completions_to(crate_name!(), s.unwrap(), &mut std::io::stdout());
-        })
-    {
-        eprintln!("Failed to get shell argument: {}", e);
-        exit(1);
-    } else {
-        exit(0);
+    match completions_to(args, &mut std::io::stdout()) {
+        Ok(()) => exit(0),
+        Err(e) => {
+            eprintln!("Failed to get shell argument: {}", e);
+            exit(1);
+        }
     }
 }
 
+/// Write the completion script for the shell in args. Runs without a device
+/// and never spawns adb
+fn completions_to<W: Write>(args: &ArgMatches, out: &mut W) -> Result<(), Error> {
+    let shell = args
+        .value_of("shell")
+        .ok_or_else(|| err_msg("Required shell argument is missing"))?
+        .parse::<Shell>()
+        .map_err(err_msg)?;
+    cli().gen_completions_to(crate_name!(), shell, out);
+    Ok(())
+}
+
 struct ZipFile {
     zip: ZipWriter<File>,
 }
 
 impl ZipFile {
     fn create(filename: &str) -> Result<Self, Error> {
-        let file = File::create(&format!("{}.zip", filename))?;
+        let file = File::create(format!("{}.zip", filename))?;
         let options = FileOptions::default()
             .compression_method(CompressionMethod::Deflated)
             .unix_permissions(0o644);
@@ -110,14 +135,18 @@ impl Drop for ZipFile {
     }
 }
 
-fn report_filename() -> Result<String, Error> {
+fn timestamp() -> Result<String, Error> {
     #[cfg(not(windows))]
     let sep = ":";
     #[cfg(windows)]
     let sep = "_";
 
     let format = format!("%m-%d_%H{}%M{}%S", sep, sep);
-    Ok(format!("{}-bugreport.txt", strftime(&format, &now())?))
+    Ok(strftime(&format, &now())?)
+}
+
+fn report_filename() -> Result<String, Error> {
+    Ok(format!("{}-bugreport.txt", timestamp()?))
 }
 
 /// Performs a dumpstate and write to fs. Note: The Android 7+ dumpstate is not supported.
@@ -130,14 +159,6 @@ pub fn bugreport(args: &ArgMatches) {
         exit(1);
     }
 
-    let mut child = Command::new(adb().expect("Failed to find adb"))
-        .arg("bugreport")
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn_async()
-        .expect("Failed to launch adb");
-    let stdout = BufReader::new(child.stdout().take().unwrap());
-
     let dir = filename_path.parent().unwrap_or_else(|| Path::new(""));
     if !dir.is_dir() {
         DirBuilder::new()
@@ -146,7 +167,23 @@ pub fn bugreport(args: &ArgMatches) {
             .expect("Failed to create outfile parent directory");
     }
 
-    let progress = ProgressBar::new(::std::u64::MAX);
+    // Android 7 and newer let adb pull a zip file
+    let pull_zip = !args.is_present("zip") && filename.ends_with(".zip");
+
+    let mut cmd = adb(args);
+    cmd.arg("bugreport");
+    if pull_zip {
+        cmd.arg(&filename);
+    }
+    let mut child = cmd
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn_async()
+        .expect("Failed to launch adb");
+    let stdout = BufReader::new(child.stdout().take().unwrap());
+    let stderr = BufReader::new(child.stderr().take().unwrap());
+
+    let progress = ProgressBar::new(u64::MAX);
     progress.set_style(
         ProgressStyle::default_bar()
             .template("{spinner:.yellow} {msg:.dim.bold} {pos:>7.dim} {elapsed_precise:.dim}")
@@ -154,66 +191,268 @@ pub fn bugreport(args: &ArgMatches) {
     );
     progress.set_message("Connecting");
 
-    let mut write = if args.is_present("zip") {
-        Box::new(ZipFile::create(&filename).expect("Failed to create zip file")) as Box<dyn Write>
+    let mut write = if pull_zip {
+        None
+    } else if args.is_present("zip") {
+        Some(
+            Box::new(ZipFile::create(&filename).expect("Failed to create zip file"))
+                as Box<dyn Write>,
+        )
     } else {
-        Box::new(File::create(&filename).expect("Failed to craete file")) as Box<dyn Write>
+        Some(Box::new(File::create(&filename).expect("Failed to craete file")) as Box<dyn Write>)
     };
 
     progress.set_message("Pulling bugreport line");
 
+    let mut missing = false;
+
     // TODO: Migrate to tokio::fs::File
-    let output = tokio::io::lines(stdout)
+    let output = lines(stdout)
+        .map(Ok)
+        .select(lines(stderr).map(Err))
         .for_each(|l| {
-            write.write_all(l.as_bytes()).expect("Failed to write");
-            write.write_all(b"\n").expect("Failed to write");
-            progress.inc(1);
+            match (l, write.as_mut()) {
+                (Ok(l), Some(write)) => {
+                    write.write_all(l.as_bytes()).expect("Failed to write");
+                    write.write_all(b"\n").expect("Failed to write");
+                    progress.inc(1);
+                }
+                // Progress of adb is shown dimmed in the progress message
+                (Ok(l), None) => progress.set_message(&l),
+                (Err(l), _) => {
+                    missing |= device_missing(&l);
+                    progress.println(&l);
+                }
+            }
             ok(())
         })
-        .then(|r| {
-            progress.set_style(ProgressStyle::default_bar().template("{msg:.dim.bold}"));
-            progress.finish_with_message(&format!("Finished {}.", filename_path.display()));
-            r
-        })
+        .and_then(|_| child)
         .map_err(|e| {
             eprintln!("Failed to create bugreport: {}", e);
             exit(1);
         });
 
-    tokio::runtime::current_thread::block_on_all(output).expect("Runtime error");
+    let exit_status = tokio::runtime::current_thread::block_on_all(output).expect("Runtime error");
+    // Flush and close the report before reading its size
+    drop(write);
+
+    progress.set_style(ProgressStyle::default_bar().template("{msg:.dim.bold}"));
+    if exit_status.success() {
+        let size = fs::metadata(&filename_path).map(|m| m.len()).unwrap_or(0);
+        progress.finish_with_message(&format!(
+            "Finished {} ({} bytes).",
+            filename_path.display(),
+            size
+        ));
+        exit(0);
+    } else {
+        progress.finish_and_clear();
+        if !pull_zip {
+            fs::remove_file(&filename_path).ok();
+        }
+        if missing {
+            status("No device found. Connect a device or select one with -s/--serial");
+        }
+        eprintln!("Failed to create bugreport: adb {}", exit_status);
+        exit(exit_status.code().unwrap_or(1));
+    }
+}
+
+/// Adb commands recorded by capture and the files they are written to
+const CAPTURES: &[(&str, &[&str])] = &[
+    ("logcat.txt", &["logcat", "-b", "all"]),
+    ("getprop.txt", &["shell", "getprop"]),
+    ("dmesg.txt", &["shell", "dmesg"]),
+];
+
+/// Records logcat, the device properties and dmesg concurrently into a
+/// directory named by the current time until ctrl-c
+pub fn capture(args: &ArgMatches) {
+    let parent = PathBuf::from(args.value_of("DIR").unwrap_or("."));
+    let dir = parent.join(timestamp().expect("Failed to generate directory name"));
+    DirBuilder::new()
+        .recursive(true)
+        .create(&dir)
+        .expect("Failed to create capture directory");
+
+    let captures = CAPTURES
+        .iter()
+        .map(|(name, adb_args)| {
+            let file = File::create(dir.join(name)).expect("Failed to create capture file");
+            let mut child = adb(args)
+                .args(*adb_args)
+                .stdout(Stdio::piped())
+                .spawn_async()
+                .expect("Failed to launch adb");
+            let stdout = child.stdout().take().unwrap();
+            // The child is killed and the file flushed if this is dropped on ctrl-c
+            FramedRead::new(stdout, BytesCodec::new())
+                .fold(BufWriter::new(file), |mut file, chunk| {
+                    file.write_all(&chunk).map(|_| file)
+                })
+                .and_then(|mut file| file.flush())
+                .and_then(|_| child)
+                .map(move |exit_status| {
+                    // Commands killed by a signal e.g on ctrl-c are not an error
+                    if exit_status.code().is_some_and(|c| c != 0) {
+                        status(&format!(
+                            "adb {} failed: {}",
+                            adb_args.join(" "),
+                            exit_status
+                        ));
+                    }
+                })
+        })
+        .collect::<Vec<_>>();
+
+    let ctrl_c = ctrl_c()
+        .flatten_stream()
+        .into_future()
+        .map(|_| ())
+        .map_err(|(e, _)| e);
+    let mut runtime = Runtime::new().expect("Failed to create runtime");
+    let result = runtime.block_on(
+        join_all(captures)
+            .select2(ctrl_c)
+            .map(|_| ())
+            .map_err(|e| e.split().0),
+    );
+    if let Err(e) = result {
+        eprintln!("Failed to capture: {}", e);
+        exit(1);
+    }
+
+    println!("Captured to {}", dir.display());
+    for (name, _) in CAPTURES {
+        let size = fs::metadata(dir.join(name)).map(|m| m.len()).unwrap_or(0);
+        println!("    {} ({} bytes)", name, size);
+    }
     exit(0);
 }
 
-pub fn devices() {
-    let mut child = Command::new(adb().expect("Failed to find adb"))
-        .arg("devices")
-        .stdout(Stdio::piped())
-        .spawn_async()
-        .expect("Failed to run adb devices");
-    let reader = BufReader::new(child.stdout().take().unwrap());
-    let result = lines(reader)
-        .skip(1)
-        .filter(|l| !l.is_empty())
-        .filter(|l| !l.starts_with("* daemon"))
-        .for_each(|l| {
-            let mut s = l.split_whitespace();
-            let id: &str = s.next().unwrap_or("unknown");
-            let name: &str = s.next().unwrap_or("unknown");
-            println!("{} {}", id, name);
-            Ok(())
+/// Device listed by "adb devices -l"
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Device {
+    pub serial: String,
+    /// e.g device, offline, unauthorized or no permissions
+    pub state: String,
+    model: Option<String>,
+    product: Option<String>,
+}
+
+/// Parse the output of "adb devices -l". The header and the messages of a
+/// starting daemon are skipped
+pub fn parse_devices(output: &str) -> Vec<Device> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with("List of devices") && !l.starts_with('*'))
+        .filter_map(|l| {
+            let mut tokens = l.split_whitespace();
+            let serial = tokens.next()?.to_owned();
+            let mut state = tokens.next().unwrap_or("unknown").to_owned();
+            let mut tokens = tokens.peekable();
+            // "no permissions (user in plugdev group; ...)"
+            if state == "no" && tokens.peek() == Some(&"permissions") {
+                tokens.next();
+                state.push_str(" permissions");
+            }
+            let mut device = Device {
+                serial,
+                state,
+                model: None,
+                product: None,
+            };
+            for token in tokens {
+                if let Some(model) = token.strip_prefix("model:") {
+                    device.model = Some(model.to_owned());
+                } else if let Some(product) = token.strip_prefix("product:") {
+                    device.product = Some(product.to_owned());
+                }
+            }
+            Some(device)
+        })
+        .collect()
+}
+
+pub fn devices(args: &ArgMatches) {
+    let output = adb(args)
+        .args(["devices", "-l"])
+        .output()
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to run adb devices: {}", e);
+            exit(1)
         });
+    if !output.status.success() {
+        eprintln!(
+            "Failed to run adb devices: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        exit(1);
+    }
+    let devices = parse_devices(&String::from_utf8_lossy(&output.stdout));
 
-    tokio::run(
-        result
-            .map_err(|e| {
-                eprintln!("Failed to run adb devices: {}", e);
+    if args.value_of("format") == Some("json") {
+        match serde_json::to_string(&devices) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("Failed to serialize devices: {}", e);
                 exit(1)
-            })
-            .map(|_| exit(0)),
-    );
+            }
+        }
+        exit(0);
+    }
+
+    let rows = devices
+        .iter()
+        .map(|d| {
+            [
+                d.serial.as_str(),
+                d.state.as_str(),
+                d.model.as_deref().unwrap_or("-"),
+                d.product.as_deref().unwrap_or("-"),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let header = ["SERIAL", "STATE", "MODEL", "PRODUCT"];
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (width, column) in widths.iter_mut().zip(row.iter()) {
+            *width = max(*width, column.chars().count());
+        }
+    }
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        let line = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(column, width)| format!("{:<width$}", column, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    }
+
+    for device in &devices {
+        match device.state.as_str() {
+            "unauthorized" => status(&format!(
+                "{} is unauthorized. Accept the USB debugging prompt on the device",
+                device.serial
+            )),
+            "offline" => status(&format!(
+                "{} is offline. Reconnect the device or restart the server with \"adb kill-server\"",
+                device.serial
+            )),
+            "no permissions" => status(&format!(
+                "{} is not accessible. Check the udev rules of the device",
+                device.serial
+            )),
+            _ => (),
+        }
+    }
+    exit(0);
 }
 
 struct Logger {
+    adb: Vec<String>,
     tag: String,
     level: Level,
 }
@@ -235,7 +474,8 @@ impl Sink for Logger {
     type SinkError = Error;
 
     fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
-        let child = Command::new(adb()?)
+        let child = Command::new(&self.adb[0])
+            .args(&self.adb[1..])
             .arg("shell")
             .arg("log")
             .arg("-p")
@@ -263,8 +503,12 @@ pub fn log(args: &ArgMatches) {
     let level = Level::from(args.value_of("level").unwrap_or(""));
     match message {
         "-" => {
-            let sink = Logger { tag, level };
-            let stream = stdin()
+            let adb = adb_argv(args).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                exit(1)
+            });
+            let sink = Logger { adb, tag, level };
+            let stream = stdin(false)
                 .map(|d| match d {
                     StreamData::Line(l) => l,
                     _ => panic!("Received non line item during log"),
@@ -275,11 +519,11 @@ pub fn log(args: &ArgMatches) {
             tokio::run(stream);
         }
         _ => {
-            let child = Command::new(adb().expect("Failed to find adb"))
+            let child = adb(args)
                 .arg("shell")
                 .arg("log")
                 .arg("-p")
-                .arg(&Logger::level(&level))
+                .arg(Logger::level(&level))
                 .arg("-t")
                 .arg(&tag)
                 .arg(format!("\"{}\"", message))
@@ -296,21 +540,58 @@ pub fn log(args: &ArgMatches) {
 
 /// Call adb logcat -c -b BUFFERS
 pub fn clear(args: &ArgMatches) {
-    let buffer = args
-        .values_of("buffer")
-        .map(|m| m.map(ToOwned::to_owned).collect::<Vec<String>>())
-        .or_else(|| utils::config_get("buffer"))
-        .unwrap_or_else(|| DEFAULT_BUFFER.iter().map(|&s| s.to_owned()).collect())
-        .join(" -b ");
-    let child = Command::new(adb().expect("Failed to find adb"))
-        .arg("logcat")
-        .arg("-c")
-        .arg("-b")
-        .args(buffer.split(' '))
-        .spawn_async()
-        .expect("Failed to run adb");
+    match reader::clear(args) {
+        Ok(_) => exit(0),
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(reader::exit_code(&e))
+        }
+    }
+}
+
+#[test]
+fn devices_list() {
+    let output = "* daemon not running; starting now at tcp:5037
+* daemon started successfully
+List of devices attached
+emulator-5554          device product:sdk_gphone_x86 model:Android_SDK_built_for_x86 device:generic_x86 transport_id:1
+R58M                   unauthorized usb:1-1 transport_id:2
+XYZ                    offline transport_id:3
+0123456789ABCDEF       no permissions (user in plugdev group; are your udev rules wrong?); see [http://developer.android.com/tools/device.html] usb:1-2 transport_id:4
 
-    let runtime = Runtime::new().expect("Failed to start runtime");
-    let h = oneshot::spawn(child, &runtime.executor());
-    exit(h.wait().expect("Failed to run").code().unwrap_or(1));
+";
+    let devices = parse_devices(output);
+    assert_eq!(devices.len(), 4);
+    assert_eq!(
+        devices[0],
+        Device {
+            serial: "emulator-5554".to_owned(),
+            state: "device".to_owned(),
+            model: Some("Android_SDK_built_for_x86".to_owned()),
+            product: Some("sdk_gphone_x86".to_owned()),
+        }
+    );
+    assert_eq!(devices[1].state, "unauthorized");
+    assert_eq!(devices[1].model, None);
+    assert_eq!(devices[2].serial, "XYZ");
+    assert_eq!(devices[2].state, "offline");
+    assert_eq!(devices[3].state, "no permissions");
+    assert!(parse_devices("List of devices attached\n\n").is_empty());
+}
+
+#[test]
+fn completions_bash() {
+    let args = crate::cli::args(&["rogcat", "completions", "bash"]);
+    let mut script = Vec::new();
+    completions_to(args.subcommand_matches("completions").unwrap(), &mut script).unwrap();
+    let script = String::from_utf8(script).unwrap();
+    assert!(!script.is_empty());
+    for flag in &[
+        "--all-devices",
+        "--show-buffer",
+        "--replay-speed",
+        "--output",
+    ] {
+        assert!(script.contains(flag), "Missing {}", flag);
+    }
 }
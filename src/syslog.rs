@@ -0,0 +1,259 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{terminal::status, LogSink};
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use futures::{AsyncSink, Poll, Sink, StartSend};
+use rogcat::record::{Level, Record, Timestamp};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+};
+use time::strftime;
+
+/// Facility of all messages: user-level messages
+const FACILITY: u8 = 1;
+
+/// Paths of the local syslog socket on Linux and macOS
+#[cfg(unix)]
+const LOCAL_SOCKETS: &[&str] = &["/dev/log", "/var/run/syslog"];
+
+enum Transport {
+    #[cfg(unix)]
+    Local(UnixDatagram),
+    Remote(UdpSocket, SocketAddr),
+}
+
+impl Transport {
+    fn send(&self, message: &[u8]) -> io::Result<()> {
+        match self {
+            #[cfg(unix)]
+            Transport::Local(socket) => socket.send(message).map(|_| ()),
+            Transport::Remote(socket, addr) => socket.send_to(message, addr).map(|_| ()),
+        }
+    }
+}
+
+/// Sink that forwards records to another sink and sends each record to
+/// syslog
+pub struct Syslog {
+    sink: LogSink,
+    transport: Option<Transport>,
+}
+
+/// Wrap sink if --syslog or --syslog-server is passed. Locally the RFC 3164
+/// format is used that every syslog daemon understands. Remote servers get
+/// RFC 5424 over UDP
+pub fn try_from(args: &ArgMatches, sink: LogSink) -> Result<LogSink, Error> {
+    let transport = match args.value_of("syslog_server") {
+        Some(server) => {
+            let addr = server
+                .to_socket_addrs()
+                .ok()
+                .and_then(|mut a| a.next())
+                .ok_or_else(|| format_err!("Invalid syslog server: {}", server))?;
+            let bind = if addr.is_ipv4() {
+                "0.0.0.0:0"
+            } else {
+                "[::]:0"
+            };
+            let socket = UdpSocket::bind(bind)
+                .map_err(|e| format_err!("Failed to bind syslog socket: {}", e))?;
+            Transport::Remote(socket, addr)
+        }
+        None if args.is_present("syslog") => local()?,
+        None => return Ok(sink),
+    };
+    Ok(Box::new(Syslog {
+        sink,
+        transport: Some(transport),
+    }))
+}
+
+#[cfg(unix)]
+fn local() -> Result<Transport, Error> {
+    let socket = UnixDatagram::unbound()?;
+    for path in LOCAL_SOCKETS {
+        if socket.connect(path).is_ok() {
+            return Ok(Transport::Local(socket));
+        }
+    }
+    Err(format_err!(
+        "Failed to connect to the local syslog socket. Tried {}",
+        LOCAL_SOCKETS.join(", ")
+    ))
+}
+
+#[cfg(not(unix))]
+fn local() -> Result<Transport, Error> {
+    Err(format_err!(
+        "There is no local syslog on this platform. Use --syslog-server"
+    ))
+}
+
+/// Syslog severity of a level. Records without a level are informational
+fn severity(level: &Level) -> u8 {
+    match level {
+        Level::Assert => 1,
+        Level::Fatal => 2,
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::None | Level::Info => 6,
+        Level::Debug | Level::Verbose | Level::Trace => 7,
+    }
+}
+
+fn priority(record: &Record) -> u8 {
+    FACILITY * 8 + severity(&record.level)
+}
+
+/// Printable ascii without spaces as required for header fields. Empty
+/// fields are "-"
+fn header_field(s: &str, max: usize) -> String {
+    let field = s
+        .chars()
+        .filter(|c| c.is_ascii_graphic())
+        .take(max)
+        .collect::<String>();
+    if field.is_empty() {
+        "-".to_owned()
+    } else {
+        field
+    }
+}
+
+/// RFC 3164 line e.g "<14>Mar 14 10:00:00 ActivityManager[123]: Start"
+fn rfc3164(record: &Record) -> String {
+    let timestamp = record.timestamp.clone().unwrap_or_else(Timestamp::now);
+    let tag = header_field(&record.tag, 32);
+    let pid = if record.process.is_empty() {
+        String::new()
+    } else {
+        format!("[{}]", header_field(&record.process, 16))
+    };
+    format!(
+        "<{}>{} {}{}: {}",
+        priority(record),
+        strftime("%b %e %H:%M:%S", &timestamp).unwrap_or_default(),
+        tag,
+        pid,
+        record.message
+    )
+}
+
+/// RFC 5424 line e.g "<14>1 2019-03-14T10:00:00.000Z emulator-5554
+/// ActivityManager 123 - - Start". Device times are converted with the zone
+/// of the host unless they are UTC already
+fn rfc5424(record: &Record) -> String {
+    let timestamp = match record.timestamp {
+        Some(ref t) if t.utc => t.iso(),
+        Some(ref t) => t.to_utc(None, &time::now()).iso(),
+        None => "-".to_owned(),
+    };
+    format!(
+        "<{}>1 {} {} {} {} - - {}",
+        priority(record),
+        timestamp,
+        header_field(record.device.as_deref().unwrap_or_default(), 255),
+        header_field(&record.tag, 48),
+        header_field(&record.process, 128),
+        record.message
+    )
+}
+
+impl Syslog {
+    fn send(&mut self, record: &Record) {
+        let result = match self.transport {
+            #[cfg(unix)]
+            Some(ref transport @ Transport::Local(_)) => transport.send(rfc3164(record).as_bytes()),
+            Some(ref transport @ Transport::Remote(..)) => {
+                transport.send(rfc5424(record).as_bytes())
+            }
+            None => return,
+        };
+        if let Err(e) = result {
+            status(&format!(
+                "Failed to send to syslog: {}. Continuing without it",
+                e
+            ));
+            self.transport = None;
+        }
+    }
+}
+
+impl Sink for Syslog {
+    type SinkItem = Record;
+    type SinkError = Error;
+
+    fn start_send(&mut self, record: Record) -> StartSend<Record, Error> {
+        match self.sink.start_send(record.clone())? {
+            AsyncSink::Ready => {
+                self.send(&record);
+                Ok(AsyncSink::Ready)
+            }
+            not_ready => Ok(not_ready),
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Error> {
+        self.sink.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), Error> {
+        self.sink.close()
+    }
+}
+
+#[test]
+fn syslog_format() {
+    let tm = time::strptime("2019-03-14 10:00:00.123", "%Y-%m-%d %H:%M:%S.%f").unwrap();
+    let mut record = Record {
+        timestamp: Some(Timestamp::new(tm)),
+        message: "Start proc".to_owned(),
+        level: Level::Warn,
+        tag: "Activity Manager".to_owned(),
+        process: "123".to_owned(),
+        ..Default::default()
+    };
+    assert_eq!(
+        rfc3164(&record),
+        "<12>Mar 14 10:00:00 ActivityManager[123]: Start proc"
+    );
+
+    record.timestamp = Some(Timestamp {
+        utc: true,
+        ..Timestamp::new(tm)
+    });
+    record.device = Some("emulator-5554".to_owned());
+    assert_eq!(
+        rfc5424(&record),
+        "<12>1 2019-03-14T10:00:00.123Z emulator-5554 ActivityManager 123 - - Start proc"
+    );
+
+    // Unparsed lines are informational and have no header fields
+    let record = Record {
+        message: "raw".to_owned(),
+        ..Default::default()
+    };
+    assert_eq!(rfc5424(&record), "<14>1 - - - - - - raw");
+}
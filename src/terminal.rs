@@ -20,39 +20,307 @@
 
 use crate::{
     profiles::Profile,
-    utils::{config_get, terminal_width},
+    utils::{config_get, csv_columns, format_template, pretty, terminal_width, Affix},
     LogSink,
 };
 use clap::{values_t, ArgMatches};
 use failure::{err_msg, format_err, Error};
 use futures::{Async, AsyncSink, Poll, Sink, StartSend};
+use lazy_static::lazy_static;
 use regex::Regex;
-use rogcat::record::{Format, Level, Record};
+use rogcat::record::{received, Format, Level, Record, LINE_NUMBER_WIDTH};
 use std::{
-    cmp::{max, min},
+    cmp::max,
+    collections::HashMap,
     convert::Into,
     io::{stdout, BufWriter, Write},
+    ops::Range,
     str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
 };
-use termcolor::{Buffer, BufferWriter, Color, ColorChoice, ColorSpec, WriteColor};
+use termcolor::{Buffer, BufferWriter, Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 
 const DIMM_COLOR: Color = Color::Ansi256(243);
 
+/// Tag colors. Greens, yellows and reds are left out to not confuse tags
+/// with levels
+#[cfg(not(target_os = "windows"))]
+const TAG_COLORS: [Color; 12] = [
+    Color::Ansi256(33),
+    Color::Ansi256(39),
+    Color::Ansi256(45),
+    Color::Ansi256(51),
+    Color::Ansi256(63),
+    Color::Ansi256(75),
+    Color::Ansi256(99),
+    Color::Ansi256(105),
+    Color::Ansi256(135),
+    Color::Ansi256(141),
+    Color::Ansi256(147),
+    Color::Ansi256(171),
+];
+
+#[cfg(target_os = "windows")]
+const TAG_COLORS: [Color; 4] = [Color::Blue, Color::Cyan, Color::Magenta, Color::White];
+
+/// Colors of the human format. Levels without a color are dimmed
+#[derive(Clone, Debug, PartialEq)]
+struct Theme {
+    dimm: Color,
+    highlight: Color,
+    levels: Vec<(Level, Color)>,
+}
+
+impl Theme {
+    /// Default theme for dark terminal backgrounds
+    fn dark() -> Theme {
+        Theme {
+            dimm: DIMM_COLOR,
+            highlight: Color::Yellow,
+            levels: vec![
+                (Level::Info, Color::Green),
+                (Level::Warn, Color::Yellow),
+                (Level::Error, Color::Red),
+                (Level::Fatal, Color::Red),
+                (Level::Assert, Color::Red),
+            ],
+        }
+    }
+
+    /// Theme with darker colors for light terminal backgrounds
+    fn light() -> Theme {
+        Theme {
+            dimm: Color::Ansi256(240),
+            highlight: Color::Blue,
+            levels: vec![
+                (Level::Info, Color::Ansi256(28)),
+                (Level::Warn, Color::Ansi256(130)),
+                (Level::Error, Color::Ansi256(160)),
+                (Level::Fatal, Color::Ansi256(160)),
+                (Level::Assert, Color::Ansi256(160)),
+            ],
+        }
+    }
+
+    /// Built in theme selected with --theme with the colors of the `theme`
+    /// section of the configuration file applied
+    fn from_args(args: &ArgMatches) -> Theme {
+        let mut theme = match args.value_of("theme") {
+            Some("light") => Theme::light(),
+            Some(_) | None => Theme::dark(),
+        };
+        if let Some(colors) = config_get::<HashMap<String, String>>("theme") {
+            theme.apply(&colors);
+        }
+        theme
+    }
+
+    /// Override colors by role or level name. Unknown roles or colors are
+    /// skipped with a warning
+    fn apply(&mut self, colors: &HashMap<String, String>) {
+        for (role, color) in colors {
+            let color = match Color::from_str(color) {
+                Ok(color) => color,
+                Err(e) => {
+                    status(&format!("Ignoring theme color {}: {}", role, e));
+                    continue;
+                }
+            };
+            match role.as_str() {
+                "dimm" => self.dimm = color,
+                "highlight" => self.highlight = color,
+                level if LEVEL_NAMES.contains(&level) => {
+                    let level = Level::from(level);
+                    self.levels.retain(|(l, _)| *l != level);
+                    self.levels.push((level, color));
+                }
+                _ => status(&format!("Ignoring unknown theme color {}", role)),
+            }
+        }
+    }
+
+    fn level(&self, level: &Level) -> Option<Color> {
+        self.levels
+            .iter()
+            .find(|(l, _)| l == level)
+            .map(|(_, c)| *c)
+    }
+}
+
+/// Level names of the theme configuration
+const LEVEL_NAMES: &[&str] = &[
+    "trace", "verbose", "debug", "info", "warn", "error", "fatal", "assert",
+];
+
+/// Default number of records sampled with --auto-width
+const AUTO_WIDTH_SAMPLE: usize = 100;
+/// Upper bound of the tag width chosen with --auto-width
+const AUTO_WIDTH_MAX: usize = 40;
+
+/// Status messages are suppressed with --quiet
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Suppress all status messages and progress output
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Meters shown on the last line of stderr e.g of --rate. Each meter has a
+/// slot and all slots share the line
+#[derive(Debug, Default)]
+struct StatusLine {
+    slots: Vec<(&'static str, String)>,
+    /// The line is on the terminal and must be cleared before other output
+    shown: bool,
+}
+
+impl StatusLine {
+    fn set(&mut self, slot: &'static str, text: String) {
+        match self.slots.iter_mut().find(|(s, _)| *s == slot) {
+            Some((_, t)) => *t = text,
+            None => self.slots.push((slot, text)),
+        }
+    }
+
+    fn remove(&mut self, slot: &'static str) {
+        self.slots.retain(|(s, _)| *s != slot);
+    }
+
+    /// Escape sequences that draw the line and return to its start
+    fn draw(&mut self) -> String {
+        if self.slots.is_empty() {
+            return self.clear();
+        }
+        self.shown = true;
+        let texts = self
+            .slots
+            .iter()
+            .map(|(_, t)| t.as_str())
+            .collect::<Vec<_>>();
+        format!("\r\x1b[K{}\r", texts.join(" | "))
+    }
+
+    /// Escape sequence that clears the line if it is shown
+    fn clear(&mut self) -> String {
+        if self.shown {
+            self.shown = false;
+            "\r\x1b[K".to_owned()
+        } else {
+            String::new()
+        }
+    }
+}
+
+lazy_static! {
+    static ref STATUS_LINE: Mutex<StatusLine> = Mutex::new(StatusLine::default());
+}
+
+/// Cheap check before locking the status line for each record
+static STATUS_LINE_SHOWN: AtomicBool = AtomicBool::new(false);
+
+fn with_status_line<T>(f: impl FnOnce(&mut StatusLine) -> T) -> T {
+    let mut line = STATUS_LINE.lock().unwrap_or_else(|e| e.into_inner());
+    let result = f(&mut line);
+    STATUS_LINE_SHOWN.store(line.shown, Ordering::Relaxed);
+    result
+}
+
+/// Replace the text of a meter on the status line and redraw it
+pub fn status_line(slot: &'static str, text: String) {
+    let draw = with_status_line(|line| {
+        line.set(slot, text);
+        line.draw()
+    });
+    eprint!("{}", draw);
+}
+
+/// Remove a meter from the status line
+pub fn status_line_remove(slot: &'static str) {
+    let draw = with_status_line(|line| {
+        line.remove(slot);
+        line.draw()
+    });
+    eprint!("{}", draw);
+}
+
+/// Clear the status line before writing to the terminal. Otherwise the
+/// output overwrites only the first columns of it
+pub fn status_line_clear() {
+    if STATUS_LINE_SHOWN.load(Ordering::Relaxed) {
+        let clear = with_status_line(StatusLine::clear);
+        eprint!("{}", clear);
+    }
+}
+
+/// Draw the cleared status line again below the output
+pub fn status_line_redraw() {
+    let draw = with_status_line(|line| {
+        if line.shown || line.slots.is_empty() {
+            String::new()
+        } else {
+            line.draw()
+        }
+    });
+    eprint!("{}", draw);
+}
+
+/// Print a dimmed status message to stderr unless quiet
+pub fn status(message: &str) {
+    if quiet() {
+        return;
+    }
+    status_line_clear();
+    let color = if atty::is(atty::Stream::Stderr) {
+        ColorChoice::Auto
+    } else {
+        ColorChoice::Never
+    };
+    let mut stderr = StandardStream::stderr(color);
+    stderr
+        .set_color(ColorSpec::new().set_fg(Some(DIMM_COLOR)))
+        .and_then(|_| writeln!(stderr, "{}", message))
+        .and_then(|_| stderr.reset())
+        .ok();
+}
+
 /// Construct a terminal sink for format from args with give profile
 pub fn try_from<'a>(args: &ArgMatches<'a>, profile: &Profile) -> Result<LogSink, Error> {
-    let format = args
-        .value_of("format")
-        .ok_or_else(|| format_err!("Missing format argument"))
-        .and_then(|f| Format::from_str(f).map_err(err_msg))
-        .unwrap_or(Format::Human);
+    let format = match format_template(args)? {
+        Some(template) => template,
+        None => args
+            .value_of("format")
+            .ok_or_else(|| format_err!("Missing format argument"))
+            .and_then(|f| Format::from_str(f).map_err(err_msg))
+            .unwrap_or(if args.is_present("hostclock") || args.is_present("raw") {
+                Format::Raw
+            } else {
+                Format::Human
+            }),
+    };
+    let format = pretty(args, csv_columns(args, format))?;
 
     if format == Format::Html {
         return Err(format_err!("HTML format is only valid for file output"));
     }
 
     let sink = match format {
-        Format::Human => Box::new(Human::from(args, profile, format)) as LogSink,
-        format => Box::new(FormatSink::new(format, stdout())) as LogSink,
+        Format::Human => Box::new(Human::from(args, profile, format)?) as LogSink,
+        format => {
+            let header = !args.is_present("no_header");
+            Box::new(FormatSink::new(
+                format,
+                stdout(),
+                header,
+                Affix::from_args(args),
+            )?) as LogSink
+        }
     };
 
     Ok(Box::new(sink.sink_map_err(|e| {
@@ -60,30 +328,147 @@ pub fn try_from<'a>(args: &ArgMatches<'a>, profile: &Profile) -> Result<LogSink,
     })))
 }
 
+/// Wrapping of messages that exceed the terminal width
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Wrap {
+    Off,
+    Char,
+    Word,
+}
+
+/// Representation of the level column
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LevelStyle {
+    Letter,
+    Word,
+    Emoji,
+}
+
+/// Width of the longest level name with LevelStyle::Word
+const LEVEL_WORD_WIDTH: usize = 7;
+
+impl LevelStyle {
+    /// Label of level. Words are padded to a common width. Glyphs are
+    /// single column symbols and may be multi byte
+    fn label(self, level: &Level) -> String {
+        match self {
+            LevelStyle::Letter => level.to_string(),
+            LevelStyle::Word => {
+                let word = match level {
+                    Level::None => "",
+                    Level::Trace => "TRACE",
+                    Level::Verbose => "VERBOSE",
+                    Level::Debug => "DEBUG",
+                    Level::Info => "INFO",
+                    Level::Warn => "WARN",
+                    Level::Error => "ERROR",
+                    Level::Fatal => "FATAL",
+                    Level::Assert => "ASSERT",
+                };
+                format!("{:<width$}", word, width = LEVEL_WORD_WIDTH)
+            }
+            LevelStyle::Emoji => match level {
+                Level::None => " ",
+                Level::Trace => "…",
+                Level::Verbose => "·",
+                Level::Debug => "•",
+                Level::Info => "ℹ",
+                Level::Warn => "⚠",
+                Level::Error => "✖",
+                Level::Fatal => "☠",
+                Level::Assert => "‼",
+            }
+            .to_owned(),
+        }
+    }
+}
+
+/// Split message into lines of at most width characters. Word wrapping breaks
+/// at the last whitespace of a line if there is one. Returns ranges of char
+/// indices
+fn wrap(message: &str, width: usize, mode: Wrap) -> Vec<Range<usize>> {
+    let chars = message.chars().collect::<Vec<char>>();
+    let width = if mode == Wrap::Off || width == 0 {
+        usize::MAX
+    } else {
+        width
+    };
+
+    let mut lines = Vec::new();
+    let mut start = 0;
+    while chars.len() - start > width {
+        let end = start + width;
+        let space = if mode == Wrap::Word {
+            chars[start + 1..=end]
+                .iter()
+                .rposition(|c| c.is_whitespace())
+                .map(|p| start + 1 + p)
+        } else {
+            None
+        };
+        match space {
+            Some(space) => {
+                lines.push(start..space);
+                start = space + 1;
+            }
+            None => {
+                lines.push(start..end);
+                start = end;
+            }
+        }
+    }
+    lines.push(start..chars.len());
+    lines
+}
+
 /// Human readable terminal output
-struct Human {
+pub(crate) struct Human {
     writer: BufferWriter,
     date_format: Option<(&'static str, usize)>,
     highlight: Vec<Regex>,
+    highlight_color: Color,
+    theme: Theme,
+    device_width: usize,
+    buffer_width: usize,
+    name_width: usize,
     process_width: usize,
     tag_width: Option<usize>,
+    /// Records buffered until the tag width is chosen with --auto-width
+    sample: Option<(usize, Vec<Record>)>,
     thread_width: usize,
     dimm_color: Option<Color>,
+    tag_color: bool,
     bright_colors: bool,
+    wrap: Wrap,
+    level_style: LevelStyle,
+    affix: Affix,
+    /// Time, level, tag and message only with --compact
+    compact: bool,
 }
 
 impl Human {
-    pub fn from<'a>(args: &ArgMatches<'a>, profile: &Profile, _: Format) -> Human {
+    pub fn from<'a>(args: &ArgMatches<'a>, profile: &Profile, _: Format) -> Result<Human, Error> {
         let mut hl = profile.highlight.clone();
         if args.is_present("highlight") {
             hl.extend(values_t!(args.values_of("highlight"), String).unwrap());
         }
         let highlight = hl.iter().flat_map(|h| Regex::new(h)).collect();
+        let theme = Theme::from_args(args);
+        let highlight_color = args
+            .value_of("highlight_color")
+            .map(ToOwned::to_owned)
+            .or_else(|| config_get("terminal_highlight_color"))
+            .map(|c| Color::from_str(&c).map_err(|e| format_err!("Invalid highlight color: {}", e)))
+            .transpose()?
+            .unwrap_or(theme.highlight);
 
         let color = {
             match args
                 .value_of("color")
-                .unwrap_or_else(|| config_get("terminal_color").unwrap_or("auto"))
+                .map(ToOwned::to_owned)
+                .or_else(|| config_get("terminal_color"))
+                .as_deref()
+                .unwrap_or("auto")
             {
                 "always" => ColorChoice::Always,
                 "never" => ColorChoice::Never,
@@ -98,12 +483,38 @@ impl Human {
             }
         };
         let no_dimm = args.is_present("no_dimm") || config_get("terminal_no_dimm").unwrap_or(false);
-        let tag_width = config_get("terminal_tag_width");
+        let no_tag_color =
+            args.is_present("no_tag_color") || config_get("terminal_no_tag_color").unwrap_or(false);
+        let tag_width = args
+            .value_of("tag_width")
+            .map(|w| usize::from_str(w).map_err(|e| format_err!("Invalid tag width: {}", e)))
+            .transpose()?
+            .or_else(|| config_get("terminal_tag_width"));
+        let sample = if args.is_present("auto_width") {
+            let n = args
+                .value_of("auto_width")
+                .map(|n| usize::from_str(n).map_err(|e| format_err!("Invalid auto width: {}", e)))
+                .transpose()?
+                .unwrap_or(AUTO_WIDTH_SAMPLE);
+            Some((n, Vec::new()))
+        } else {
+            None
+        };
         let hide_timestamp = args.is_present("hide_timestamp")
             || config_get("terminal_hide_timestamp").unwrap_or(false);
         let show_date =
             args.is_present("show_date") || config_get("terminal_show_date").unwrap_or(false);
-        let date_format = if show_date {
+        let compact = args.is_present("compact");
+        let date_format = if compact {
+            if hide_timestamp {
+                None
+            } else {
+                Some(("%H:%M:%S", 8))
+            }
+        } else if args.is_present("utc") && !hide_timestamp {
+            // Width of the ISO-8601 timestamps e.g "2019-03-14T08:00:00.123Z"
+            Some(("", 24))
+        } else if show_date {
             if hide_timestamp {
                 Some(("%m-%d", 5))
             } else {
@@ -118,16 +529,54 @@ impl Human {
         let bright_colors = args.is_present("bright_colors")
             || config_get("terminal_bright_colors").unwrap_or(false);
 
-        Human {
+        // Never wrap if stdout is redirected
+        let wrap = if atty::is(atty::Stream::Stdout) {
+            match args.value_of("wrap") {
+                Some("off") => Wrap::Off,
+                Some("word") => Wrap::Word,
+                Some(_) | None => Wrap::Char,
+            }
+        } else {
+            Wrap::Off
+        };
+
+        let level_style = match args
+            .value_of("level_style")
+            .map(ToOwned::to_owned)
+            .or_else(|| config_get("terminal_level_style"))
+            .as_deref()
+        {
+            Some("word") => LevelStyle::Word,
+            Some("emoji") => LevelStyle::Emoji,
+            Some("letter") | None => LevelStyle::Letter,
+            Some(s) => return Err(format_err!("Invalid level style: {}", s)),
+        };
+
+        Ok(Human {
             writer: BufferWriter::stdout(color),
-            dimm_color: if no_dimm { None } else { Some(DIMM_COLOR) },
+            dimm_color: if no_dimm { None } else { Some(theme.dimm) },
+            tag_color: !no_tag_color,
             highlight,
+            highlight_color,
+            theme,
             date_format,
             tag_width,
+            sample,
+            device_width: 0,
+            buffer_width: 0,
+            name_width: 0,
             process_width: 0,
             thread_width: 0,
             bright_colors,
-        }
+            wrap,
+            level_style: if compact {
+                LevelStyle::Letter
+            } else {
+                level_style
+            },
+            affix: Affix::from_args(args),
+            compact,
+        })
     }
 
     // Dynamic tag width estimation according to terminal width
@@ -138,10 +587,50 @@ impl Human {
             Some(n) if n <= 90 => 20,
             Some(n) if n <= 100 => 25,
             Some(n) if n <= 110 => 30,
-            _ => 35,
+            Some(_) => 35,
+            None => 20,
         })
     }
 
+    /// Width of the longest tag of the sampled records capped to
+    /// AUTO_WIDTH_MAX
+    fn auto_width(sample: &[Record]) -> Option<usize> {
+        sample
+            .iter()
+            .map(|r| r.tag.chars().count())
+            .max()
+            .map(|w| w.min(AUTO_WIDTH_MAX))
+    }
+
+    /// Choose the tag width and print the sampled records
+    fn finish_sample(&mut self) -> Result<(), Error> {
+        if let Some((_, sample)) = self.sample.take() {
+            if let Some(width) = Self::auto_width(&sample) {
+                self.tag_width = Some(width);
+            }
+            for record in &sample {
+                self.print(record)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Right align tag to width. Longer tags are truncated with an ellipsis
+    fn fit_tag(tag: &str, width: usize) -> String {
+        if tag.chars().count() > width {
+            let mut tag = tag
+                .chars()
+                .take(width.saturating_sub(1))
+                .collect::<String>();
+            if width > 0 {
+                tag.push('…');
+            }
+            tag
+        } else {
+            format!("{:>width$}", tag, width = width)
+        }
+    }
+
     #[cfg(target_os = "windows")]
     fn hashed_color(i: &str) -> Color {
         let v = i.bytes().fold(42u8, |c, x| c ^ x) % 7;
@@ -171,12 +660,49 @@ impl Human {
         })
     }
 
+    /// Index into `TAG_COLORS` from a FNV-1a hash of tag. The index of a tag is
+    /// the same across runs
+    fn tag_color_index(tag: &str) -> usize {
+        let hash = tag.bytes().fold(0x811c_9dc5u32, |h, b| {
+            (h ^ u32::from(b)).wrapping_mul(0x0100_0193)
+        });
+        hash as usize % TAG_COLORS.len()
+    }
+
+    /// Mark the characters of message that match any highlight pattern
+    fn highlighted(&self, message: &str) -> Vec<bool> {
+        let mut highlighted = vec![false; message.len()];
+        for m in self.highlight.iter().flat_map(|r| r.find_iter(message)) {
+            highlighted[m.start()..m.end()]
+                .iter_mut()
+                .for_each(|h| *h = true);
+        }
+        message
+            .char_indices()
+            .map(|(i, _)| highlighted[i])
+            .collect()
+    }
+
     fn print(&mut self, record: &Record) -> Result<(), Error> {
-        let timestamp = if let Some((format, len)) = self.date_format {
+        let buffer = self.format(record)?;
+        status_line_clear();
+        self.writer.print(&buffer).map_err(Into::into)
+    }
+
+    /// Format record into a buffer colored according to the color choice
+    pub(crate) fn format(&mut self, record: &Record) -> Result<Buffer, Error> {
+        // The receive time of --timestamp-monotonic replaces the device time
+        let timestamp = if let Some(seconds) = record.received {
+            received(seconds)
+        } else if let Some((format, len)) = self.date_format {
             if let Some(ref ts) = record.timestamp {
-                let mut ts = time::strftime(format, ts).expect("Date format error");
-                ts.truncate(len);
-                ts
+                if ts.utc && !self.compact {
+                    format!("{:<width$}", ts.iso(), width = len)
+                } else {
+                    let mut ts = time::strftime(format, ts).expect("Date format error");
+                    ts.truncate(len);
+                    ts
+                }
             } else {
                 " ".repeat(len)
             }
@@ -184,17 +710,43 @@ impl Human {
             String::new()
         };
 
-        let tag_width = self.tag_width();
-        let tag_chars = record.tag.chars().count();
-        let tag = format!(
-            "{:>width$}",
-            record
-                .tag
-                .chars()
-                .take(min(tag_width, tag_chars))
-                .collect::<String>(),
-            width = tag_width
-        );
+        let tag = match self.tag_width {
+            None if self.compact => record.tag.clone(),
+            _ => Self::fit_tag(&record.tag, self.tag_width()),
+        };
+
+        // Serial of the device with --all-devices
+        let device = match record.device {
+            Some(ref device) => {
+                self.device_width = max(self.device_width, device.chars().count());
+                format!("{:<width$} ", device, width = self.device_width)
+            }
+            None => String::new(),
+        };
+
+        // Number with --line-numbers
+        let number = match record.number {
+            Some(n) => format!("{:>width$} ", n, width = LINE_NUMBER_WIDTH),
+            None => String::new(),
+        };
+
+        // Buffer with --show-buffer
+        let buffer_name = match record.buffer {
+            Some(ref buffer) => {
+                self.buffer_width = max(self.buffer_width, buffer.chars().count());
+                format!("{:<width$} ", buffer, width = self.buffer_width)
+            }
+            None => String::new(),
+        };
+
+        // Process name with --resolve-pids
+        let name = match record.process_name {
+            Some(ref name) => {
+                self.name_width = max(self.name_width, name.chars().count());
+                format!(" {:<width$}", name, width = self.name_width)
+            }
+            None => String::new(),
+        };
 
         self.process_width = max(self.process_width, record.process.chars().count());
         let pid = if record.process.is_empty() {
@@ -211,40 +763,86 @@ impl Human {
             String::new()
         };
 
+        let level = self.level_style.label(&record.level);
+
         let highlight = !self.highlight.is_empty()
             && (self.highlight.iter().any(|r| r.is_match(&record.tag))
                 || self.highlight.iter().any(|r| r.is_match(&record.message)));
 
-        let preamble_width = timestamp.chars().count()
+        let preamble_width = if self.compact {
+            number.chars().count()
+                + device.chars().count()
+                + buffer_name.chars().count()
+                + timestamp.chars().count()
+                + if timestamp.is_empty() { 0 } else { 1 }
+                + level.chars().count()
+                + 1 // " "
+                + tag.chars().count()
+                + 1 // ":"
+        } else {
+            number.chars().count()
+            + device.chars().count()
+            + buffer_name.chars().count()
+            + timestamp.chars().count()
             + 1 // " "
             + tag.chars().count()
             + 2 // " ("
-            + pid.chars().count() + tid.chars().count()
+            + pid.chars().count() + tid.chars().count() + name.chars().count()
             + 2 // ") "
-            + 3; // level
+            + level.chars().count() + 2
+        };
 
         let timestamp_color = if highlight {
-            Some(Color::Yellow)
+            Some(self.highlight_color)
         } else {
             self.dimm_color
         };
-        let tag_color = Self::hashed_color(&record.tag);
+        let tag_color = if self.tag_color {
+            Some(TAG_COLORS[Self::tag_color_index(&record.tag)])
+        } else {
+            None
+        };
+        let device_color = Self::hashed_color(device.trim_end());
         let pid_color = Self::hashed_color(&pid);
         let tid_color = Self::hashed_color(&tid);
-        let level_color = match record.level {
-            Level::Info => Some(Color::Green),
-            Level::Warn => Some(Color::Yellow),
-            Level::Error | Level::Fatal | Level::Assert => Some(Color::Red),
-            _ => self.dimm_color,
-        };
+        let level_color = self.theme.level(&record.level).or(self.dimm_color);
 
+        let compact = self.compact;
+        let dimm_color = self.dimm_color;
         let write_preamble = |buffer: &mut Buffer| -> Result<(), Error> {
             let mut spec = ColorSpec::new();
-            buffer.set_color(spec.set_fg(timestamp_color))?;
-            buffer.write_all(timestamp.as_bytes())?;
-            buffer.write_all(b" ")?;
+            if !number.is_empty() {
+                buffer.set_color(spec.set_fg(dimm_color))?;
+                buffer.write_all(number.as_bytes())?;
+            }
+            if !device.is_empty() {
+                buffer.set_color(spec.set_fg(Some(device_color)))?;
+                buffer.write_all(device.as_bytes())?;
+            }
+            if !buffer_name.is_empty() {
+                buffer.set_color(spec.set_fg(dimm_color))?;
+                buffer.write_all(buffer_name.as_bytes())?;
+            }
+            if !timestamp.is_empty() || !compact {
+                buffer.set_color(spec.set_fg(timestamp_color))?;
+                buffer.write_all(timestamp.as_bytes())?;
+                buffer.write_all(b" ")?;
+            }
 
-            buffer.set_color(spec.set_fg(Some(tag_color)))?;
+            if compact {
+                buffer.set_color(spec.set_fg(level_color))?;
+                buffer.write_all(level.as_bytes())?;
+                buffer.set_color(spec.set_fg(None))?;
+                buffer.write_all(b" ")?;
+                buffer.set_color(spec.set_fg(tag_color))?;
+                buffer.write_all(tag.as_bytes())?;
+                buffer.set_color(spec.set_fg(None))?;
+                buffer.write_all(b":")?;
+                buffer.set_color(&ColorSpec::new())?;
+                return Ok(());
+            }
+
+            buffer.set_color(spec.set_fg(tag_color))?;
             buffer.write_all(tag.as_bytes())?;
             buffer.set_color(spec.set_fg(None))?;
 
@@ -255,6 +853,10 @@ impl Human {
                 buffer.set_color(spec.set_fg(Some(tid_color)))?;
                 buffer.write_all(tid.as_bytes())?;
             }
+            if !name.is_empty() {
+                buffer.set_color(spec.set_fg(Some(pid_color)))?;
+                buffer.write_all(name.as_bytes())?;
+            }
             buffer.set_color(spec.set_fg(None))?;
             buffer.write_all(b") ")?;
 
@@ -262,24 +864,34 @@ impl Human {
                 spec.set_bg(level_color)
                     .set_fg(level_color.map(|_| Color::Black)), // Set fg only if bg is set
             )?;
-            write!(buffer, " {} ", record.level)?;
+            write!(buffer, " {} ", level)?;
             buffer.set_color(&ColorSpec::new())?;
 
             Ok(())
         };
 
-        let payload_len = terminal_width().unwrap_or(std::usize::MAX) - preamble_width - 3;
+        // The terminal width is queried for each record to follow resizes
+        let payload_len = terminal_width()
+            .unwrap_or(usize::MAX)
+            .saturating_sub(preamble_width + 3)
+            .max(1);
         let message = record.message.replace('\t', "");
-        let message_len = message.chars().count();
-        let chunks = message_len / payload_len + 1;
+        let highlighted = self.highlighted(&message);
+        let lines = wrap(&message, payload_len, self.wrap);
+        let chunks = lines.len();
 
         let mut buffer = self.writer.buffer();
 
-        for i in 0..chunks {
+        for (i, line) in lines.into_iter().enumerate() {
+            buffer.write_all(self.affix.prefix.as_bytes())?;
             write_preamble(&mut buffer)?;
 
             let c = if chunks == 1 {
-                "   "
+                if self.compact {
+                    " "
+                } else {
+                    "   "
+                }
             } else if i == 0 {
                 " ┌ "
             } else if i == chunks - 1 {
@@ -290,42 +902,174 @@ impl Human {
 
             buffer.write_all(c.as_bytes())?;
 
+            let mut message_spec = ColorSpec::new();
+            message_spec
+                .set_intense(self.bright_colors)
+                .set_fg(level_color);
+            let mut highlight_spec = ColorSpec::new();
+            highlight_spec
+                .set_bold(true)
+                .set_fg(Some(self.highlight_color));
+
+            // Write runs of equally highlighted characters with one color each
             let chunk = message
                 .chars()
-                .skip(i * payload_len)
-                .take(payload_len)
-                .collect::<String>();
-            buffer.set_color(
-                ColorSpec::new()
-                    .set_intense(self.bright_colors)
-                    .set_fg(level_color),
-            )?;
-            buffer.write_all(chunk.as_bytes())?;
+                .zip(highlighted.iter())
+                .skip(line.start)
+                .take(line.len())
+                .collect::<Vec<(char, &bool)>>();
+            for run in chunk.chunk_by(|a, b| a.1 == b.1) {
+                buffer.set_color(if *run[0].1 {
+                    &highlight_spec
+                } else {
+                    &message_spec
+                })?;
+                buffer.write_all(run.iter().map(|(c, _)| c).collect::<String>().as_bytes())?;
+            }
+            buffer.reset()?;
+            buffer.write_all(self.affix.suffix.as_bytes())?;
             buffer.write_all(b"\n")?;
         }
 
-        self.writer.print(&buffer).map_err(Into::into)
+        Ok(buffer)
     }
 }
 
 impl Drop for Human {
     fn drop(&mut self) {
+        self.finish_sample().ok();
         let mut buffer = self.writer.buffer();
         buffer.reset().and_then(|_| self.writer.print(&buffer)).ok();
     }
 }
 
+#[test]
+fn human_color() {
+    let record = Record {
+        level: Level::Error,
+        tag: "Tag".to_owned(),
+        message: "message".to_owned(),
+        ..Default::default()
+    };
+    let format = |color: &str| {
+        let args = crate::cli::args(&["rogcat", "--color", color]);
+        let mut human = Human::from(&args, &Profile::default(), Format::Human).unwrap();
+        String::from_utf8(human.format(&record).unwrap().into_inner()).unwrap()
+    };
+
+    let never = format("never");
+    assert!(never.contains("Tag"));
+    assert!(never.contains("message"));
+    assert!(!never.contains('\x1b'));
+    assert!(format("always").contains('\x1b'));
+}
+
+#[test]
+fn human_compact() {
+    let record = Record {
+        timestamp: Some(rogcat::record::Timestamp::new(
+            time::strptime("03-14 10:00:00.123", "%m-%d %H:%M:%S.%f").unwrap(),
+        )),
+        level: Level::Info,
+        process: "123".to_owned(),
+        thread: "456".to_owned(),
+        tag: "ActivityManager".to_owned(),
+        message: "Start proc".to_owned(),
+        ..Default::default()
+    };
+    let format = |argv: &[&str]| {
+        let args = crate::cli::args(argv);
+        let mut human = Human::from(&args, &Profile::default(), Format::Human).unwrap();
+        String::from_utf8(human.format(&record).unwrap().into_inner()).unwrap()
+    };
+
+    assert_eq!(
+        format(&["rogcat", "--compact", "--color", "never"]),
+        "10:00:00 I ActivityManager: Start proc\n"
+    );
+    assert_eq!(
+        format(&[
+            "rogcat",
+            "--compact",
+            "--color",
+            "never",
+            "--tag-width",
+            "8"
+        ]),
+        "10:00:00 I Activit…: Start proc\n"
+    );
+    // The receive time replaces the device time
+    let record = Record {
+        received: Some(1.5),
+        ..record
+    };
+    let args = crate::cli::args(&["rogcat", "--compact", "--color", "never"]);
+    let mut human = Human::from(&args, &Profile::default(), Format::Human).unwrap();
+    assert_eq!(
+        String::from_utf8(human.format(&record).unwrap().into_inner()).unwrap(),
+        "[   1.500] I ActivityManager: Start proc\n"
+    );
+}
+
+#[test]
+fn fit_tag() {
+    assert_eq!(Human::fit_tag("Tag", 5), "  Tag");
+    assert_eq!(Human::fit_tag("Tag", 3), "Tag");
+    assert_eq!(Human::fit_tag("ActivityManager", 8), "Activit…");
+    assert_eq!(Human::fit_tag("ActivityManager", 8).chars().count(), 8);
+    assert_eq!(Human::fit_tag("Tag", 0), "");
+}
+
+#[test]
+fn auto_width() {
+    let tagged = |tag: &str| Record {
+        tag: tag.to_owned(),
+        ..Default::default()
+    };
+    let sample = vec![tagged("vold"), tagged("ActivityManager"), tagged("netd")];
+    assert_eq!(Human::auto_width(&sample), Some("ActivityManager".len()));
+    assert_eq!(
+        Human::auto_width(&[tagged(&"x".repeat(100))]),
+        Some(AUTO_WIDTH_MAX)
+    );
+    assert_eq!(Human::auto_width(&[]), None);
+
+    let args = crate::cli::args(&["rogcat", "--auto-width=2"]);
+    let mut human = Human::from(&args, &Profile::default(), Format::Human).unwrap();
+    assert_eq!(human.sample.as_ref().map(|s| s.0), Some(2));
+    human.start_send(sample[0].clone()).unwrap();
+    assert_eq!(human.tag_width, None);
+    assert_eq!(human.sample.as_ref().map(|s| s.1.len()), Some(1));
+}
+
+#[test]
+fn tag_color_index() {
+    assert_eq!(
+        Human::tag_color_index("MyTag"),
+        Human::tag_color_index("MyTag")
+    );
+    #[cfg(not(target_os = "windows"))]
+    assert_eq!(Human::tag_color_index("MyTag"), 5);
+    assert!(Human::tag_color_index("") < TAG_COLORS.len());
+}
+
 struct FormatSink<T: Write> {
     format: Format,
     sink: BufWriter<T>,
+    affix: Affix,
 }
 
 impl<T: Write> FormatSink<T> {
-    fn new(format: Format, sink: T) -> FormatSink<T> {
-        FormatSink {
-            format,
-            sink: BufWriter::new(sink),
+    fn new(format: Format, sink: T, header: bool, affix: Affix) -> Result<FormatSink<T>, Error> {
+        let mut sink = BufWriter::new(sink);
+        if let (true, Some(header)) = (header, format.header()) {
+            writeln!(sink, "{}", header)?;
         }
+        Ok(FormatSink {
+            format,
+            sink,
+            affix,
+        })
     }
 }
 
@@ -334,13 +1078,17 @@ impl<T: Write> Sink for FormatSink<T> {
     type SinkError = Error;
 
     fn start_send(&mut self, record: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
-        self.sink
-            .write_all(self.format.fmt_record(&record)?.as_bytes())?;
-        self.sink.write_all(&[b'\n'])?;
+        let line = self.format.fmt_record(&record)?;
+        // The buffer might be flushed by the write
+        status_line_clear();
+        self.sink.write_all(self.affix.apply(&line).as_bytes())?;
+        self.sink.write_all(b"\n")?;
         Ok(AsyncSink::Ready)
     }
 
     fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        self.sink.flush()?;
+        status_line_redraw();
         Ok(Async::Ready(()))
     }
 }
@@ -350,10 +1098,157 @@ impl Sink for Human {
     type SinkError = Error;
 
     fn start_send(&mut self, record: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        if let Some((n, ref mut sample)) = self.sample {
+            sample.push(record);
+            if sample.len() >= n {
+                self.finish_sample()?;
+            }
+            return Ok(AsyncSink::Ready);
+        }
         self.print(&record).map(|_| AsyncSink::Ready)
     }
 
     fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        status_line_redraw();
         Ok(Async::Ready(()))
     }
+
+    /// Print the sample if the input ends before the sample is complete
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        self.finish_sample().map(Async::Ready)
+    }
+}
+
+#[test]
+fn format_sink_affix() {
+    let affix = Affix {
+        prefix: "dev\t".to_owned(),
+        suffix: ";".to_owned(),
+    };
+    let mut sink =
+        FormatSink::new(Format::Csv(Default::default()), Vec::new(), true, affix).unwrap();
+    let record = Record {
+        tag: "Tag".to_owned(),
+        message: "message".to_owned(),
+        ..Default::default()
+    };
+    sink.start_send(record).unwrap();
+    sink.poll_complete().unwrap();
+    // The header is no record line
+    assert_eq!(
+        String::from_utf8(sink.sink.get_ref().clone()).unwrap(),
+        "timestamp,level,pid,tid,tag,message\ndev\t,,,,Tag,message;\n"
+    );
+}
+
+#[test]
+fn format_sink_flush() {
+    let record = |raw: &str| Record {
+        raw: raw.to_owned(),
+        ..Default::default()
+    };
+
+    // Each record is visible once the sink completes
+    let mut sink = FormatSink::new(Format::Raw, Vec::new(), false, Affix::default()).unwrap();
+    sink.start_send(record("a")).unwrap();
+    sink.poll_complete().unwrap();
+    assert_eq!(sink.sink.get_ref(), b"a\n");
+    sink.start_send(record("b")).unwrap();
+    sink.poll_complete().unwrap();
+    assert_eq!(sink.sink.get_ref(), b"a\nb\n");
+}
+
+#[test]
+fn level_style_width() {
+    let levels = [
+        Level::None,
+        Level::Trace,
+        Level::Verbose,
+        Level::Debug,
+        Level::Info,
+        Level::Warn,
+        Level::Error,
+        Level::Fatal,
+        Level::Assert,
+    ];
+    for level in &levels {
+        let word = LevelStyle::Word.label(level);
+        assert_eq!(word.chars().count(), LEVEL_WORD_WIDTH);
+        assert_eq!(word.trim_end(), word.trim());
+        assert_eq!(LevelStyle::Emoji.label(level).chars().count(), 1);
+        assert_eq!(LevelStyle::Letter.label(level).chars().count(), 1);
+    }
+    assert_eq!(LevelStyle::Word.label(&Level::Info), "INFO   ");
+    assert_eq!(LevelStyle::Emoji.label(&Level::Warn), "⚠");
+}
+
+#[test]
+fn wrap_word() {
+    let lines = |message: &str, width, mode| {
+        wrap(message, width, mode)
+            .into_iter()
+            .map(|r| {
+                message
+                    .chars()
+                    .skip(r.start)
+                    .take(r.len())
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+    };
+    let message = "the quick brown fox jumps";
+    assert_eq!(
+        lines(message, 10, Wrap::Word),
+        vec!["the quick", "brown fox", "jumps"]
+    );
+    assert_eq!(
+        lines(message, 10, Wrap::Char),
+        vec!["the quick ", "brown fox ", "jumps"]
+    );
+    assert_eq!(lines(message, 10, Wrap::Off), vec![message]);
+    // Words longer than the width are broken
+    assert_eq!(
+        lines("abcdefghijkl mn", 5, Wrap::Word),
+        vec!["abcde", "fghij", "kl mn"]
+    );
+    // A break right after the width
+    assert_eq!(lines("abcde fgh", 5, Wrap::Word), vec!["abcde", "fgh"]);
+}
+
+#[test]
+fn theme_colors() {
+    let mut theme = Theme::light();
+    let colors = vec![("dimm", "white"), ("warn", "magenta"), ("debug", "cyan")]
+        .into_iter()
+        .chain(vec![("error", "nocolor"), ("nolevel", "red")])
+        .map(|(r, c)| (r.to_owned(), c.to_owned()))
+        .collect::<HashMap<_, _>>();
+    theme.apply(&colors);
+    assert_eq!(theme.dimm, Color::White);
+    assert_eq!(theme.highlight, Color::Blue);
+    assert_eq!(theme.level(&Level::Warn), Some(Color::Magenta));
+    assert_eq!(theme.level(&Level::Debug), Some(Color::Cyan));
+    // Unknown colors keep the theme color
+    assert_eq!(theme.level(&Level::Error), Some(Color::Ansi256(160)));
+    assert_eq!(theme.level(&Level::Verbose), None);
+}
+
+#[test]
+fn status_line_slots() {
+    let mut line = StatusLine::default();
+    assert_eq!(line.clear(), "");
+    line.set("rate", "10 records/s, 10 total".to_owned());
+    assert_eq!(line.draw(), "\r\x1b[K10 records/s, 10 total\r");
+    line.set("stats", "2 records: I 2".to_owned());
+    line.set("rate", "5 records/s, 15 total".to_owned());
+    assert_eq!(
+        line.draw(),
+        "\r\x1b[K5 records/s, 15 total | 2 records: I 2\r"
+    );
+    // Output is written on a cleared line. The meter is drawn below again
+    assert_eq!(line.clear(), "\r\x1b[K");
+    assert_eq!(line.clear(), "");
+    line.remove("rate");
+    line.remove("stats");
+    assert_eq!(line.draw(), "");
 }
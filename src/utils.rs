@@ -18,20 +18,89 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use clap::ArgMatches;
 use config::Config;
-use failure::Error;
+use failure::{err_msg, format_err, Error};
 use lazy_static::lazy_static;
+use regex::Regex;
+use rogcat::record::{CsvColumns, Format, Template};
 use serde::Deserialize;
-use std::{convert::Into, env, path::PathBuf, sync::RwLock};
+use std::{
+    env, ffi::OsString, fs, path::PathBuf, process::Command, str::FromStr, sync::RwLock,
+    time::Duration,
+};
 use which::which_in;
 
 lazy_static! {
     static ref CONFIG: RwLock<Config> = RwLock::new(Config::default());
 }
 
-/// Find adb binary
-pub fn adb() -> Result<PathBuf, Error> {
-    which_in("adb", env::var_os("PATH"), env::current_dir()?).map_err(Into::into)
+/// Find adb binary. `--adb` and `ADB` take precedence over the lookup in `PATH`
+pub fn adb(args: &ArgMatches) -> Result<PathBuf, Error> {
+    let adb = args.value_of_os("adb").map(PathBuf::from).or_else(|| {
+        env::var_os("ADB")
+            .filter(|a| !a.is_empty())
+            .map(PathBuf::from)
+    });
+    match adb {
+        Some(adb) => executable(adb),
+        None => find_adb(env::var_os("PATH")),
+    }
+}
+
+/// Search adb in the directories of path
+fn find_adb(path: Option<OsString>) -> Result<PathBuf, Error> {
+    which_in("adb", path, env::current_dir()?).map_err(|_| {
+        err_msg("adb not found in PATH; install Android platform-tools or pass --adb <path>")
+    })
+}
+
+/// Check that path is an executable file
+fn executable(path: PathBuf) -> Result<PathBuf, Error> {
+    let metadata =
+        fs::metadata(&path).map_err(|e| format_err!("Invalid adb {}: {}", path.display(), e))?;
+    if !metadata.is_file() {
+        return Err(format_err!("Invalid adb {}: Not a file", path.display()));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 == 0 {
+            return Err(format_err!(
+                "Invalid adb {}: Not executable",
+                path.display()
+            ));
+        }
+    }
+    Ok(path)
+}
+
+/// Serial of the selected device. Falls back to the address passed with
+/// `--connect` and `ANDROID_SERIAL`
+pub fn serial(args: &ArgMatches) -> Option<String> {
+    args.value_of("serial")
+        .or_else(|| args.value_of("connect"))
+        .map(ToOwned::to_owned)
+        .or_else(|| env::var("ANDROID_SERIAL").ok())
+        .filter(|s| !s.is_empty())
+}
+
+/// Adb binary and device selection arguments
+pub fn adb_argv(args: &ArgMatches) -> Result<Vec<String>, Error> {
+    let mut argv = vec![adb(args)?.display().to_string()];
+    if let Some(serial) = serial(args) {
+        argv.push("-s".into());
+        argv.push(serial);
+    }
+    Ok(argv)
+}
+
+/// Adb command for the device selected in args
+pub fn adb_command(args: &ArgMatches) -> Result<Command, Error> {
+    let argv = adb_argv(args)?;
+    let mut command = Command::new(&argv[0]);
+    command.args(&argv[1..]);
+    Ok(command)
 }
 
 pub fn terminal_width() -> Option<usize> {
@@ -43,6 +112,279 @@ pub fn terminal_width() -> Option<usize> {
     }
 }
 
+/// Parse a plain number or a number with k, M or G suffix
+pub fn parse_size(s: &str) -> Option<usize> {
+    Regex::new(r"^(\d+)([kMG])$")
+        .unwrap()
+        .captures(s)
+        .and_then(|caps| {
+            caps.get(1)
+                .map(|m| m.as_str())
+                .and_then(|size| usize::from_str(size).ok())
+                .map(|size| (size, caps.get(2).map(|m| m.as_str())))
+        })
+        .and_then(|(size, suffix)| match suffix {
+            Some("k") => Some(1_000 * size),
+            Some("M") => Some(1_000_000 * size),
+            Some("G") => Some(1_000_000_000 * size),
+            _ => None,
+        })
+        .or_else(|| usize::from_str(s).ok())
+}
+
+/// Parse a duration with the suffixes ms, s, m, h or d e.g "10m". Plain
+/// numbers are seconds
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let n = u64::from_str(&s[..split]).ok()?;
+    match &s[split..] {
+        "ms" => Some(Duration::from_millis(n)),
+        "" | "s" => Some(Duration::from_secs(n)),
+        "m" => n.checked_mul(60).map(Duration::from_secs),
+        "h" => n.checked_mul(3600).map(Duration::from_secs),
+        "d" => n.checked_mul(86400).map(Duration::from_secs),
+        _ => None,
+    }
+}
+
+/// Expand `$VAR` and `${VAR}` in a command line with the process environment.
+/// Undefined variables expand to nothing. Single quoted text and `\$` are
+/// kept for `split_command`. Quotes and backslashes of values are escaped for
+/// `split_command`. Whitespace of unquoted values separates arguments like in
+/// a shell
+pub fn expand_env(s: &str) -> String {
+    expand_vars(s, |name| env::var(name).ok())
+}
+
+fn expand_vars<F: Fn(&str) -> Option<String>>(s: &str, lookup: F) -> String {
+    let is_name = |c: char| c == '_' || c.is_ascii_alphanumeric();
+    let mut expanded = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    let (mut single_quoted, mut double_quoted) = (false, false);
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' if !double_quoted => {
+                single_quoted = !single_quoted;
+                expanded.push(c);
+            }
+            '"' if !single_quoted => {
+                double_quoted = !double_quoted;
+                expanded.push(c);
+            }
+            '\\' if !single_quoted => {
+                expanded.push(c);
+                expanded.extend(chars.next());
+            }
+            '$' if !single_quoted => match chars.peek() {
+                Some('{') => {
+                    let rest = chars.clone().skip(1).collect::<String>();
+                    match rest.find('}') {
+                        Some(end) if rest[..end].chars().all(is_name) && end > 0 => {
+                            let value = lookup(&rest[..end]).unwrap_or_default();
+                            expanded.push_str(&escape_value(&value, double_quoted));
+                            for _ in 0..rest[..end].chars().count() + 2 {
+                                chars.next();
+                            }
+                        }
+                        _ => expanded.push(c),
+                    }
+                }
+                Some(n) if *n == '_' || n.is_ascii_alphabetic() => {
+                    let mut name = String::new();
+                    while let Some(n) = chars.peek().filter(|n| is_name(**n)) {
+                        name.push(*n);
+                        chars.next();
+                    }
+                    let value = lookup(&name).unwrap_or_default();
+                    expanded.push_str(&escape_value(&value, double_quoted));
+                }
+                _ => expanded.push(c),
+            },
+            c => expanded.push(c),
+        }
+    }
+    expanded
+}
+
+/// Escape the characters of an expanded value that `split_command` would
+/// interpret in the quoting context of the variable
+fn escape_value(value: &str, double_quoted: bool) -> String {
+    let special: &[char] = if double_quoted {
+        &['"', '\\', '$', '`']
+    } else {
+        &['"', '\'', '\\']
+    };
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Split a command line into arguments like a POSIX shell. Single quotes
+/// keep everything literally, double quotes allow escaping `"`, `\`, `$`
+/// and `` ` `` and a backslash outside of quotes escapes the next character
+pub fn split_command(s: &str) -> Result<Vec<String>, Error> {
+    let mut argv = Vec::new();
+    let mut arg: Option<String> = None;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => arg.push(c),
+                        None => return Err(format_err!("Unterminated quote in command: {}", s)),
+                    }
+                }
+            }
+            '"' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ '"') | Some(c @ '\\') | Some(c @ '$') | Some(c @ '`') => {
+                                arg.push(c)
+                            }
+                            Some(c) => {
+                                arg.push('\\');
+                                arg.push(c);
+                            }
+                            None => {
+                                return Err(format_err!("Unterminated quote in command: {}", s))
+                            }
+                        },
+                        Some(c) => arg.push(c),
+                        None => return Err(format_err!("Unterminated quote in command: {}", s)),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => arg.get_or_insert_with(String::new).push(c),
+                None => return Err(format_err!("Trailing backslash in command: {}", s)),
+            },
+            c if c.is_whitespace() => argv.extend(arg.take()),
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    argv.extend(arg);
+    Ok(argv)
+}
+
+/// Offset of the device time zone from --tz in seconds e.g "+02:00". The
+/// host zone is used if none is given
+pub fn tz_offset(args: &ArgMatches) -> Result<Option<i32>, Error> {
+    let tz = match args.value_of("tz") {
+        Some(tz) => tz,
+        None => return Ok(None),
+    };
+    if tz == "Z" || tz == "UTC" {
+        return Ok(Some(0));
+    }
+    let captures = Regex::new(r"^([+-])(\d{1,2})(:?(\d{2}))?$")
+        .unwrap()
+        .captures(tz)
+        .ok_or_else(|| format_err!("Invalid tz offset: {}. Use e.g \"+02:00\"", tz))?;
+    let hours = i32::from_str(&captures[2])?;
+    let minutes = captures
+        .get(4)
+        .map(|m| i32::from_str(m.as_str()))
+        .transpose()?
+        .unwrap_or(0);
+    if hours > 14 || minutes > 59 {
+        return Err(format_err!("Invalid tz offset: {}", tz));
+    }
+    let offset = hours * 3600 + minutes * 60;
+    Ok(Some(if &captures[1] == "-" { -offset } else { offset }))
+}
+
+/// Output format from a template passed with --format-template
+pub fn format_template(args: &ArgMatches) -> Result<Option<Format>, Error> {
+    // Templates with --extract are rendered by the extract adapter
+    if args.is_present("extract") && args.is_present("format_template") {
+        return Ok(Some(Format::Extract));
+    }
+    args.value_of("format_template")
+        .map(|t| {
+            Template::from_str(t)
+                .map(Format::Template)
+                .map_err(|e| format_err!("Invalid format template: {}", e))
+        })
+        .transpose()
+}
+
+/// Enable the optional csv columns of the flags that fill them
+pub fn csv_columns(args: &ArgMatches, format: Format) -> Format {
+    match format {
+        Format::Csv(_) => Format::Csv(CsvColumns {
+            name: args.is_present("resolve_pids"),
+            buffer: args.is_present("show_buffer"),
+            number: args.is_present("line_numbers"),
+            received: args.is_present("timestamp_monotonic"),
+        }),
+        format => format,
+    }
+}
+
+/// Replace json with indented json if --pretty is passed
+pub fn pretty(args: &ArgMatches, format: Format) -> Result<Format, Error> {
+    match format {
+        _ if !args.is_present("pretty") => Ok(format),
+        Format::Json => Ok(Format::JsonPretty),
+        _ => Err(err_msg("Pretty printing is only supported for json")),
+    }
+}
+
+/// Strings written before and after each output line with --prefix and
+/// --suffix
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Affix {
+    pub prefix: String,
+    pub suffix: String,
+}
+
+impl Affix {
+    pub fn from_args(args: &ArgMatches) -> Affix {
+        Affix {
+            prefix: args.value_of("prefix").map(unescape).unwrap_or_default(),
+            suffix: args.value_of("suffix").map(unescape).unwrap_or_default(),
+        }
+    }
+
+    pub fn apply(&self, line: &str) -> String {
+        format!("{}{}{}", self.prefix, line, self.suffix)
+    }
+}
+
+/// Replace the escapes \t, \n and \\. Other backslashes are kept
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('\\') => unescaped.push('\\'),
+            Some(c) => {
+                unescaped.push('\\');
+                unescaped.push(c);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
 /// Detect configuration directory
 pub fn config_dir() -> PathBuf {
     directories::BaseDirs::new()
@@ -65,3 +407,137 @@ pub fn config_init() {
         .merge(config::File::from(config_file))
         .ok();
 }
+
+/// Directory for the files of a test. The directory and its content are
+/// removed on drop
+#[cfg(test)]
+pub struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    /// Create an empty directory named after the test
+    pub fn new(name: &str) -> TempDir {
+        let path = env::temp_dir().join(format!("rogcat-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&path).ok();
+        fs::create_dir_all(&path).expect("Failed to create temp dir");
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.0
+    }
+
+    /// Path of name in the directory
+    pub fn join<P: AsRef<std::path::Path>>(&self, name: P) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.0).ok();
+    }
+}
+
+#[test]
+fn adb_not_found() {
+    let e = find_adb(Some(OsString::new())).unwrap_err().to_string();
+    assert_eq!(
+        e,
+        "adb not found in PATH; install Android platform-tools or pass --adb <path>"
+    );
+}
+
+#[test]
+fn adb_override() {
+    let args = crate::cli::args(&["rogcat", "--adb", "/nonexistent/adb"]);
+    let e = adb(&args).unwrap_err().to_string();
+    assert!(e.starts_with("Invalid adb /nonexistent/adb"));
+
+    let dir = env::temp_dir();
+    let args = crate::cli::args(&["rogcat", "--adb", dir.to_str().unwrap()]);
+    assert!(adb(&args).unwrap_err().to_string().ends_with("Not a file"));
+}
+
+#[test]
+fn duration_suffixes() {
+    assert_eq!(parse_duration("100ms"), Some(Duration::from_millis(100)));
+    assert_eq!(parse_duration("30"), Some(Duration::from_secs(30)));
+    assert_eq!(parse_duration("10m"), Some(Duration::from_secs(600)));
+    assert_eq!(parse_duration("1h"), Some(Duration::from_secs(3600)));
+    assert_eq!(parse_duration("h"), None);
+    assert_eq!(parse_duration("1w"), None);
+    assert_eq!(parse_duration(&format!("{}d", u64::MAX)), None);
+}
+
+#[test]
+fn affix_escapes() {
+    let args = crate::cli::args(&["rogcat", "--prefix", r"dev\t", "--suffix", r" \\ \q"]);
+    let affix = Affix::from_args(&args);
+    assert_eq!(affix.prefix, "dev\t");
+    // Unknown escapes are kept
+    assert_eq!(affix.suffix, r" \ \q");
+    assert_eq!(affix.apply("line"), "dev\tline \\ \\q");
+    assert_eq!(Affix::default().apply("line"), "line");
+}
+
+#[test]
+fn expand_env_vars() {
+    let lookup = |name: &str| match name {
+        "SERIAL" => Some("emulator-5554".to_owned()),
+        "ARGS" => Some("-b main".to_owned()),
+        _ => None,
+    };
+    assert_eq!(
+        expand_vars("adb -s $SERIAL logcat ${ARGS}", lookup),
+        "adb -s emulator-5554 logcat -b main"
+    );
+    assert_eq!(
+        expand_vars("${SERIAL}x $SERIAL.x", lookup),
+        "emulator-5554x emulator-5554.x"
+    );
+    assert_eq!(expand_vars("a $UNDEFINED b", lookup), "a  b");
+    assert_eq!(
+        expand_vars("\"it's $SERIAL\"", lookup),
+        "\"it's emulator-5554\""
+    );
+    assert_eq!(
+        expand_vars("'$SERIAL' \\$SERIAL", lookup),
+        "'$SERIAL' \\$SERIAL"
+    );
+    assert_eq!(expand_vars("$ $1 ${ ${SERIAL", lookup), "$ $1 ${ ${SERIAL");
+
+    assert_eq!(
+        split_command(&expand_vars("echo \"$SERIAL\" '$SERIAL'", lookup)).unwrap(),
+        vec!["echo", "emulator-5554", "$SERIAL"]
+    );
+
+    // Quotes and backslashes of values are kept literally
+    let lookup = |_: &str| Some("it's \"a\" \\ $b".to_owned());
+    assert_eq!(
+        split_command(&expand_vars("echo \"$X\" $X", lookup)).unwrap(),
+        vec!["echo", "it's \"a\" \\ $b", "it's", "\"a\"", "\\", "$b"]
+    );
+}
+
+#[test]
+fn split_command_quotes() {
+    let split = |s| split_command(s).unwrap();
+    assert_eq!(
+        split("adb  logcat -v time"),
+        vec!["adb", "logcat", "-v", "time"]
+    );
+    assert_eq!(
+        split("adb logcat -T '03-14 10:00:00.000'"),
+        vec!["adb", "logcat", "-T", "03-14 10:00:00.000"]
+    );
+    assert_eq!(
+        split(r#"sh -c "echo \"a b\" \x""#),
+        vec!["sh", "-c", r#"echo "a b" \x"#]
+    );
+    assert_eq!(split(r"a\ b ''"), vec!["a b", ""]);
+    assert!(split_command("adb 'logcat").is_err());
+    assert!(split_command("adb \\").is_err());
+    assert!(split("  ").is_empty());
+}
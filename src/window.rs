@@ -0,0 +1,215 @@
+// Copyright © 2019 Felix Obenhuber
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{profiles::Profile, terminal::Human, LogSink};
+use clap::ArgMatches;
+use failure::{format_err, Error};
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+use rogcat::record::{Format, Record};
+use std::{
+    collections::VecDeque,
+    io::{stdout, Write},
+    str::FromStr,
+    time::Duration,
+};
+use tokio::timer::Interval;
+
+/// Minimum time between two repaints
+const REPAINT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Terminal sink that repaints the last n records in place instead of
+/// scrolling
+pub struct Window {
+    human: Human,
+    records: VecDeque<Record>,
+    size: usize,
+    interval: Interval,
+    /// Records were received since the last repaint
+    dirty: bool,
+    terminal_size: Option<(usize, usize)>,
+}
+
+/// Sink for --window. Only used if stdout is a terminal
+pub fn try_from(args: &ArgMatches, profile: &Profile) -> Result<LogSink, Error> {
+    let size = args
+        .value_of("window")
+        .map(|v| usize::from_str(v).map_err(|e| format_err!("Invalid window size {}: {}", v, e)))
+        .transpose()?
+        .unwrap_or(0);
+    if size == 0 {
+        return Err(format_err!("Invalid window size: 0"));
+    }
+    // Clear the screen once. Repaints overwrite the lines from the top
+    print!("\x1b[H\x1b[2J");
+    Ok(Box::new(Window {
+        human: Human::from(args, profile, Format::Human)?,
+        records: VecDeque::with_capacity(size),
+        size,
+        interval: Interval::new_interval(REPAINT_INTERVAL),
+        dirty: true,
+        terminal_size: None,
+    }))
+}
+
+/// Select the lines of the newest records that fit into rows. Records are
+/// passed newest first as lines. A record that does not fit completely is
+/// cut at the top. Returns the lines in display order
+fn visible_lines(records: impl Iterator<Item = Vec<Vec<u8>>>, rows: usize) -> Vec<Vec<u8>> {
+    let mut lines = VecDeque::new();
+    for record in records {
+        for line in record.into_iter().rev() {
+            if lines.len() == rows {
+                return lines.into_iter().collect();
+            }
+            lines.push_front(line);
+        }
+    }
+    lines.into_iter().collect()
+}
+
+impl Window {
+    fn push(&mut self, record: Record) {
+        if self.records.len() == self.size {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+        self.dirty = true;
+    }
+
+    /// Format the records again on each repaint to follow resizes of the
+    /// terminal
+    fn repaint(&mut self) -> Result<(), Error> {
+        // Keep the last row free to not scroll the terminal
+        let rows = self
+            .terminal_size
+            .map(|(_, h)| h)
+            .unwrap_or(24)
+            .saturating_sub(1)
+            .max(1);
+        let mut records = Vec::with_capacity(self.records.len());
+        for record in self.records.iter().rev() {
+            let buffer = self.human.format(record)?.into_inner();
+            let lines = buffer
+                .split(|b| *b == b'\n')
+                .filter(|l| !l.is_empty())
+                .map(<[u8]>::to_vec)
+                .collect::<Vec<_>>();
+            records.push(lines);
+            if records.iter().map(Vec::len).sum::<usize>() >= rows {
+                break;
+            }
+        }
+
+        let stdout = stdout();
+        let mut stdout = stdout.lock();
+        stdout.write_all(b"\x1b[H")?;
+        for line in visible_lines(records.into_iter(), rows) {
+            stdout.write_all(&line)?;
+            stdout.write_all(b"\x1b[K\n")?;
+        }
+        // Clear the remaining rows e.g after the terminal grew
+        stdout.write_all(b"\x1b[J")?;
+        stdout.flush()?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl Sink for Window {
+    type SinkItem = Record;
+    type SinkError = Error;
+
+    fn start_send(&mut self, record: Record) -> StartSend<Record, Error> {
+        self.push(record);
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Error> {
+        let mut ticked = false;
+        while let Ok(Async::Ready(Some(_))) = self.interval.poll() {
+            ticked = true;
+        }
+        if ticked {
+            let size = term_size::dimensions();
+            if size != self.terminal_size {
+                self.terminal_size = size;
+                self.dirty = true;
+            }
+            if self.dirty {
+                self.repaint()?;
+            }
+        }
+        Ok(Async::Ready(()))
+    }
+
+    /// Show the final state of the window
+    fn close(&mut self) -> Poll<(), Error> {
+        self.terminal_size = term_size::dimensions();
+        self.repaint()?;
+        Ok(Async::Ready(()))
+    }
+}
+
+#[test]
+fn window_lines() {
+    let record = |lines: &[&str]| {
+        lines
+            .iter()
+            .map(|l| l.as_bytes().to_vec())
+            .collect::<Vec<_>>()
+    };
+    let lines = |records: Vec<Vec<Vec<u8>>>, rows| {
+        visible_lines(records.into_iter(), rows)
+            .into_iter()
+            .map(|l| String::from_utf8(l).unwrap())
+            .collect::<Vec<_>>()
+    };
+    // Newest first
+    let records = vec![record(&["c"]), record(&["b1", "b2"]), record(&["a"])];
+    assert_eq!(lines(records.clone(), 10), vec!["a", "b1", "b2", "c"]);
+    assert_eq!(lines(records.clone(), 3), vec!["b1", "b2", "c"]);
+    assert_eq!(lines(records, 2), vec!["b2", "c"]);
+}
+
+#[test]
+fn window_size() {
+    let args = crate::cli::args(&["rogcat", "--window", "2"]);
+    let mut window = Window {
+        human: Human::from(&args, &Profile::default(), Format::Human).unwrap(),
+        records: VecDeque::new(),
+        size: 2,
+        interval: Interval::new_interval(REPAINT_INTERVAL),
+        dirty: false,
+        terminal_size: None,
+    };
+    for tag in &["a", "b", "c"] {
+        window.push(Record {
+            tag: (*tag).to_owned(),
+            ..Default::default()
+        });
+    }
+    let tags = window
+        .records
+        .iter()
+        .map(|r| r.tag.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(tags, vec!["b", "c"]);
+    assert!(window.dirty);
+}